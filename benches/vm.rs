@@ -0,0 +1,221 @@
+// Run with `cargo bench`. Each program is parsed once and interpreted several times, and
+// the best time is reported along with the instruction throughput and how many
+// allocations a run makes, both as written and with common pairs of instructions fused.
+use albus::{assemble, HeapKind, Insn, Label, Num, Value, Vm};
+use hashbrown::HashMap;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// Counts every allocation, growing ones included.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+// Sums 1..=n, staying well within i64 range.
+const SUM: &str = "
+    push 0
+    push 1000000
+label loop
+    dup
+    jz done
+    swap
+    copy 1
+    add
+    swap
+    push 1
+    sub
+    jump loop
+label done
+    pop
+    exit
+";
+
+// Doubles a value past i64 range and keeps going, so most arithmetic happens on bignums.
+const DOUBLE: &str = "
+    push 1
+    push 20000
+label loop
+    dup
+    jz done
+    swap
+    dup
+    add
+    swap
+    push 1
+    sub
+    jump loop
+label done
+    pop
+    exit
+";
+
+// Counts down in the heap, loading and storing through constant addresses.
+const HEAP: &str = "
+    push 0
+    push 1000000
+    store
+label loop
+    push 0
+    load
+    dup
+    jz done
+    push 1
+    sub
+    push 0
+    swap
+    store
+    jump loop
+label done
+    pop
+    exit
+";
+
+// Fills 100000 addresses counting up from 0, then adds them all up, as programs using the
+// heap as a tape do.
+const TAPE: &str = "
+    push 0
+label fill
+    dup
+    dup
+    store
+    push 1
+    add
+    dup
+    push 100000
+    sub
+    jn fill
+    pop
+    push 0
+    push 0
+label sum
+    copy 1
+    load
+    add
+    swap
+    push 1
+    add
+    swap
+    copy 1
+    push 100000
+    sub
+    jn sum
+    exit
+";
+
+fn program(src: &str) -> (Vec<Insn>, HashMap<Label, usize>) {
+    let insns = assemble(src).expect("benchmark program should assemble");
+    let labels = insns
+        .iter()
+        .enumerate()
+        .filter_map(|(i, insn)| match insn {
+            Insn::Label(l) => Some((l.clone(), i)),
+            _ => None,
+        })
+        .collect();
+    (insns, labels)
+}
+
+fn bench(name: &str, src: &str, fused: bool, heap: HeapKind) {
+    let (insns, labels) = program(src);
+    let mut best = Duration::MAX;
+    let mut steps = 0;
+    let mut allocs = 0;
+
+    for _ in 0..5 {
+        let mut vm = Vm::new(insns.clone(), labels.clone()).expect("benchmark program should resolve");
+        vm.heap.set_kind(heap);
+        if fused {
+            vm.fuse();
+        }
+        let (start, before) = (Instant::now(), allocations());
+        vm.run().expect("benchmark program should run");
+        best = best.min(start.elapsed());
+        allocs = allocations() - before;
+        steps = vm.steps;
+    }
+
+    let rate = steps as f64 / best.as_secs_f64() / 1e6;
+    let name = if fused { format!("{} -O2", name) } else { name.to_string() };
+    println!("{:<12} {:>10} insns {:>10.2?} {:>8.1} Minsn/s {:>8} allocs", name, steps, best, rate, allocs);
+}
+
+// The same additions performed on plain bignums and on values, which stay on the i64 path.
+fn arith() {
+    let n = 10_000_000i64;
+
+    let start = Instant::now();
+    let mut acc = Num::from(0);
+    for i in 0..n {
+        acc += Num::from(i);
+    }
+    let big = start.elapsed();
+
+    let start = Instant::now();
+    let mut acc2 = Value::from(0);
+    for i in 0..n {
+        acc2 = &acc2 + &Value::from(i);
+    }
+    let small = start.elapsed();
+
+    assert_eq!(Num::from(acc2), acc);
+    println!("add        bignum {:>10.2?}  value {:>10.2?}  ({:.1}x)", big, small, big.as_secs_f64() / small.as_secs_f64());
+}
+
+// Copies of a value too large for an i64, as `dup`, `copy` and `load` make, taken from a
+// plain bignum and from a value, which shares its digits.
+fn clones() {
+    let n = 1_000_000;
+    let big: Num = Num::from(1) << 200;
+    let mut nums = Vec::with_capacity(n);
+    let mut values = Vec::with_capacity(n);
+    let value = Value::from(big.clone());
+
+    let before = allocations();
+    nums.extend((0..n).map(|_| big.clone()));
+    let num_allocs = allocations() - before;
+
+    let before = allocations();
+    values.extend((0..n).map(|_| value.clone()));
+    let value_allocs = allocations() - before;
+
+    println!("clone      bignum {:>10} allocs  value {:>10} allocs", num_allocs, value_allocs);
+}
+
+fn main() {
+    for fused in [false, true] {
+        bench("sum", SUM, fused, HeapKind::Auto);
+        bench("double", DOUBLE, fused, HeapKind::Auto);
+        bench("heap", HEAP, fused, HeapKind::Auto);
+    }
+    for heap in [HeapKind::Sparse, HeapKind::Dense, HeapKind::Paged, HeapKind::Auto] {
+        bench(&format!("tape {:?}", heap).to_lowercase(), TAPE, false, heap);
+    }
+    arith();
+    clones();
+}
@@ -0,0 +1,68 @@
+/*
+ * Embeds the albus Whitespace interpreter. Link against the cdylib that
+ * `cargo build --release` leaves in target/release.
+ *
+ * Every function taking an `albus_vm *` needs one returned by albus_vm_new and
+ * not yet passed to albus_vm_free, used from one thread at a time. Pointers to
+ * data must be valid for the length given with them.
+ */
+#ifndef ALBUS_H
+#define ALBUS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AlbusVm albus_vm;
+
+/* A Vm with no program loaded and no input. */
+albus_vm *albus_vm_new(void);
+void albus_vm_free(albus_vm *vm);
+
+/* Loads Whitespace source, replacing any program loaded before but keeping
+ * unread input. Returns 0, or -1 if the program doesn't load. */
+int albus_vm_load(albus_vm *vm, const uint8_t *src, size_t len);
+
+/* Adds to the input the program reads. */
+void albus_vm_write_input(albus_vm *vm, const uint8_t *data, size_t len);
+
+/* Executes one instruction. Returns 1 if the program can carry on, 0 once it
+ * has halted and -1 if it failed. */
+int albus_vm_step(albus_vm *vm);
+
+/* Runs the program to the end. Returns 0, or -1 if it failed. */
+int albus_vm_run(albus_vm *vm);
+
+/* Moves up to `cap` bytes of output not yet read into `buf`, returning how
+ * many. */
+size_t albus_vm_read_output(albus_vm *vm, uint8_t *buf, size_t cap);
+
+size_t albus_vm_stack_len(const albus_vm *vm);
+
+/* Reads the value `i` places from the top of the stack into `out`. Returns 0,
+ * or -1 if there is none or it doesn't fit in 64 bits. */
+int albus_vm_stack_get(const albus_vm *vm, size_t i, int64_t *out);
+
+/* Reads the heap at `key` into `out`. Returns 0, or -1 if it was never stored
+ * to or doesn't fit in 64 bits. */
+int albus_vm_heap_get(const albus_vm *vm, int64_t key, int64_t *out);
+
+/* How many instructions the program has executed. */
+uint64_t albus_vm_steps(const albus_vm *vm);
+
+/* The message for the last failure, valid until the next one or until the Vm
+ * is freed, or NULL if nothing has failed. */
+const char *albus_vm_error(const albus_vm *vm);
+
+/* The loaded program's disassembly, valid until the next call for it or until
+ * the Vm is freed. */
+const char *albus_vm_disassemble(albus_vm *vm);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,62 @@
+// Runs albus in Node through its WebAssembly build, so nothing has to be compiled for the
+// platform or shelled out to. The module is read from ALBUS_WASM, or from albus.wasm next
+// to this file, which `npm run build` copies there along with the web loader, albus.mjs,
+// after building it with
+//
+//   cargo build --release --lib --target wasm32-unknown-unknown
+import { EventEmitter } from "node:events";
+import { readFile } from "node:fs/promises";
+import { load } from "./albus.mjs";
+
+let albus;
+const ready = () => (albus ??= readFile(process.env.ALBUS_WASM ?? new URL("albus.wasm", import.meta.url)).then(load));
+
+// Runs a program to the end, resolving to its output, final stack and heap, and the
+// error that stopped it if one did.
+export async function run(source, input = "") {
+  return (await ready()).run(source, input);
+}
+
+// A program being stepped through, which emits "output" with text the program wrote,
+// "step" with its state after each call to step, then "halt" or "error" once it stops.
+export class Vm extends EventEmitter {
+  static async create(source, input = "") {
+    return new Vm((await ready()).session(source, input));
+  }
+
+  constructor(session) {
+    super();
+    this.session = session;
+    this.done = false;
+  }
+
+  // Executes up to `n` instructions, returning the state the program is left in.
+  step(n = 1) {
+    const state = this.session.step(n);
+    if (state.output) this.emit("output", state.output);
+    this.emit("step", state);
+    if (!this.done && state.error) {
+      this.done = true;
+      this.emit("error", new Error(state.error));
+    } else if (!this.done && state.halted) {
+      this.done = true;
+      this.emit("halt", state);
+    }
+    return state;
+  }
+
+  // Steps until the program stops, a batch at a time so that events can be handled in
+  // between, resolving to its final state.
+  async finish(batch = 10000) {
+    let state;
+    do {
+      state = this.step(batch);
+      await new Promise(setImmediate);
+    } while (!state.halted && !state.error);
+    return state;
+  }
+
+  free() {
+    this.session.free();
+  }
+}
@@ -0,0 +1,35 @@
+// Checks the package against the WebAssembly build, after `npm run build`:
+//
+//   npm test
+import assert from "node:assert/strict";
+import test from "node:test";
+import { run, Vm } from "./index.js";
+
+// push 1, push 2, add, onum, then end.
+const ADD = "   \t\n   \t \n\t   \t\n \t\n\n\n";
+// ichr at address 0, then load it and ochr it.
+const ECHO = "   \n\t\n\t    \n\t\t\t\t\n  \n\n\n";
+
+test("run", async () => {
+  const result = await run(ADD);
+  assert.equal(result.output, "3");
+  assert.equal(result.error, undefined);
+  assert.equal((await run(ECHO, "x")).output, "x");
+});
+
+test("errors", async () => {
+  assert.ok((await run("\t   \n\n\n")).error);
+});
+
+test("stepping", async () => {
+  const vm = await Vm.create(ADD);
+  const output = [];
+  vm.on("output", (text) => output.push(text));
+  assert.deepEqual(vm.step(2).stack, [1, 2]);
+  assert.deepEqual(vm.step().stack, [3]);
+  const halted = new Promise((resolve) => vm.on("halt", resolve));
+  await vm.finish();
+  await halted;
+  assert.deepEqual(output, ["3"]);
+  vm.free();
+});
@@ -0,0 +1,145 @@
+"""Drives the albus Whitespace interpreter from Python, through its C interface.
+
+The shared library comes from `cargo build --release` and is looked for in the
+ALBUS_LIB environment variable, then next to this file, then in target/release.
+
+    >>> import albus
+    >>> albus.run(source, input="5\\n")
+    '120\\n'
+    >>> vm = albus.Vm(source, input="5\\n")
+    >>> while vm.step(): print(vm.stack)
+"""
+
+import ctypes
+import os
+import sys
+
+__all__ = ["AlbusError", "Vm", "parse", "run"]
+
+
+def _library():
+    name = {"darwin": "libalbus.dylib", "win32": "albus.dll"}.get(sys.platform, "libalbus.so")
+    here = os.path.dirname(os.path.abspath(__file__))
+    candidates = [
+        os.environ.get("ALBUS_LIB"),
+        os.path.join(here, name),
+        os.path.join(here, "..", "target", "release", name),
+    ]
+    for path in filter(None, candidates):
+        if os.path.exists(path):
+            return ctypes.CDLL(path)
+    raise OSError("can't find %s; build it with `cargo build --release` or set ALBUS_LIB" % name)
+
+
+_lib = _library()
+_vm = ctypes.c_void_p
+_bytes = ctypes.POINTER(ctypes.c_uint8)
+for _name, _args, _result in [
+    ("albus_vm_new", [], _vm),
+    ("albus_vm_free", [_vm], None),
+    ("albus_vm_load", [_vm, ctypes.c_char_p, ctypes.c_size_t], ctypes.c_int),
+    ("albus_vm_write_input", [_vm, ctypes.c_char_p, ctypes.c_size_t], None),
+    ("albus_vm_step", [_vm], ctypes.c_int),
+    ("albus_vm_run", [_vm], ctypes.c_int),
+    ("albus_vm_read_output", [_vm, _bytes, ctypes.c_size_t], ctypes.c_size_t),
+    ("albus_vm_stack_len", [_vm], ctypes.c_size_t),
+    ("albus_vm_stack_get", [_vm, ctypes.c_size_t, ctypes.POINTER(ctypes.c_int64)], ctypes.c_int),
+    ("albus_vm_heap_get", [_vm, ctypes.c_int64, ctypes.POINTER(ctypes.c_int64)], ctypes.c_int),
+    ("albus_vm_steps", [_vm], ctypes.c_uint64),
+    ("albus_vm_error", [_vm], ctypes.c_char_p),
+    ("albus_vm_disassemble", [_vm], ctypes.c_char_p),
+]:
+    _f = getattr(_lib, _name)
+    _f.argtypes, _f.restype = _args, _result
+
+
+class AlbusError(Exception):
+    """A program that failed to load or stopped with an error."""
+
+
+def _encode(s):
+    return s.encode() if isinstance(s, str) else bytes(s)
+
+
+class Vm:
+    """A Whitespace program being run, one instruction at a time if need be."""
+
+    def __init__(self, source, input=b""):
+        self._vm = _lib.albus_vm_new()
+        src = _encode(source)
+        if _lib.albus_vm_load(self._vm, src, len(src)) != 0:
+            raise AlbusError(self._error())
+        self.write_input(input)
+
+    def __del__(self):
+        if getattr(self, "_vm", None):
+            _lib.albus_vm_free(self._vm)
+            self._vm = None
+
+    def _error(self):
+        return _lib.albus_vm_error(self._vm).decode()
+
+    def write_input(self, data):
+        """Adds to the input the program reads."""
+        data = _encode(data)
+        _lib.albus_vm_write_input(self._vm, data, len(data))
+
+    def step(self):
+        """Executes one instruction, returning False once the program has halted."""
+        result = _lib.albus_vm_step(self._vm)
+        if result < 0:
+            raise AlbusError(self._error())
+        return result == 1
+
+    def run(self):
+        """Runs the program to the end, returning the output not read yet."""
+        if _lib.albus_vm_run(self._vm) != 0:
+            raise AlbusError(self._error())
+        return self.read_output()
+
+    def read_output(self):
+        """The output the program wrote since this was last called."""
+        chunks = []
+        buf = (ctypes.c_uint8 * 4096)()
+        while True:
+            n = _lib.albus_vm_read_output(self._vm, buf, len(buf))
+            if n == 0:
+                return b"".join(chunks).decode(errors="replace")
+            chunks.append(bytes(buf[:n]))
+
+    @property
+    def stack(self):
+        """The stack from the bottom up, with None for values too large for 64 bits."""
+        out = ctypes.c_int64()
+        values = []
+        for i in reversed(range(_lib.albus_vm_stack_len(self._vm))):
+            ok = _lib.albus_vm_stack_get(self._vm, i, ctypes.byref(out)) == 0
+            values.append(out.value if ok else None)
+        return values
+
+    def heap(self, key):
+        """The value stored at `key`, or None if there is none or it's too large."""
+        out = ctypes.c_int64()
+        return out.value if _lib.albus_vm_heap_get(self._vm, key, ctypes.byref(out)) == 0 else None
+
+    @property
+    def steps(self):
+        return _lib.albus_vm_steps(self._vm)
+
+    def disassemble(self):
+        return _lib.albus_vm_disassemble(self._vm).decode()
+
+
+def parse(source):
+    """The program's instructions as (mnemonic, argument) pairs, with labels named
+    L0, L1 and so on in the order they appear."""
+    insns = []
+    for line in Vm(source).disassemble().splitlines():
+        op, _, arg = line.strip().partition(" ")
+        insns.append((op, (arg if arg.startswith("L") else int(arg)) if arg else None))
+    return insns
+
+
+def run(source, input=b""):
+    """Runs a program to the end, returning its output."""
+    return Vm(source, input).run()
@@ -0,0 +1,56 @@
+"""Checks the module against the C interface, after `cargo build`:
+
+    python3 -m unittest discover python
+"""
+
+import os
+import unittest
+
+# Falls back on a debug build when there's no release one.
+_here = os.path.dirname(os.path.abspath(__file__))
+for _build in ["release", "debug"]:
+    _path = os.path.join(_here, "..", "target", _build, "libalbus.so")
+    if os.path.exists(_path):
+        os.environ.setdefault("ALBUS_LIB", _path)
+        break
+
+import albus  # noqa: E402
+
+# push 1, push 2, add, onum, then end.
+ADD = "   \t\n   \t \n\t   \t\n \t\n\n\n"
+# ichr at address 0, then load it and ochr it.
+ECHO = "   \n\t\n\t    \n\t\t\t\t\n  \n\n\n"
+
+
+class AlbusTest(unittest.TestCase):
+    def test_run(self):
+        self.assertEqual(albus.run(ADD), "3")
+
+    def test_input(self):
+        self.assertEqual(albus.run(ECHO, input="x"), "x")
+
+    def test_step(self):
+        vm = albus.Vm(ADD)
+        stacks = []
+        while vm.step():
+            stacks.append(vm.stack)
+        self.assertEqual(stacks[:3], [[1], [1, 2], [3]])
+        self.assertEqual(vm.read_output(), "3")
+        self.assertEqual(vm.steps, 5)
+
+    def test_heap(self):
+        vm = albus.Vm(ECHO, input="x")
+        vm.run()
+        self.assertEqual(vm.heap(0), ord("x"))
+        self.assertIsNone(vm.heap(1))
+
+    def test_parse(self):
+        self.assertEqual(albus.parse(ADD), [("push", 1), ("push", 2), ("add", None), ("onum", None), ("exit", None)])
+
+    def test_error(self):
+        with self.assertRaises(albus.AlbusError):
+            albus.run("\t   \n\n\n")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,98 @@
+// Running programs as futures, so that a server can host many at once on whatever executor
+// it uses without a program waiting on input holding up the rest. The traits have the
+// shape of the `futures` crate's, so that adapting a runtime's streams is a few lines.
+
+use crate::{AlbusError, Feed, Result, StepEvent, Vm};
+use std::{
+    future::{poll_fn, Future},
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub trait AsyncRead {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+pub trait AsyncWrite {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>>;
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>>;
+}
+
+// How many instructions run between giving other tasks a turn, and how much output is
+// gathered before it's written.
+const SLICE: u32 = 4096;
+const CHUNK: usize = 8192;
+
+// Lets every other task run once before carrying on.
+struct Yield(bool);
+
+impl Future for Yield {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+async fn write_all<O: AsyncWrite + Unpin>(output: &mut O, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *output).poll_write(cx, bytes)).await? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => bytes = &bytes[n..],
+        }
+    }
+    poll_fn(|cx| Pin::new(&mut *output).poll_flush(cx)).await
+}
+
+impl Vm<Feed, Vec<u8>> {
+    // Runs the program to the end, feeding it from `input` whenever it wants more than it
+    // has and writing its output to `output` before each wait and once it stops.
+    pub async fn run_async<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<()>
+    where
+        I: AsyncRead + Unpin,
+        O: AsyncWrite + Unpin,
+    {
+        let mut pending = Vec::new();
+        let mut buf = vec![0; CHUNK];
+        let mut slice = 0;
+        loop {
+            let ip = self.ip;
+            let io = |error| AlbusError::IoError { ip, error };
+            let event = self.step_event();
+            if matches!(event, StepEvent::Output(_) | StepEvent::Executed) {
+                slice += 1;
+                if slice == SLICE {
+                    slice = 0;
+                    Yield(false).await;
+                }
+            }
+            match event {
+                StepEvent::Executed => {}
+                StepEvent::Output(bytes) => {
+                    pending.extend(bytes);
+                    if pending.len() >= CHUNK {
+                        write_all(output, &std::mem::take(&mut pending)).await.map_err(io)?;
+                    }
+                }
+                StepEvent::NeedsInput => {
+                    write_all(output, &std::mem::take(&mut pending)).await.map_err(io)?;
+                    match poll_fn(|cx| Pin::new(&mut *input).poll_read(cx, &mut buf)).await.map_err(io)? {
+                        0 => self.input.close(),
+                        n => self.input.push(&buf[..n]),
+                    }
+                }
+                StepEvent::Halted => return write_all(output, &pending).await.map_err(io),
+                StepEvent::Trapped(e) => {
+                    write_all(output, &pending).await.ok();
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{AlbusError, Insn, Label, Num, Result, Symbols};
+use hashbrown::HashMap;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// A macro's parameters and the lines of source it expands to.
+#[derive(Clone, Debug, Default)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// How deeply macros can expand within one another before the assembler assumes one of them
+// refers to itself.
+const MAX_EXPANSION: usize = 64;
+
+#[derive(Default)]
+pub struct Assembler {
+    names: HashMap<String, Label>,
+    macros: HashMap<String, Macro>,
+    // The macro whose body is being read, which starts on the given line.
+    defining: Option<(String, usize, Macro)>,
+    expansions: usize,
+    // Where each label was defined, to report it being defined again.
+    defined: HashMap<Label, String>,
+    // The files being assembled, innermost last, which includes are relative to.
+    files: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    // Names become the labels earlier versions would have emitted for their index, which
+    // read the same whether or not the parser treats labels as numbers.
+    fn label(&mut self, name: &str) -> Label {
+        let next = Label::from(&Num::from(self.names.len()));
+        self.names.entry(name.to_string()).or_insert(next).clone()
+    }
+
+    // The name a label was given in the source.
+    pub fn name(&self, l: &Label) -> Option<&str> {
+        self.names.iter().find(|(_, label)| *label == l).map(|(name, _)| name.as_str())
+    }
+
+    // The names of every label used so far, to save beside the program.
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, label) in &self.names {
+            symbols.insert(label.clone(), name.clone());
+        }
+        symbols
+    }
+
+    // Assembles a single line of source, which may be blank or hold only a comment. A line
+    // within a macro definition gives no instructions, and one using a macro gives all of
+    // those it expands to.
+    pub fn line(&mut self, src: &str, lineno: usize) -> Result<Vec<Insn>> {
+        self.expand(src, lineno, 0)
+    }
+
+    // Assembles source that isn't from a file, with includes relative to the current directory.
+    pub fn source(&mut self, src: &str) -> Result<Vec<Insn>> {
+        Ok(self.source_lines(src)?.into_iter().map(|(_, insn)| insn).collect())
+    }
+
+    // Like `source`, but pairs each instruction with the line it came from.
+    pub fn source_lines(&mut self, src: &str) -> Result<Vec<(usize, Insn)>> {
+        self.lines(src)
+    }
+
+    // Assembles the contents of a file and any files it includes, which are relative to it.
+    pub fn file(&mut self, path: &Path, src: &str) -> Result<Vec<Insn>> {
+        Ok(self.file_lines(path, src)?.into_iter().map(|(_, insn)| insn).collect())
+    }
+
+    // Like `file`, but pairs each instruction with the line it came from, which for those
+    // from a macro or an included file is the line using it.
+    pub fn file_lines(&mut self, path: &Path, src: &str) -> Result<Vec<(usize, Insn)>> {
+        self.files.push(path.to_path_buf());
+        let insns = self.lines(src);
+        self.files.pop();
+
+        insns
+    }
+
+    fn lines(&mut self, src: &str) -> Result<Vec<(usize, Insn)>> {
+        let mut insns = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            insns.extend(self.line(line, i + 1)?.into_iter().map(|insn| (i + 1, insn)));
+        }
+        if let Some((name, line, _)) = self.defining.take() {
+            return Err(AlbusError::AsmError { line, reason: format!("macro `{}` has no `end`", name) });
+        }
+
+        Ok(insns)
+    }
+
+    fn include(&mut self, name: &str, lineno: usize) -> Result<Vec<Insn>> {
+        let path = match self.files.last().and_then(|f| f.parent()) {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        let err = |reason: String| AlbusError::AsmError { line: lineno, reason };
+        let canonical = |p: &Path| fs::canonicalize(p).ok();
+        if canonical(&path).is_some() && self.files.iter().any(|f| canonical(f) == canonical(&path)) {
+            return Err(err(format!("{} includes itself", name)));
+        }
+        let src = fs::read_to_string(&path).map_err(|e| err(format!("unable to read {}: {}", name, e)))?;
+
+        self.file(&path, &src).map_err(|e| err(format!("in {}: {}", name, e)))
+    }
+
+    fn expand(&mut self, src: &str, lineno: usize, depth: usize) -> Result<Vec<Insn>> {
+        let err = |reason: String| AlbusError::AsmError { line: lineno, reason };
+        let words = split(src).map_err(|reason| err(reason.into()))?;
+        let mut words = words.into_iter();
+
+        let op = match words.next() {
+            Some(op) => op.to_ascii_lowercase(),
+            None => return Ok(Vec::new()),
+        };
+
+        if let Some((name, start, mut def)) = self.defining.take() {
+            match op.as_str() {
+                "end" => {
+                    self.macros.insert(name, def);
+                }
+                "macro" => return Err(err(format!("macro `{}` from line {} has no `end`", name, start))),
+                _ => {
+                    def.body.push(src.to_string());
+                    self.defining = Some((name, start, def));
+                }
+            }
+            return Ok(Vec::new());
+        }
+        if op == "%include" {
+            return match (words.next().filter(|w| w.starts_with('"')), words.next()) {
+                (Some(name), None) => self.include(&unquote(name).map_err(|reason| err(reason.into()))?, lineno),
+                _ => Err(err("`%include` needs a quoted file name".into())),
+            };
+        }
+        if op == "macro" {
+            let name = words.next().ok_or_else(|| err("`macro` needs a name".into()))?.to_ascii_lowercase();
+            if INSNS.contains(&name.as_str()) {
+                return Err(err(format!("`{}` is an instruction", name)));
+            }
+            let params = words.map(String::from).collect();
+            self.defining = Some((name, lineno, Macro { params, body: Vec::new() }));
+            return Ok(Vec::new());
+        }
+        if let Some(def) = self.macros.get(&op).cloned() {
+            let args: Vec<_> = words.collect();
+            if args.len() != def.params.len() {
+                let reason = format!("`{}` takes {} argument(s), got {}", op, def.params.len(), args.len());
+                return Err(err(reason));
+            }
+            if depth == MAX_EXPANSION {
+                return Err(err(format!("`{}` expands too deeply, perhaps into itself", op)));
+            }
+            // Labels starting with a dot belong to this one expansion, so a macro can loop.
+            self.expansions += 1;
+            let mut insns = Vec::new();
+            for line in &def.body {
+                let words = split(line).map_err(|reason| err(reason.into()))?;
+                let words = words.into_iter().map(|word| match def.params.iter().position(|p| p == word) {
+                    Some(i) => args[i].to_string(),
+                    None if word.starts_with('.') => format!("{}#{}", word, self.expansions),
+                    None => word.to_string(),
+                });
+                insns.extend(self.expand(&words.collect::<Vec<_>>().join(" "), lineno, depth + 1)?);
+            }
+            return Ok(insns);
+        }
+
+        let arg = words.next();
+        if let Some(extra) = words.next() {
+            return Err(err(format!("unexpected `{}`", extra)));
+        }
+
+        let num = || match arg {
+            Some(arg) if arg.starts_with('\'') => match unquote(arg) {
+                Ok(c) if c.chars().count() == 1 => Ok(Num::from(c.chars().next().unwrap() as u32)),
+                Ok(_) => Err(err(format!("`{}` isn't a single character", arg))),
+                Err(reason) => Err(err(reason.into())),
+            },
+            Some(arg) => arg.parse::<Num>().map_err(|_| err(format!("invalid number `{}`", arg))),
+            None => Err(err(format!("`{}` needs a numeric argument", op))),
+        };
+        let label = |asm: &mut Assembler| match arg {
+            Some(arg) => Ok(asm.label(arg)),
+            None => Err(err(format!("`{}` needs a label", op))),
+        };
+        let none = |insn| match arg {
+            Some(arg) => Err(err(format!("`{}` takes no argument, got `{}`", op, arg))),
+            None => Ok(insn),
+        };
+
+        // The terminating zero goes first and the first character last, leaving the string
+        // on the stack ready to be printed from the top down.
+        if op == "pushstr" {
+            let text = match arg.filter(|arg| arg.starts_with('"')) {
+                Some(arg) => unquote(arg).map_err(|reason| err(reason.into()))?,
+                None => return Err(err("`pushstr` needs a quoted string".into())),
+            };
+            let chars = text.chars().rev().map(|c| Insn::Push(Num::from(c as u32)));
+            return Ok(std::iter::once(Insn::Push(Num::from(0))).chain(chars).collect());
+        }
+
+        let insn = match op.as_str() {
+            "push" => Insn::Push(num()?),
+            "copy" => Insn::Copy(num()?),
+            "slide" => Insn::Slide(num()?),
+            "label" => Insn::Label(label(self)?),
+            "call" => Insn::Call(label(self)?),
+            "jump" => Insn::Jump(label(self)?),
+            "jz" => Insn::Jz(label(self)?),
+            "jn" => Insn::Jn(label(self)?),
+            "pop" => none(Insn::Pop)?,
+            "dup" => none(Insn::Dup)?,
+            "swap" => none(Insn::Swap)?,
+            "add" => none(Insn::Add)?,
+            "sub" => none(Insn::Sub)?,
+            "mul" => none(Insn::Mul)?,
+            "div" => none(Insn::Div)?,
+            "mod" => none(Insn::Mod)?,
+            "store" => none(Insn::Store)?,
+            "load" => none(Insn::Load)?,
+            "ret" => none(Insn::Ret)?,
+            "ichr" => none(Insn::Ichr)?,
+            "inum" => none(Insn::Inum)?,
+            "ochr" => none(Insn::Ochr)?,
+            "onum" => none(Insn::Onum)?,
+            "exit" => none(Insn::Exit)?,
+            "dumpstack" => none(Insn::DumpStack)?,
+            "dumpheap" => none(Insn::DumpHeap)?,
+            "random" => none(Insn::Random)?,
+            _ => return Err(err(format!("unknown instruction `{}`", op))),
+        };
+
+        if let (Insn::Label(l), Some(name)) = (&insn, arg) {
+            let site = match self.files.last() {
+                Some(file) => format!("line {} of {}", lineno, file.display()),
+                None => format!("line {}", lineno),
+            };
+            if let Some(first) = self.defined.insert(l.clone(), site) {
+                return Err(err(format!("label `{}` is already defined on {}", name, first)));
+            }
+        }
+
+        Ok(vec![insn])
+    }
+}
+
+// Splits a line into words, keeping quoted strings and characters whole and stopping at a
+// comment.
+pub(crate) fn split(src: &str) -> std::result::Result<Vec<&str>, &'static str> {
+    let mut words = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' | '\'' => {
+                chars.next();
+                let end = loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            chars.next();
+                        }
+                        Some((i, q)) if q == c => break i + 1,
+                        Some(_) => {}
+                        None => return Err("unterminated quote"),
+                    }
+                };
+                words.push(&src[start..end]);
+            }
+            _ => {
+                let end = chars.find(|(_, c)| c.is_whitespace()).map_or(src.len(), |(i, _)| i);
+                let word = &src[start..end];
+                // A comment can follow a word without a space.
+                words.push(word.split(';').next().unwrap());
+                if word.contains(';') {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+// Reads a quoted string or character, with the escapes \n, \t, \r, \0 and a backslash
+// before anything else standing for that character.
+fn unquote(word: &str) -> std::result::Result<String, &'static str> {
+    let mut chars = word[1..word.len() - 1].chars();
+    let mut out = String::new();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c) => c,
+                None => return Err("unterminated quote"),
+            },
+            c => c,
+        });
+    }
+
+    Ok(out)
+}
+
+// The mnemonics a macro can't be named after.
+const INSNS: &[&str] = &[
+    "pushstr", "push", "copy", "slide", "label", "call", "jump", "jz", "jn", "pop", "dup", "swap", "add", "sub",
+    "mul", "div", "mod", "store", "load", "ret", "ichr", "inum", "ochr", "onum", "exit",
+    "dumpstack", "dumpheap", "random",
+];
+
+// Includes are relative to the current directory.
+pub fn assemble(src: &str) -> Result<Vec<Insn>> {
+    Assembler::new().source(src)
+}
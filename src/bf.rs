@@ -0,0 +1,82 @@
+use crate::{minify::nth, AlbusError, Insn, Label, Num, Result};
+
+// How many cells the tape has, all set to 0 before the program starts.
+const TAPE: i64 = 30_000;
+
+// Cells hold bytes, wrapping around past either end.
+const CELL: i64 = 256;
+
+fn push(n: i64) -> Insn {
+    Insn::Push(Num::from(n))
+}
+
+// Compiles Brainfuck to Whitespace, with the tape in the heap from address 0 and the
+// pointer kept on top of the stack. Runs of the same command become one instruction, and
+// `[-]` stores 0 directly. Characters other than the eight commands are comments, and
+// reading past the end of input does what the Vm's `eof` says.
+pub fn from_bf(src: &[u8]) -> Result<Vec<Insn>> {
+    let ready = nth(0);
+    let init = nth(1);
+    let mut out = vec![
+        push(TAPE),
+        Insn::Label(init.clone()),
+        push(1),
+        Insn::Sub,
+        Insn::Dup,
+        push(0),
+        Insn::Store,
+        Insn::Dup,
+        Insn::Jz(ready.clone()),
+        Insn::Jump(init),
+        Insn::Label(ready),
+    ];
+    let mut loops: Vec<(usize, Label, Label)> = Vec::new();
+    let mut labels = 2;
+    let mut label = || {
+        labels += 1;
+        nth(labels - 1)
+    };
+
+    let mut i = 0;
+    while i < src.len() {
+        let c = src[i];
+        let run = src[i..].iter().take_while(|&&b| b == c).count();
+        match c {
+            b'>' | b'<' => out.extend([push(run as i64), if c == b'>' { Insn::Add } else { Insn::Sub }]),
+            b'+' | b'-' => {
+                let step = if c == b'+' { run as i64 } else { CELL - run as i64 % CELL };
+                out.extend([Insn::Dup, Insn::Dup, Insn::Load, push(step), Insn::Add]);
+                out.extend([push(CELL), Insn::Mod, Insn::Store]);
+            }
+            b'.' => out.extend((0..run).flat_map(|_| [Insn::Dup, Insn::Load, Insn::Ochr])),
+            b',' => out.extend((0..run).flat_map(|_| [Insn::Dup, Insn::Ichr])),
+            b'[' if src[i..].starts_with(b"[-]") => {
+                out.extend([Insn::Dup, push(0), Insn::Store]);
+                i += 3;
+                continue;
+            }
+            b'[' => {
+                let (start, end) = (label(), label());
+                out.extend([Insn::Label(start.clone()), Insn::Dup, Insn::Load, Insn::Jz(end.clone())]);
+                loops.push((i, start, end));
+                i += 1;
+                continue;
+            }
+            b']' => {
+                let (_, start, end) = loops.pop().ok_or(AlbusError::ParseError { offset: i, reason: "unmatched ]" })?;
+                out.extend([Insn::Jump(start), Insn::Label(end)]);
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        i += run;
+    }
+
+    if let Some((offset, ..)) = loops.pop() {
+        return Err(AlbusError::ParseError { offset, reason: "unmatched [" });
+    }
+    out.push(Insn::Exit);
+
+    Ok(out)
+}
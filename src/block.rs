@@ -0,0 +1,39 @@
+use crate::Insn;
+use std::ops::Range;
+
+// A maximal straight-line run of instructions: control only enters at a label or after a
+// flow-control instruction, and only leaves at the end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub range: Range<usize>,
+}
+
+fn ends_block(insn: &Insn) -> bool {
+    matches!(
+        insn,
+        Insn::Call(_) | Insn::Jump(_) | Insn::Jz(_) | Insn::Jn(_) | Insn::Ret | Insn::Exit
+    )
+}
+
+pub fn blocks(insns: &[Insn]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+
+    for (i, insn) in insns.iter().enumerate() {
+        if let Insn::Label(_) = insn {
+            if i > start {
+                blocks.push(Block { range: start..i });
+                start = i;
+            }
+        }
+        if ends_block(insn) {
+            blocks.push(Block { range: start..i + 1 });
+            start = i + 1;
+        }
+    }
+    if start < insns.len() {
+        blocks.push(Block { range: start..insns.len() });
+    }
+
+    blocks
+}
@@ -0,0 +1,191 @@
+use crate::{AlbusError, Insn, Label, Num, Result};
+use hashbrown::HashMap;
+use num_bigint::{BigUint, Sign};
+use num_traits::{ToPrimitive, Zero};
+
+pub const MAGIC: &[u8] = b"ALBC\x02";
+
+const OPCODES: [&str; 28] = [
+    "none", "push", "pop", "dup", "swap", "copy", "slide", "add", "sub", "mul", "div", "mod", "label", "call", "jump",
+    "jz", "jn", "ret", "store", "load", "ichr", "inum", "ochr", "onum", "exit",
+    "dumpstack", "dumpheap", "random",
+];
+
+// Matches any version of the format, so that decoding can reject old ones explicitly.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC[..4])
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut n: BigUint) {
+    loop {
+        let byte = (&n & BigUint::from(0x7fu8)).to_u8().unwrap();
+        n >>= 7;
+        if n.is_zero() {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Zigzag encoding keeps small negative numbers as short as small positive ones.
+pub(crate) fn write_num(out: &mut Vec<u8>, n: &Num) {
+    let (sign, mag) = (n.sign(), n.magnitude());
+    write_varint(out, if sign == Sign::Minus { (mag << 1) - 1u8 } else { mag << 1 });
+}
+
+// Labels are stored as their length in bits followed by the bits packed into bytes.
+fn write_label(out: &mut Vec<u8>, l: &Label) {
+    write_varint(out, BigUint::from(l.bits().len()));
+    for chunk in l.bits().chunks(8) {
+        out.push(chunk.iter().enumerate().fold(0, |byte, (i, &bit)| byte | bit << (7 - i)));
+    }
+}
+
+// Flow control stores the index of its target label instead of the label itself.
+pub fn encode(insns: &[Insn], labels: &HashMap<Label, usize>) -> Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    write_varint(&mut out, BigUint::from(insns.len()));
+
+    for (ip, insn) in insns.iter().enumerate() {
+        out.push(OPCODES.iter().position(|&op| op == insn.mnemonic()).unwrap() as u8);
+
+        match insn {
+            Insn::Push(n) | Insn::Copy(n) | Insn::Slide(n) => write_num(&mut out, n),
+            Insn::Label(l) => write_label(&mut out, l),
+            Insn::Call(l) | Insn::Jump(l) | Insn::Jz(l) | Insn::Jn(l) => match labels.get(l) {
+                Some(&target) => write_varint(&mut out, BigUint::from(target)),
+                None => return Err(AlbusError::UndefinedLabel { ip, label: l.clone() }),
+            },
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn error(&self, reason: &'static str) -> AlbusError {
+        AlbusError::ParseError { offset: self.pos, reason }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        let byte = *self.bytes.get(self.pos).ok_or_else(|| self.error("unexpected end of bytecode"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<BigUint> {
+        let mut n = BigUint::zero();
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            n |= BigUint::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    pub(crate) fn num(&mut self) -> Result<Num> {
+        let n = self.varint()?;
+        Ok(if n.bit(0) {
+            -Num::from((n >> 1) + 1u8)
+        } else {
+            Num::from(n >> 1)
+        })
+    }
+
+    fn label(&mut self) -> Result<Label> {
+        let len = self.index()?;
+        let mut bits = Vec::new();
+        for i in 0..len {
+            if i % 8 == 0 {
+                self.byte()?;
+            }
+            bits.push(self.bytes[self.pos - 1] >> (7 - i % 8) & 1);
+        }
+        Ok(Label::new(bits))
+    }
+
+    pub(crate) fn index(&mut self) -> Result<usize> {
+        self.varint()?.to_usize().ok_or_else(|| self.error("jump target out of range"))
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(Vec<Insn>, HashMap<Label, usize>)> {
+    let mut r = Reader { bytes, pos: 0 };
+    if !is_bytecode(bytes) {
+        return Err(r.error("not an albus bytecode file"));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err(r.error("unsupported bytecode version"));
+    }
+    r.pos = MAGIC.len();
+
+    let len = r.index()?;
+    let mut insns = Vec::new();
+    let mut targets = Vec::new();
+    let mut labels = HashMap::new();
+
+    for _ in 0..len {
+        let insn = match OPCODES.get(r.byte()? as usize).copied() {
+            Some("push") => Insn::Push(r.num()?),
+            Some("copy") => Insn::Copy(r.num()?),
+            Some("slide") => Insn::Slide(r.num()?),
+            Some("label") => {
+                let l = r.label()?;
+                labels.insert(l.clone(), insns.len());
+                Insn::Label(l)
+            }
+            Some(op @ "call") | Some(op @ "jump") | Some(op @ "jz") | Some(op @ "jn") => {
+                targets.push((insns.len(), op, r.index()?, r.pos));
+                Insn::None
+            }
+            Some("none") => Insn::None,
+            Some("pop") => Insn::Pop,
+            Some("dup") => Insn::Dup,
+            Some("swap") => Insn::Swap,
+            Some("add") => Insn::Add,
+            Some("sub") => Insn::Sub,
+            Some("mul") => Insn::Mul,
+            Some("div") => Insn::Div,
+            Some("mod") => Insn::Mod,
+            Some("ret") => Insn::Ret,
+            Some("store") => Insn::Store,
+            Some("load") => Insn::Load,
+            Some("ichr") => Insn::Ichr,
+            Some("inum") => Insn::Inum,
+            Some("ochr") => Insn::Ochr,
+            Some("onum") => Insn::Onum,
+            Some("exit") => Insn::Exit,
+            Some("dumpstack") => Insn::DumpStack,
+            Some("dumpheap") => Insn::DumpHeap,
+            Some("random") => Insn::Random,
+            _ => return Err(r.error("unknown opcode")),
+        };
+        insns.push(insn);
+    }
+
+    for (i, op, target, pos) in targets {
+        let l = match insns.get(target) {
+            Some(Insn::Label(l)) => l.clone(),
+            _ => return Err(AlbusError::ParseError { offset: pos, reason: "jump target is not a label" }),
+        };
+        insns[i] = match op {
+            "call" => Insn::Call(l),
+            "jump" => Insn::Jump(l),
+            "jz" => Insn::Jz(l),
+            _ => Insn::Jn(l),
+        };
+    }
+
+    Ok((insns, labels))
+}
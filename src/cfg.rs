@@ -0,0 +1,68 @@
+use crate::{blocks, Insn, Label};
+use hashbrown::HashMap;
+use std::fmt::Write;
+
+// Escapes text for a double-quoted DOT string.
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Renders the control-flow graph of a program as Graphviz DOT, one node per basic block.
+// Solid edges are jumps and fallthrough, a call has a dashed edge to its subroutine and a
+// dotted one to where it returns, and blocks ending in `ret` or `exit` are drawn bold.
+pub fn cfg(insns: &[Insn], labels: &HashMap<Label, usize>) -> String {
+    let blocks = blocks(insns);
+    let starts: HashMap<usize, usize> = blocks.iter().enumerate().map(|(i, b)| (b.range.start, i)).collect();
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+    let mut undefined = Vec::new();
+
+    for (i, block) in blocks.iter().enumerate() {
+        let mut text = String::new();
+        for ip in block.range.clone() {
+            write!(text, "{}: {}\\l", ip, quote(&insns[ip].to_string())).unwrap();
+        }
+        let last = &insns[block.range.end - 1];
+        let style = if matches!(last, Insn::Ret | Insn::Exit) { ", style=bold" } else { "" };
+        writeln!(out, "    b{} [label=\"{}\"{}];", i, text, style).unwrap();
+
+        let next = starts.get(&block.range.end);
+        let mut edge = |to: String, attrs: &str| writeln!(out, "    b{} -> {}{};", i, to, attrs).unwrap();
+        let mut target = |l: &Label| match labels.get(l) {
+            Some(t) => format!("b{}", starts[t]),
+            None => {
+                undefined.push(l.clone());
+                format!("\"undefined {}\"", l)
+            }
+        };
+        match last {
+            Insn::Jump(l) => edge(target(l), ""),
+            Insn::Jz(l) | Insn::Jn(l) => {
+                edge(target(l), &format!(" [label=\"{}\"]", last.mnemonic()));
+                if let Some(next) = next {
+                    edge(format!("b{}", next), "");
+                }
+            }
+            Insn::Call(l) => {
+                edge(target(l), " [label=\"call\", style=dashed]");
+                if let Some(next) = next {
+                    edge(format!("b{}", next), " [label=\"return\", style=dotted]");
+                }
+            }
+            Insn::Ret | Insn::Exit => {}
+            _ => {
+                if let Some(next) = next {
+                    edge(format!("b{}", next), "");
+                }
+            }
+        }
+    }
+
+    undefined.sort();
+    undefined.dedup();
+    for l in undefined {
+        writeln!(out, "    \"undefined {}\" [color=red, fontcolor=red];", l).unwrap();
+    }
+    out.push_str("}\n");
+
+    out
+}
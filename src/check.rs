@@ -0,0 +1,224 @@
+use crate::{load_source, Insn, Label, Num, ParseOptions, Result};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+// A problem found without running the program. `ip` is the instruction it concerns, or
+// the instruction count for problems at the end of the program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub ip: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: instruction {}: {}", severity, self.ip, self.message)
+    }
+}
+
+// Whether control can reach the instruction after `insn`. Calls are assumed to return.
+fn falls_through(insn: &Insn) -> bool {
+    !matches!(insn, Insn::Jump(_) | Insn::Ret | Insn::Exit)
+}
+
+// Finds the instructions reachable from the start of the program.
+pub(crate) fn reachable(insns: &[Insn], labels: &HashMap<Label, usize>) -> Vec<bool> {
+    reachable_from(insns, labels, vec![0])
+}
+
+// Finds the instructions reachable from any of the places to start from.
+pub(crate) fn reachable_from(insns: &[Insn], labels: &HashMap<Label, usize>, mut work: Vec<usize>) -> Vec<bool> {
+    let mut seen = vec![false; insns.len()];
+
+    while let Some(ip) = work.pop() {
+        if ip >= insns.len() || seen[ip] {
+            continue;
+        }
+        seen[ip] = true;
+        if let Insn::Call(l) | Insn::Jump(l) | Insn::Jz(l) | Insn::Jn(l) = &insns[ip] {
+            work.extend(labels.get(l));
+        }
+        if falls_through(&insns[ip]) {
+            work.push(ip + 1);
+        }
+    }
+
+    seen
+}
+
+pub fn check(insns: &[Insn], labels: &HashMap<Label, usize>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |severity, ip, message| diagnostics.push(Diagnostic { severity, ip, message });
+    let mut defined = HashMap::new();
+
+    for (ip, insn) in insns.iter().enumerate() {
+        match insn {
+            Insn::Label(l) => {
+                if let Some(first) = defined.insert(l, ip) {
+                    let message = format!("label {} is already defined at instruction {}", l, first);
+                    report(Severity::Warning, ip, message);
+                }
+            }
+            Insn::Call(l) | Insn::Jump(l) | Insn::Jz(l) | Insn::Jn(l) if !labels.contains_key(l) => {
+                report(Severity::Error, ip, format!("undefined label {}", l));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(last) = insns.len().checked_sub(1) {
+        if falls_through(&insns[last]) && reachable(insns, labels)[last] {
+            report(Severity::Warning, last, "execution can run past the end of the program".into());
+        }
+    }
+
+    diagnostics
+}
+
+// Checks a program as it would be loaded, also reporting source that ends partway through
+// an instruction. That is an error unless the options allow it.
+pub fn check_source(bytes: &[u8], options: &ParseOptions) -> Result<Vec<Diagnostic>> {
+    let lenient = ParseOptions { lenient: true, ..options.clone() };
+    let parsed = load_source(bytes, &lenient)?;
+    let mut diagnostics = check(&parsed.insns, &parsed.labels);
+    if let Some(at) = parsed.truncated {
+        let severity = if options.lenient { Severity::Warning } else { Severity::Error };
+        let message = format!("truncated instruction at offset {} ({})", at.offset, at);
+        let ip = parsed.locations.iter().position(|&l| l == at).unwrap_or(parsed.insns.len());
+        diagnostics.push(Diagnostic { severity, ip, message });
+    }
+
+    Ok(diagnostics)
+}
+
+// The lowest depth the relative analysis of a subroutine tracks, so that loops which keep
+// popping still settle.
+const FLOOR: i64 = -1024;
+
+// How many times subroutine effects are recomputed before those still changing, which only
+// happens with recursion that keeps consuming the stack, are given up on.
+const ROUNDS: usize = 32;
+
+// How many values an instruction needs on the stack, and how it changes the depth.
+fn stack_effect(insn: &Insn) -> (i64, i64) {
+    let arg = |n: &Num| n.to_i64().unwrap_or(i64::MAX).max(0).saturating_add(1);
+    match insn {
+        Insn::Push(_) => (0, 1),
+        Insn::Dup => (1, 1),
+        Insn::Copy(n) => (arg(n), 1),
+        Insn::Slide(n) => (arg(n), 1 - arg(n)),
+        Insn::Swap => (2, 0),
+        Insn::Add | Insn::Sub | Insn::Mul | Insn::Div | Insn::Mod => (2, -1),
+        Insn::Store => (2, -2),
+        Insn::Load | Insn::Random => (1, 0),
+        Insn::Pop | Insn::Ichr | Insn::Inum | Insn::Ochr | Insn::Onum | Insn::Jz(_) | Insn::Jn(_) => (1, -1),
+        _ => (0, 0),
+    }
+}
+
+struct Flow {
+    // The lowest stack depth each instruction can be reached with, if it can be reached.
+    depths: Vec<Option<i64>>,
+    // The lowest depth any reachable `Ret` can be reached with.
+    ret: Option<i64>,
+}
+
+// Follows every path from `entry`, starting with `depth` values on the stack. A call
+// continues after itself with the net effect recorded for its subroutine, and only enters
+// the subroutine too if `enter_calls` is set.
+fn flow(
+    insns: &[Insn],
+    labels: &HashMap<Label, usize>,
+    effects: &HashMap<usize, Option<i64>>,
+    (entry, depth): (usize, i64),
+    floor: i64,
+    enter_calls: bool,
+) -> Flow {
+    let mut flow = Flow { depths: vec![None; insns.len()], ret: None };
+    let mut work = vec![(entry, depth)];
+    let lower = |a: Option<i64>, b| Some(a.map_or(b, |a: i64| a.min(b)));
+
+    while let Some((ip, depth)) = work.pop() {
+        match flow.depths.get(ip) {
+            Some(Some(seen)) if *seen <= depth => continue,
+            Some(_) => flow.depths[ip] = Some(depth),
+            None => continue,
+        }
+
+        let after = (depth + stack_effect(&insns[ip]).1).max(floor);
+        let target = insns[ip].label().and_then(|l| labels.get(l)).copied();
+        match &insns[ip] {
+            Insn::Jump(_) => work.extend(target.map(|t| (t, after))),
+            Insn::Jz(_) | Insn::Jn(_) => {
+                work.extend(target.map(|t| (t, after)));
+                work.push((ip + 1, after));
+            }
+            Insn::Call(_) => {
+                if enter_calls {
+                    work.extend(target.map(|t| (t, after)));
+                }
+                if let Some(Some(effect)) = target.and_then(|t| effects.get(&t)) {
+                    work.push((ip + 1, (after + effect).max(floor)));
+                }
+            }
+            Insn::Ret => flow.ret = lower(flow.ret, depth),
+            Insn::Exit => {}
+            _ => work.push((ip + 1, after)),
+        }
+    }
+
+    flow
+}
+
+// Reports instructions that may find fewer values on the stack than they need along some
+// path through the program. Subroutines are summarized by the lowest net change in depth
+// they can return with, so a call is followed by its worst case.
+pub fn check_stack(insns: &[Insn], labels: &HashMap<Label, usize>) -> Vec<Diagnostic> {
+    let mut effects: HashMap<usize, Option<i64>> = insns
+        .iter()
+        .filter_map(|insn| match insn {
+            Insn::Call(l) => labels.get(l).map(|&t| (t, None)),
+            _ => None,
+        })
+        .collect();
+
+    for round in 0.. {
+        let mut changed = false;
+        for t in effects.keys().copied().collect::<Vec<_>>() {
+            let effect = flow(insns, labels, &effects, (t, 0), FLOOR, false).ret;
+            if effect != effects[&t] {
+                effects.insert(t, if round < ROUNDS { effect } else { Some(FLOOR) });
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let depths = flow(insns, labels, &effects, (0, 0), 0, true).depths;
+    insns
+        .iter()
+        .zip(depths)
+        .enumerate()
+        .filter_map(|(ip, (insn, depth))| {
+            let (need, _) = stack_effect(insn);
+            let depth = depth.filter(|&depth| depth < need)?;
+            let values = if need == 1 { "value" } else { "values" };
+            let message = format!("{} needs {} {} but the stack may hold only {}", insn.mnemonic(), need, values, depth);
+            Some(Diagnostic { severity: Severity::Warning, ip, message })
+        })
+        .collect()
+}
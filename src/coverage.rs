@@ -0,0 +1,79 @@
+use crate::{disassemble, Insn};
+use hashbrown::HashMap;
+use std::fmt::Write;
+
+// Labels only mark a place in the program, so they are neither covered nor missed.
+fn counted(insn: &Insn) -> bool {
+    !matches!(insn, Insn::Label(_) | Insn::None)
+}
+
+// The line each instruction is on in the program's disassembly, counting from 1, which is
+// what coverage is reported against since a Whitespace source has no useful lines.
+fn lines(insns: &[Insn]) -> Vec<Option<usize>> {
+    let mut line = 0;
+    insns
+        .iter()
+        .map(|insn| {
+            (*insn != Insn::None).then(|| {
+                line += 1;
+                line
+            })
+        })
+        .collect()
+}
+
+// How many of a program's instructions were executed, out of how many could have been.
+pub fn summary(insns: &[Insn], counts: &[u64]) -> (usize, usize) {
+    let executed = |ip: usize| counts.get(ip).is_some_and(|&n| n > 0);
+    let counted: Vec<_> = (0..insns.len()).filter(|&ip| counted(&insns[ip])).collect();
+    (counted.iter().filter(|&&ip| executed(ip)).count(), counted.len())
+}
+
+// An lcov tracefile for execution counts per instruction, naming `source` as the file.
+pub fn lcov(insns: &[Insn], counts: &[u64], source: &str) -> String {
+    let mut out = format!("TN:\nSF:{}\n", source);
+    for (ip, line) in lines(insns).into_iter().enumerate() {
+        if let Some(line) = line.filter(|_| counted(&insns[ip])) {
+            writeln!(out, "DA:{},{}", line, counts.get(ip).copied().unwrap_or(0)).unwrap();
+        }
+    }
+    let (hit, found) = summary(insns, counts);
+    writeln!(out, "LF:{}\nLH:{}\nend_of_record", found, hit).unwrap();
+
+    out
+}
+
+// Reads execution counts per instruction back from a tracefile written by `lcov`, ignoring
+// lines that don't belong to the program.
+pub fn read_lcov(insns: &[Insn], src: &str) -> Vec<u64> {
+    let ips: HashMap<_, _> = lines(insns).into_iter().enumerate().filter_map(|(ip, l)| Some((l?, ip))).collect();
+    let mut counts = vec![0; insns.len()];
+    for record in src.lines().filter_map(|line| line.strip_prefix("DA:")) {
+        let mut fields = record.split(',');
+        let line = fields.next().and_then(|l| l.parse().ok());
+        let n = fields.next().and_then(|n| n.parse::<u64>().ok());
+        if let (Some(&ip), Some(n)) = (line.and_then(|l| ips.get(&l)), n) {
+            counts[ip] += n;
+        }
+    }
+
+    counts
+}
+
+// The disassembly with each instruction's execution count before it, marking those never
+// executed with `#####` as gcov does.
+pub fn annotate(insns: &[Insn], counts: &[u64]) -> String {
+    let listing = disassemble(insns);
+    let shown = insns.iter().enumerate().filter(|(_, insn)| **insn != Insn::None);
+    let mut out = String::new();
+    for ((ip, insn), line) in shown.zip(listing.lines()) {
+        let count = match counts.get(ip).copied().unwrap_or(0) {
+            _ if !counted(insn) => "-".to_string(),
+            0 => "#####".to_string(),
+            n => n.to_string(),
+        };
+        writeln!(out, "{:>10}: {}", count, line).unwrap();
+    }
+
+    out
+}
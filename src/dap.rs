@@ -0,0 +1,335 @@
+use crate::{disassemble, json::Json, load_with, Alphabet, Condition, Debugger, Dialect, ParseOptions, Result, Vm};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+// Reads a message framed with a Content-Length header, as both DAP and LSP send them.
+pub(crate) fn recv(input: &mut dyn BufRead) -> io::Result<Option<Json>> {
+    let mut len = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(n) = header.strip_prefix("Content-Length:") {
+            len = n.trim().parse().ok();
+        }
+    }
+
+    let mut body = vec![0; len.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Json::parse(&String::from_utf8_lossy(&body)))
+}
+
+pub(crate) fn send(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+const STACK_REF: u64 = 1;
+const HEAP_REF: u64 = 2;
+
+// Breakpoints and stack frames refer to lines of the disassembly, which the client fetches
+// through the `source` request; line N holds instruction N - 1.
+pub struct DapServer<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    seq: u64,
+    debugger: Option<Debugger>,
+    name: String,
+    stop_on_entry: bool,
+}
+
+impl<'a> DapServer<'a> {
+    pub fn new(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> DapServer<'a> {
+        DapServer {
+            input,
+            output,
+            seq: 0,
+            debugger: None,
+            name: String::new(),
+            stop_on_entry: false,
+        }
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", self.seq.into()));
+        send(self.output, &Json::object(fields))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        let mut fields = vec![("type", "event".into()), ("event", event.into())];
+        if body != Json::Null {
+            fields.push(("body", body));
+        }
+        self.send(fields)
+    }
+
+    fn respond(&mut self, request: &Json, body: std::result::Result<Json, String>) -> io::Result<()> {
+        let mut fields = vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("command", request.get("command").cloned().unwrap_or(Json::Null)),
+            ("success", body.is_ok().into()),
+        ];
+        match body {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body", body)),
+            Err(message) => fields.push(("message", message.into())),
+        }
+        self.send(fields)
+    }
+
+    fn source(&self) -> Json {
+        Json::object(vec![("name", self.name.as_str().into()), ("sourceReference", 1usize.into())])
+    }
+
+    // Reports where the program stopped after resuming it.
+    fn stopped(&mut self, result: Result<bool>, reason: &str) -> io::Result<()> {
+        if let Some(debugger) = &mut self.debugger {
+            debugger.vm.output.flush()?;
+        }
+        match result {
+            Ok(true) => self.event(
+                "stopped",
+                Json::object(vec![
+                    ("reason", reason.into()),
+                    ("threadId", 1usize.into()),
+                    ("allThreadsStopped", true.into()),
+                ]),
+            ),
+            Ok(false) => {
+                self.event("exited", Json::object(vec![("exitCode", 0usize.into())]))?;
+                self.event("terminated", Json::object(vec![]))
+            }
+            Err(e) => self.event(
+                "stopped",
+                Json::object(vec![
+                    ("reason", "exception".into()),
+                    ("description", e.to_string().into()),
+                    ("text", e.to_string().into()),
+                    ("threadId", 1usize.into()),
+                    ("allThreadsStopped", true.into()),
+                ]),
+            ),
+        }
+    }
+
+    fn launch(&mut self, args: &Json) -> std::result::Result<Json, String> {
+        let path = args.get("program").and_then(Json::as_str).ok_or("missing `program`")?;
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let extension = |name: &str| args.get("extensions").and_then(Json::as_array).is_some_and(|e| e.contains(&name.into()));
+        let alphabet = match args.get("tokens").and_then(Json::as_str) {
+            Some(tokens) => tokens.parse().map_err(|e| format!("bad `tokens`: {}", e))?,
+            None => Alphabet::default(),
+        };
+        let dialect = match args.get("dialect").and_then(Json::as_str) {
+            Some(dialect) => dialect.parse().map_err(|_| "`dialect` must be 0.2 or 0.3")?,
+            None => Dialect::default(),
+        };
+        let options = ParseOptions {
+            legacy_labels: args.get("legacyLabels").and_then(Json::as_bool).unwrap_or(false),
+            lenient: args.get("lenient").and_then(Json::as_bool).unwrap_or(false),
+            strict: args.get("strictParse").and_then(Json::as_bool).unwrap_or(false),
+            debug_opcodes: extension("debug"),
+            random_opcode: extension("random"),
+            alphabet,
+            dialect,
+        };
+        let (insns, labels) = load_with(&bytes, &options).map_err(|e| e.to_string())?;
+
+        self.name = Path::new(path).file_name().map_or(path.into(), |n| n.to_string_lossy().into_owned());
+        self.stop_on_entry = args.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+        let vm = Vm::new(insns, labels).map_err(|e| e.to_string())?;
+
+        self.debugger = Some(Debugger::new(vm));
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> std::result::Result<Json, String> {
+        let debugger = self.debugger.as_mut().ok_or("no program launched")?;
+        let lines = args.get("breakpoints").and_then(Json::as_array).unwrap_or(&[]);
+
+        debugger.breakpoints.clear();
+        let mut verified = Vec::new();
+        for bp in lines {
+            let line = bp.get("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+            let at = line.checked_sub(1).and_then(|i| debugger.locate(&i.to_string()));
+            if let Some(i) = at {
+                let condition = bp.get("condition").and_then(Json::as_str).and_then(Condition::parse);
+                debugger.breakpoints.insert(i, condition);
+            }
+            verified.push(Json::object(vec![
+                ("verified", at.is_some().into()),
+                ("line", at.map_or(line, |i| i + 1).into()),
+            ]));
+        }
+
+        Ok(Json::object(vec![("breakpoints", verified.into())]))
+    }
+
+    fn stack_trace(&self) -> std::result::Result<Json, String> {
+        let vm = &self.debugger.as_ref().ok_or("no program launched")?.vm;
+        let sites = std::iter::once(vm.ip).chain(vm.calls.iter().rev().copied());
+
+        let frames: Vec<Json> = sites
+            .enumerate()
+            .map(|(id, i)| {
+                let name = vm.insns().get(i).map_or("<end>".into(), |insn| insn.to_string());
+                Json::object(vec![
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("line", (i + 1).into()),
+                    ("column", 1usize.into()),
+                    ("source", self.source()),
+                ])
+            })
+            .collect();
+
+        let total = frames.len();
+        Ok(Json::object(vec![("stackFrames", frames.into()), ("totalFrames", total.into())]))
+    }
+
+    fn variables(&self, args: &Json) -> std::result::Result<Json, String> {
+        let vm = &self.debugger.as_ref().ok_or("no program launched")?.vm;
+        let var = |name: String, value: String| {
+            Json::object(vec![
+                ("name", name.into()),
+                ("value", value.into()),
+                ("variablesReference", 0usize.into()),
+            ])
+        };
+
+        let vars: Vec<Json> = match args.get("variablesReference").and_then(Json::as_u64) {
+            Some(STACK_REF) => vm
+                .stack
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, v)| var(format!("[{}]", i), v.to_string()))
+                .collect(),
+            Some(HEAP_REF) => {
+                let mut heap: Vec<_> = vm.heap.iter().collect();
+                heap.sort();
+                heap.into_iter().map(|(k, v)| var(k.to_string(), v.to_string())).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(Json::object(vec![("variables", vars.into())]))
+    }
+
+    fn resume(&mut self, how: fn(&mut Debugger) -> Result<bool>, reason: &str) -> io::Result<()> {
+        let result = match self.debugger.as_mut() {
+            Some(debugger) => how(debugger),
+            None => return Ok(()),
+        };
+        self.stopped(result, reason)
+    }
+
+    // Serves requests until the client disconnects.
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(request) = recv(self.input)? {
+            let command = request.get("command").and_then(Json::as_str).unwrap_or("").to_string();
+            let args = request.get("arguments").cloned().unwrap_or(Json::Null);
+
+            match command.as_str() {
+                "initialize" => {
+                    let caps = Json::object(vec![
+                        ("supportsConfigurationDoneRequest", true.into()),
+                        ("supportsConditionalBreakpoints", true.into()),
+                    ]);
+                    self.respond(&request, Ok(caps))?;
+                }
+                "launch" => {
+                    let result = self.launch(&args);
+                    let ok = result.is_ok();
+                    self.respond(&request, result)?;
+                    if ok {
+                        self.event("initialized", Json::Null)?;
+                    }
+                }
+                "setBreakpoints" => {
+                    let result = self.set_breakpoints(&args);
+                    self.respond(&request, result)?;
+                }
+                "configurationDone" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    let at_breakpoint = self.debugger.as_ref().is_some_and(Debugger::at_breakpoint);
+                    if self.stop_on_entry {
+                        self.stopped(Ok(true), "entry")?;
+                    } else if at_breakpoint {
+                        self.stopped(Ok(true), "breakpoint")?;
+                    } else {
+                        self.resume(Debugger::cont, "breakpoint")?;
+                    }
+                }
+                "threads" => {
+                    let thread = Json::object(vec![("id", 1usize.into()), ("name", "main".into())]);
+                    let body = Json::object(vec![("threads", vec![thread].into())]);
+                    self.respond(&request, Ok(body))?;
+                }
+                "stackTrace" => {
+                    let result = self.stack_trace();
+                    self.respond(&request, result)?;
+                }
+                "scopes" => {
+                    let scope = |name: &str, reference: u64| {
+                        Json::object(vec![
+                            ("name", name.into()),
+                            ("variablesReference", reference.into()),
+                            ("expensive", false.into()),
+                        ])
+                    };
+                    let scopes = vec![scope("Stack", STACK_REF), scope("Heap", HEAP_REF)];
+                    self.respond(&request, Ok(Json::object(vec![("scopes", scopes.into())])))?;
+                }
+                "variables" => {
+                    let result = self.variables(&args);
+                    self.respond(&request, result)?;
+                }
+                "source" => {
+                    let content = self.debugger.as_ref().map(|d| disassemble(d.vm.insns()));
+                    let result = content
+                        .map(|c| Json::object(vec![("content", c.into())]))
+                        .ok_or_else(|| "no program launched".to_string());
+                    self.respond(&request, result)?;
+                }
+                "continue" => {
+                    self.respond(&request, Ok(Json::object(vec![("allThreadsContinued", true.into())])))?;
+                    self.resume(Debugger::cont, "breakpoint")?;
+                }
+                "next" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    self.resume(Debugger::step_over, "step")?;
+                }
+                "stepIn" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    self.resume(Debugger::step, "step")?;
+                }
+                "stepOut" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    self.resume(Debugger::finish, "step")?;
+                }
+                "pause" => self.respond(&request, Ok(Json::Null))?,
+                "disconnect" | "terminate" => {
+                    self.respond(&request, Ok(Json::Null))?;
+                    return Ok(());
+                }
+                _ => self.respond(&request, Err(format!("unsupported request `{}`", command)))?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,627 @@
+use crate::{gen::Rng, Heap, Insn, Label, Num, Result, Symbols, Value, Vm};
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt,
+    io::{self, Cursor, Read, Sink, Stdin, Stdout, Write},
+};
+
+const HELP: &str = "\
+step [N]      execute N instructions (default 1)
+next          step over a call
+finish        run until the current subroutine returns
+continue      run until a breakpoint or the program halts
+rstep [N]     go back N instructions (default 1)
+rcontinue     go back to the previous breakpoint, or the start
+break [LOC]   set a breakpoint at LOC, or list breakpoints; add `if COND` to stop
+              there only when COND holds, comparing `top`, `depth`, `calls` or
+              `steps` to a number with one of == != < <= > >=
+delete LOC    remove the breakpoint at LOC
+watch [A]     stop after a store to heap address A, or list watchpoints; add
+              `changed` to stop only when the value there changes
+unwatch A     remove the watchpoint on A
+list [N]      show N instructions around the current one
+print         show the current instruction
+stack         show the stack
+heap          show the heap
+calls         show the call stack
+quit          leave the debugger
+LOC is an instruction index or @BITS for the definition of the label spelled BITS,
+with 0 for a space and 1 for a tab, or @NAME for a label named in the program's .wsmap,
+or :LINE for the first instruction from that line of the assembly it was made from.";
+
+// How many instructions apart the copies of the state kept for going backwards are.
+const SNAPSHOT_INTERVAL: u64 = 1000;
+
+// The parts of a Vm that change as it runs.
+#[derive(Clone)]
+struct State {
+    stack: Vec<Value>,
+    calls: Vec<usize>,
+    heap: Heap,
+    ip: usize,
+    steps: u64,
+    max_depth: usize,
+    max_call_depth: usize,
+    big_allocated: u64,
+    halted: bool,
+    rng: Rng,
+}
+
+impl State {
+    fn of<R, W>(vm: &Vm<R, W>) -> State {
+        State {
+            stack: vm.stack.clone(),
+            calls: vm.calls.clone(),
+            heap: vm.heap.clone(),
+            ip: vm.ip,
+            steps: vm.steps,
+            max_depth: vm.max_depth,
+            max_call_depth: vm.max_call_depth,
+            big_allocated: vm.big_allocated,
+            halted: vm.halted,
+            rng: vm.rng,
+        }
+    }
+
+    fn apply<R, W>(self, vm: &mut Vm<R, W>) {
+        vm.stack = self.stack;
+        vm.calls = self.calls;
+        vm.heap = self.heap;
+        vm.ip = self.ip;
+        vm.steps = self.steps;
+        vm.max_depth = self.max_depth;
+        vm.max_call_depth = self.max_call_depth;
+        vm.big_allocated = self.big_allocated;
+        vm.halted = self.halted;
+        vm.rng = self.rng;
+    }
+}
+
+// Enough of the past to return to any earlier step by running forward again from the copy
+// of the state before it. Input is kept as bytes that reproduce what was read, rather than
+// what was actually read, since only the values stored matter.
+#[derive(Default)]
+struct History {
+    snapshots: Vec<(State, usize)>,
+    input: Vec<u8>,
+    // How much of the input the program has read at its current step.
+    consumed: usize,
+    // Input ran out, so anything read after it does too.
+    eof: bool,
+    // The furthest the program has run, up to which going forward replays the past.
+    frontier: u64,
+}
+
+// Where a program is, for deciding whether to stop there.
+struct Here<'a> {
+    ip: usize,
+    calls: usize,
+    stack: &'a [Value],
+    steps: u64,
+}
+
+impl Here<'_> {
+    fn of<R, W>(vm: &Vm<R, W>) -> Here<'_> {
+        Here { ip: vm.ip, calls: vm.calls.len(), stack: &vm.stack, steps: vm.steps }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subject {
+    // The value on top of the stack.
+    Top,
+    // How many values are on the stack.
+    Depth,
+    // How many subroutine calls haven't returned.
+    Calls,
+    Steps,
+}
+
+// What must hold for a breakpoint to stop the program, like `top == 0`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub subject: Subject,
+    pub op: Ordering,
+    // Whether `op` is negated, so that `!=`, `<=` and `>=` are the opposites of `==`, `>`
+    // and `<`.
+    pub not: bool,
+    pub value: Value,
+}
+
+impl Condition {
+    pub fn parse(src: &str) -> Option<Condition> {
+        let mut words = src.split_whitespace();
+        let subject = match words.next()? {
+            "top" => Subject::Top,
+            "depth" => Subject::Depth,
+            "calls" => Subject::Calls,
+            "steps" => Subject::Steps,
+            _ => return None,
+        };
+        let (op, not) = match words.next()? {
+            "==" => (Ordering::Equal, false),
+            "!=" => (Ordering::Equal, true),
+            "<" => (Ordering::Less, false),
+            ">=" => (Ordering::Less, true),
+            ">" => (Ordering::Greater, false),
+            "<=" => (Ordering::Greater, true),
+            _ => return None,
+        };
+        let value = words.next()?.parse::<Num>().ok()?.into();
+
+        words.next().is_none().then_some(Condition { subject, op, not, value })
+    }
+
+    fn holds(&self, here: &Here) -> bool {
+        let actual = match self.subject {
+            Subject::Top => match here.stack.last() {
+                Some(top) => top.clone(),
+                None => return false,
+            },
+            Subject::Depth => Value::from(here.stack.len() as i64),
+            Subject::Calls => Value::from(here.calls as i64),
+            Subject::Steps => Value::from(here.steps as i64),
+        };
+        (actual.cmp(&self.value) == self.op) != self.not
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subject = match self.subject {
+            Subject::Top => "top",
+            Subject::Depth => "depth",
+            Subject::Calls => "calls",
+            Subject::Steps => "steps",
+        };
+        let op = match (self.op, self.not) {
+            (Ordering::Equal, false) => "==",
+            (Ordering::Equal, true) => "!=",
+            (Ordering::Less, false) => "<",
+            (Ordering::Less, true) => ">=",
+            (Ordering::Greater, false) => ">",
+            (Ordering::Greater, true) => "<=",
+        };
+        write!(f, "{} {} {}", subject, op, self.value)
+    }
+}
+
+// Breakpoints by instruction, with the condition for each to stop the program if it has one.
+pub type Breakpoints = BTreeMap<usize, Option<Condition>>;
+
+fn breaks(breakpoints: &Breakpoints, here: &Here) -> bool {
+    match breakpoints.get(&here.ip) {
+        Some(Some(condition)) => condition.holds(here),
+        Some(None) => true,
+        None => false,
+    }
+}
+
+// A store to a watched address about to happen, with what was there before it.
+struct Store {
+    key: Value,
+    old: Option<Value>,
+    changed: bool,
+}
+
+impl Store {
+    fn about<R: Read, W: Write>(watchpoints: &BTreeMap<Value, bool>, vm: &Vm<R, W>) -> Option<Store> {
+        if !matches!(vm.current(), Some(Insn::Store)) {
+            return None;
+        }
+        let key = vm.stack.iter().rev().nth(1)?;
+        let &changed = watchpoints.get(key)?;
+        Some(Store { key: key.clone(), old: vm.heap.get(key).cloned(), changed })
+    }
+
+    // Whether the store, now that it happened, should stop the program.
+    fn hit(&self, heap: &Heap) -> bool {
+        !self.changed || heap.get(&self.key) != self.old.as_ref()
+    }
+}
+
+pub struct Debugger<R = Stdin, W = Stdout> {
+    pub vm: Vm<R, W>,
+    pub breakpoints: Breakpoints,
+    // Heap addresses to stop after stores to, and whether only if the value changes.
+    pub watchpoints: BTreeMap<Value, bool>,
+    // The store that last stopped the program, as its address and old and new values.
+    pub stored: Option<(Value, Option<Value>, Value)>,
+    // Names to show labels by, and to accept in locations.
+    pub symbols: Symbols,
+    history: History,
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    pub fn new(vm: Vm<R, W>) -> Debugger<R, W> {
+        let mut history = History { frontier: vm.steps, ..History::default() };
+        history.snapshots.push((State::of(&vm), 0));
+        let (breakpoints, watchpoints, symbols) = (Breakpoints::new(), BTreeMap::new(), Symbols::new());
+        Debugger { vm, breakpoints, watchpoints, stored: None, symbols, history }
+    }
+
+    // A Vm to rerun the past with, starting from a state and where in the input it was.
+    fn replayer(&self, state: State, consumed: usize) -> Vm<Cursor<Vec<u8>>, Sink> {
+        let input = Cursor::new(self.history.input[consumed..].to_vec());
+        let insns = self.vm.insns().to_vec();
+        let mut vm = Vm::with_io(insns, self.vm.labels().clone(), input, io::sink()).expect("labels were resolved");
+        vm.limits = self.vm.limits.clone();
+        vm.eof = self.vm.eof;
+        vm.trunc_div = self.vm.trunc_div;
+        vm.clamp_args = self.vm.clamp_args;
+        vm.tail_calls = self.vm.tail_calls;
+        vm.int_width = self.vm.int_width;
+        vm.charset = self.vm.charset;
+        state.apply(&mut vm);
+        vm
+    }
+
+    // Takes on the state a replay reached.
+    fn adopt(&mut self, replay: Vm<Cursor<Vec<u8>>, Sink>, consumed: usize) {
+        self.history.consumed = consumed + replay.input.position() as usize;
+        State::of(&replay).apply(&mut self.vm);
+    }
+
+    // Executes an instruction for real, noting what it read and keeping a copy of the state
+    // every so often.
+    fn record(&mut self) -> Result<bool> {
+        let chr = matches!(self.vm.current(), Some(Insn::Ichr));
+        let reading = chr || matches!(self.vm.current(), Some(Insn::Inum));
+        let key = self.vm.stack.last().cloned().filter(|_| reading);
+        let result = self.vm.step();
+
+        let read = key.and_then(|k| self.vm.heap.get(&k)).filter(|_| result.is_ok() && !self.history.eof);
+        match (read, chr) {
+            (Some(v), true) => match self.vm.charset.encode(v) {
+                Some(bytes) => self.history.input.extend(bytes),
+                None => self.history.eof = true,
+            },
+            (Some(v), false) => self.history.input.extend(format!("{}\n", v).bytes()),
+            (None, _) if reading && self.vm.halted => self.history.eof = true,
+            (None, _) => {}
+        }
+        self.history.consumed = self.history.input.len();
+
+        let last = self.history.snapshots.last().map_or(0, |(s, _)| s.steps);
+        if self.vm.steps.is_multiple_of(SNAPSHOT_INTERVAL) && self.vm.steps > last {
+            self.history.snapshots.push((State::of(&self.vm), self.history.consumed));
+        }
+        self.history.frontier = self.history.frontier.max(self.vm.steps);
+        result
+    }
+
+    // Runs until `stop` accepts where the program is or a watchpoint is hit, always executing
+    // at least one instruction. Steps already taken before are replayed, so the program sees
+    // the same input again and its output isn't repeated.
+    fn run_until(&mut self, mut stop: impl FnMut(&Here) -> bool) -> Result<bool> {
+        let watchpoints = self.watchpoints.clone();
+        self.stored = None;
+
+        if self.vm.steps < self.history.frontier && !self.vm.halted {
+            let consumed = self.history.consumed;
+            let mut replay = self.replayer(State::of(&self.vm), consumed);
+            while replay.steps < self.history.frontier {
+                let store = Store::about(&watchpoints, &replay);
+                let result = replay.step();
+                let stopped = match result {
+                    Ok(true) => {
+                        self.stored = store.filter(|s| s.hit(&replay.heap)).map(|s| self.written(s, &replay.heap));
+                        self.stored.is_some() || stop(&Here::of(&replay))
+                    }
+                    _ => true,
+                };
+                if stopped {
+                    self.adopt(replay, consumed);
+                    return result;
+                }
+            }
+            self.adopt(replay, consumed);
+        }
+
+        loop {
+            let store = Store::about(&watchpoints, &self.vm);
+            if !self.record()? {
+                return Ok(false);
+            }
+            self.stored = store.filter(|s| s.hit(&self.vm.heap)).map(|s| self.written(s, &self.vm.heap));
+            if self.stored.is_some() || stop(&Here::of(&self.vm)) {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn written(&self, store: Store, heap: &Heap) -> (Value, Option<Value>, Value) {
+        let new = heap[&store.key].clone();
+        (store.key, store.old, new)
+    }
+
+    // Returns to the first point the program had taken `steps` steps, past any labels. An
+    // instruction that failed isn't repeated.
+    fn rewind(&mut self, steps: u64) -> Result<bool> {
+        let (state, consumed) = match self.history.snapshots.iter().rev().find(|(s, _)| s.steps <= steps) {
+            Some((state, consumed)) => (state.clone(), *consumed),
+            None => return Ok(true),
+        };
+        let mut replay = self.replayer(state, consumed);
+        while replay.steps < steps || matches!(replay.current(), Some(Insn::Label(_)) | Some(Insn::None)) {
+            if !matches!(replay.step(), Ok(true)) {
+                break;
+            }
+        }
+        self.adopt(replay, consumed);
+        Ok(true)
+    }
+
+    // Goes back `n` instructions, or to the start of the program.
+    pub fn reverse_step(&mut self, n: u64) -> Result<bool> {
+        self.stored = None;
+        let start = self.history.snapshots[0].0.steps;
+        self.rewind(self.vm.steps.saturating_sub(n).max(start))
+    }
+
+    // Goes back to the last time the program was at a breakpoint, or to its start.
+    pub fn reverse_continue(&mut self) -> Result<bool> {
+        self.stored = None;
+        let now = self.vm.steps;
+        for (state, consumed) in self.history.snapshots.iter().rev().filter(|(s, _)| s.steps < now) {
+            let mut replay = self.replayer(state.clone(), *consumed);
+            let mut hit = None;
+            while replay.steps < now && matches!(replay.step(), Ok(true)) {
+                if breaks(&self.breakpoints, &Here::of(&replay)) && replay.steps < now {
+                    hit = Some(replay.steps);
+                }
+            }
+            if let Some(steps) = hit {
+                return self.rewind(steps);
+            }
+        }
+
+        let start = self.history.snapshots[0].0.clone();
+        start.apply(&mut self.vm);
+        self.history.consumed = 0;
+        Ok(true)
+    }
+
+    // Resolves a location to the first non-label instruction at or after it, since jumps
+    // resume execution just past their target label.
+    pub fn locate(&self, loc: &str) -> Option<usize> {
+        let insns = self.vm.insns();
+        let mut i = if let Some(label) = loc.strip_prefix('@') {
+            let named = self.symbols.label(label).cloned();
+            *self.vm.labels().get(&label.parse::<Label>().ok().or(named)?)?
+        } else if let Some(line) = loc.strip_prefix(':') {
+            self.symbols.ip(line.parse().ok()?)?
+        } else {
+            loc.parse().ok().filter(|&i| i < insns.len())?
+        };
+
+        while let Some(Insn::Label(_)) = insns.get(i) {
+            i += 1;
+        }
+        Some(i).filter(|&i| i < insns.len())
+    }
+
+    pub fn step(&mut self) -> Result<bool> {
+        self.run_until(|_| true)
+    }
+
+    // Runs until the next breakpoint, always executing at least one instruction.
+    pub fn cont(&mut self) -> Result<bool> {
+        let breakpoints = self.breakpoints.clone();
+        self.run_until(|here| breaks(&breakpoints, here))
+    }
+
+    fn run_to_depth(&mut self, depth: usize) -> Result<bool> {
+        let breakpoints = self.breakpoints.clone();
+        self.run_until(|here| here.calls < depth || breaks(&breakpoints, here))
+    }
+
+    // Steps over calls, stopping early only at breakpoints inside the callee.
+    pub fn step_over(&mut self) -> Result<bool> {
+        let depth = self.vm.calls.len();
+        let call = matches!(self.vm.current(), Some(Insn::Call(_)));
+
+        if !self.step()? {
+            return Ok(false);
+        }
+        if call && self.vm.calls.len() > depth && !self.at_breakpoint() {
+            self.run_to_depth(depth + 1)
+        } else {
+            Ok(true)
+        }
+    }
+
+    // Whether the program is at a breakpoint whose condition holds.
+    pub fn at_breakpoint(&self) -> bool {
+        breaks(&self.breakpoints, &Here::of(&self.vm))
+    }
+
+    // Runs until the current subroutine returns.
+    pub fn finish(&mut self) -> Result<bool> {
+        let depth = self.vm.calls.len();
+        self.run_to_depth(depth)
+    }
+
+    pub(crate) fn show(&self, out: &mut dyn Write, i: usize) -> io::Result<()> {
+        let mark = if i == self.vm.ip { "=>" } else { "  " };
+        let bp = if self.breakpoints.contains_key(&i) { '*' } else { ' ' };
+        let insn = self.symbols.show(&self.vm.insns()[i]);
+        match self.symbols.line(i) {
+            Some(line) => writeln!(out, "{}{}{:>5}  {:<16} ; line {}", mark, bp, i, insn, line),
+            None => writeln!(out, "{}{}{:>5}  {}", mark, bp, i, insn),
+        }
+    }
+
+    fn show_current(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self.vm.current() {
+            Some(_) => self.show(out, self.vm.ip),
+            None => writeln!(out, "program halted after {} instructions", self.vm.steps),
+        }
+    }
+
+    fn report(&mut self, out: &mut dyn Write, result: Result<bool>) -> io::Result<()> {
+        self.vm.output.flush()?;
+        match result {
+            Ok(_) => {
+                if let Some((key, old, new)) = &self.stored {
+                    let old = old.as_ref().map_or_else(|| "unset".to_string(), Value::to_string);
+                    writeln!(out, "watchpoint {}: {} -> {}", key, old, new)?;
+                }
+                self.show_current(out)
+            }
+            Err(e) => match e.ip().and_then(|ip| self.symbols.at(ip)) {
+                Some(at) => writeln!(out, "error: {} ({})", e, at),
+                None => writeln!(out, "error: {}", e),
+            },
+        }
+    }
+
+    // Executes one debugger command, returning false when the session should end.
+    pub fn command(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let arg = words.next();
+
+        match cmd {
+            "s" | "step" => {
+                let n = arg.and_then(|n| n.parse().ok()).unwrap_or(1);
+                let mut result = Ok(true);
+                for _ in 0..n {
+                    result = self.step();
+                    if !matches!(result, Ok(true)) {
+                        break;
+                    }
+                }
+                self.report(out, result)?;
+            }
+            "n" | "next" => {
+                let result = self.step_over();
+                self.report(out, result)?;
+            }
+            "finish" => {
+                let result = self.finish();
+                self.report(out, result)?;
+            }
+            "c" | "continue" => {
+                let result = self.cont();
+                self.report(out, result)?;
+            }
+            "rs" | "rstep" | "reverse-step" => {
+                let n = arg.and_then(|n| n.parse().ok()).unwrap_or(1);
+                let result = self.reverse_step(n);
+                self.report(out, result)?;
+            }
+            "rc" | "rcontinue" | "reverse-continue" => {
+                let result = self.reverse_continue();
+                self.report(out, result)?;
+            }
+            "b" | "break" => match arg {
+                None => {
+                    for (&i, condition) in &self.breakpoints {
+                        self.show(out, i)?;
+                        if let Some(condition) = condition {
+                            writeln!(out, "          if {}", condition)?;
+                        }
+                    }
+                }
+                Some(loc) => {
+                    let rest: Vec<_> = words.collect();
+                    let condition = match rest.split_first() {
+                        None => Some(None),
+                        Some((&"if", cond)) => Condition::parse(&cond.join(" ")).map(Some),
+                        Some(_) => None,
+                    };
+                    match (self.locate(loc), condition) {
+                        (Some(i), Some(condition)) => {
+                            self.breakpoints.insert(i, condition);
+                            writeln!(out, "breakpoint at {}", i)?;
+                        }
+                        (None, _) => writeln!(out, "no such location: {}", loc)?,
+                        (_, None) => writeln!(out, "bad condition; try `help`")?,
+                    }
+                }
+            },
+            "d" | "delete" => match arg.and_then(|loc| self.locate(loc)) {
+                Some(i) if self.breakpoints.remove(&i).is_some() => writeln!(out, "deleted breakpoint at {}", i)?,
+                _ => writeln!(out, "no breakpoint there")?,
+            },
+            "w" | "watch" => match arg {
+                None => {
+                    for (key, &changed) in &self.watchpoints {
+                        writeln!(out, "{}{}", key, if changed { " changed" } else { "" })?;
+                    }
+                }
+                Some(key) => match (key.parse::<Num>().map(Value::from), words.next()) {
+                    (Ok(key), changed @ (None | Some("changed"))) => {
+                        writeln!(out, "watchpoint on {}", key)?;
+                        self.watchpoints.insert(key, changed.is_some());
+                    }
+                    _ => writeln!(out, "usage: watch ADDRESS [changed]")?,
+                },
+            },
+            "unwatch" => match arg.and_then(|key| key.parse::<Num>().ok()).map(Value::from) {
+                Some(key) if self.watchpoints.remove(&key).is_some() => writeln!(out, "deleted watchpoint on {}", key)?,
+                _ => writeln!(out, "no watchpoint there")?,
+            },
+            "l" | "list" => {
+                let n = arg.and_then(|n| n.parse().ok()).unwrap_or(10);
+                let start = self.vm.ip.saturating_sub(n / 2);
+                let end = (start + n).min(self.vm.insns().len());
+                for i in start..end {
+                    self.show(out, i)?;
+                }
+            }
+            "p" | "print" => self.show_current(out)?,
+            "stack" => {
+                let stack: Vec<_> = self.vm.stack.iter().map(Value::to_string).collect();
+                writeln!(out, "[{}]", stack.join(", "))?;
+            }
+            "heap" => {
+                let mut heap: Vec<_> = self.vm.heap.iter().collect();
+                heap.sort();
+                for (k, v) in heap {
+                    writeln!(out, "{}: {}", k, v)?;
+                }
+            }
+            "calls" => {
+                for &i in self.vm.calls.iter().rev() {
+                    self.show(out, i)?;
+                }
+            }
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(out, "unknown command `{}`; try `help`", cmd)?,
+        }
+
+        Ok(true)
+    }
+
+    // Drives an interactive session, repeating the previous command on an empty line. Lines
+    // are read one at a time so the program itself can share the same input.
+    pub fn session(
+        &mut self,
+        read_line: &mut dyn FnMut(&mut String) -> io::Result<usize>,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let mut last = String::from("print");
+        let mut line = String::new();
+
+        self.show_current(out)?;
+        loop {
+            write!(out, "(albus) ")?;
+            out.flush()?;
+
+            line.clear();
+            if read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !line.trim().is_empty() {
+                last = line.trim().to_string();
+            }
+            if !self.command(&last, out)? {
+                return Ok(());
+            }
+        }
+    }
+}
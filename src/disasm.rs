@@ -0,0 +1,57 @@
+use crate::{Insn, Label, Location, Symbols};
+use hashbrown::HashMap;
+use std::fmt::Write;
+
+// Where source positions go when the disassembly is annotated with them.
+const COMMENT_COLUMN: usize = 24;
+
+pub fn disassemble(insns: &[Insn]) -> String {
+    disassemble_located(insns, &[])
+}
+
+// Disassembles with a comment after each instruction giving where it was in the source,
+// for as many instructions as there are locations.
+pub fn disassemble_located(insns: &[Insn], locations: &[Location]) -> String {
+    disassemble_named(insns, locations, &Symbols::new())
+}
+
+// Disassembles with labels called by their names in the source where they have them, and
+// numbered past any of those names otherwise.
+pub fn disassemble_named(insns: &[Insn], locations: &[Location], symbols: &Symbols) -> String {
+    let mut names = HashMap::<&Label, String>::new();
+    let mut next = 0;
+    let mut out = String::new();
+
+    for (ip, insn) in insns.iter().enumerate() {
+        let line = out.len();
+        match insn {
+            Insn::None => continue,
+            Insn::Label(_) => {}
+            _ => out.push_str("    "),
+        }
+        out.push_str(insn.mnemonic());
+
+        if let Some(l) = insn.label() {
+            let name = names.entry(l).or_insert_with(|| match symbols.name(l) {
+                Some(name) => name.to_string(),
+                None => loop {
+                    next += 1;
+                    let name = format!("L{}", next - 1);
+                    if symbols.label(&name).is_none() {
+                        break name;
+                    }
+                },
+            });
+            write!(out, " {}", name).unwrap();
+        } else if let Some(n) = insn.arg() {
+            write!(out, " {}", n).unwrap();
+        }
+        if let Some(at) = locations.get(ip) {
+            let width = COMMENT_COLUMN.saturating_sub(out.len() - line);
+            write!(out, "{:width$} ; {}", "", at, width = width).unwrap();
+        }
+        out.push('\n');
+    }
+
+    out
+}
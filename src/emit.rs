@@ -0,0 +1,63 @@
+use crate::{Insn, Label, Num};
+use num_traits::Signed;
+
+fn emit_num(out: &mut String, n: &Num) {
+    out.push(if n.is_negative() { '\t' } else { ' ' });
+    for bit in n.magnitude().to_str_radix(2).chars().skip_while(|&b| b == '0') {
+        out.push(if bit == '1' { '\t' } else { ' ' });
+    }
+    out.push('\n');
+}
+
+fn emit_label(out: &mut String, l: &Label) {
+    for &bit in l.bits() {
+        out.push(if bit == 1 { '\t' } else { ' ' });
+    }
+    out.push('\n');
+}
+
+pub fn emit(insns: &[Insn]) -> String {
+    let mut out = String::new();
+
+    for insn in insns {
+        let code = match insn {
+            Insn::None => continue,
+            Insn::Push(_) => "  ",
+            Insn::Pop => " \n\n",
+            Insn::Dup => " \n ",
+            Insn::Swap => " \n\t",
+            Insn::Copy(_) => " \t ",
+            Insn::Slide(_) => " \t\n",
+            Insn::Add => "\t   ",
+            Insn::Sub => "\t  \t",
+            Insn::Mul => "\t  \n",
+            Insn::Div => "\t \t ",
+            Insn::Mod => "\t \t\t",
+            Insn::Label(_) => "\n  ",
+            Insn::Call(_) => "\n \t",
+            Insn::Jump(_) => "\n \n",
+            Insn::Jz(_) => "\n\t ",
+            Insn::Jn(_) => "\n\t\t",
+            Insn::Ret => "\n\t\n",
+            Insn::Store => "\t\t ",
+            Insn::Load => "\t\t\t",
+            Insn::Ichr => "\t\n\t ",
+            Insn::Inum => "\t\n\t\t",
+            Insn::Ochr => "\t\n  ",
+            Insn::Onum => "\t\n \t",
+            Insn::Exit => "\n\n\n",
+            Insn::DumpStack => "\n\n  ",
+            Insn::DumpHeap => "\n\n \t",
+            Insn::Random => "\n\n \n",
+        };
+
+        out.push_str(code);
+        if let Some(n) = insn.arg() {
+            emit_num(&mut out, n);
+        } else if let Some(l) = insn.label() {
+            emit_label(&mut out, l);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,110 @@
+use crate::{Label, Num};
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum AlbusError {
+    ParseError { offset: usize, reason: &'static str },
+    TruncatedInstruction { offset: usize },
+    // Tokens that don't begin any opcode, found when parsing strictly.
+    UnknownOpcode { offset: usize, tokens: String },
+    AsmError { line: usize, reason: String },
+    // Source compressed in a format it can't be decompressed from.
+    Decompression { format: &'static str, reason: &'static str },
+    UndefinedLabel { ip: usize, label: Label },
+    StackUnderflow { ip: usize },
+    CallStackUnderflow { ip: usize },
+    BadArgument { ip: usize, arg: Num },
+    ArgumentOutOfRange { ip: usize, arg: Num, depth: usize },
+    BadInput { ip: usize, input: String },
+    UnexpectedEof { ip: usize },
+    BadChar { ip: usize, value: Num },
+    UninitializedHeap { ip: usize, key: Num },
+    DivisionByZero { ip: usize },
+    IoError { ip: usize, error: io::Error },
+    StepLimitExceeded { ip: usize, limit: u64 },
+    ResourceExhausted { ip: usize, resource: &'static str, limit: usize },
+    TimedOut { ip: usize, steps: u64 },
+    // A hook stopped the program.
+    Stopped { ip: usize, reason: String },
+    HostFailed { ip: usize, label: Label, reason: String },
+    Interrupted { ip: usize, steps: u64 },
+    Unsupported { ip: usize, what: &'static str, target: &'static str },
+}
+
+pub type Result<T> = std::result::Result<T, AlbusError>;
+
+impl AlbusError {
+    // The instruction the error happened at, for errors raised while running a program.
+    pub fn ip(&self) -> Option<usize> {
+        use AlbusError::*;
+
+        match self {
+            ParseError { .. }
+            | TruncatedInstruction { .. }
+            | UnknownOpcode { .. }
+            | AsmError { .. }
+            | Decompression { .. } => None,
+            UndefinedLabel { ip, .. }
+            | StackUnderflow { ip }
+            | CallStackUnderflow { ip }
+            | BadArgument { ip, .. }
+            | ArgumentOutOfRange { ip, .. }
+            | BadInput { ip, .. }
+            | UnexpectedEof { ip }
+            | BadChar { ip, .. }
+            | UninitializedHeap { ip, .. }
+            | DivisionByZero { ip }
+            | IoError { ip, .. }
+            | StepLimitExceeded { ip, .. }
+            | ResourceExhausted { ip, .. }
+            | TimedOut { ip, .. }
+            | Stopped { ip, .. }
+            | HostFailed { ip, .. }
+            | Interrupted { ip, .. }
+            | Unsupported { ip, .. } => Some(*ip),
+        }
+    }
+}
+
+impl fmt::Display for AlbusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AlbusError::*;
+
+        match self {
+            ParseError { offset, reason } => write!(f, "parse error at token {}: {}", offset, reason),
+            TruncatedInstruction { offset } => write!(f, "truncated instruction at offset {}", offset),
+            UnknownOpcode { offset, tokens } => write!(f, "unknown opcode {} at offset {}", tokens, offset),
+            AsmError { line, reason } => write!(f, "line {}: {}", line, reason),
+            Decompression { format, reason } => write!(f, "unable to decompress {} source: {}", format, reason),
+            UndefinedLabel { ip, label } => write!(f, "undefined label {} at instruction {}", label, ip),
+            StackUnderflow { ip } => write!(f, "stack underflow at instruction {}", ip),
+            CallStackUnderflow { ip } => write!(f, "return outside of a call at instruction {}", ip),
+            BadArgument { ip, arg } => write!(f, "argument {} out of range at instruction {}", arg, ip),
+            ArgumentOutOfRange { ip, arg, depth } => {
+                let values = if *depth == 1 { "value" } else { "values" };
+                write!(f, "argument {} out of range for {} {} on the stack at instruction {}", arg, depth, values, ip)
+            }
+            BadInput { ip, input } => write!(f, "invalid number {:?} read at instruction {}", input, ip),
+            UnexpectedEof { ip } => write!(f, "end of input at instruction {}", ip),
+            BadChar { ip, value } => write!(f, "{} is not a character at instruction {}", value, ip),
+            UninitializedHeap { ip, key } => {
+                write!(f, "load from uninitialized heap address {} at instruction {}", key, ip)
+            }
+            DivisionByZero { ip } => write!(f, "division by zero at instruction {}", ip),
+            IoError { ip, error } => write!(f, "{} at instruction {}", error, ip),
+            StepLimitExceeded { ip, limit } => write!(f, "step limit of {} exceeded at instruction {}", limit, ip),
+            ResourceExhausted { ip, resource, limit } => {
+                write!(f, "{} limit of {} exceeded at instruction {}", resource, limit, ip)
+            }
+            TimedOut { ip, steps } => write!(f, "timed out at instruction {} after {} instructions", ip, steps),
+            Stopped { ip, reason } => write!(f, "stopped at instruction {}: {}", ip, reason),
+            Unsupported { ip, what, target } => write!(f, "{} at instruction {} isn't supported by {}", what, ip, target),
+            Interrupted { ip, steps } => write!(f, "interrupted at instruction {} after {} instructions", ip, steps),
+            HostFailed { ip, label, reason } => {
+                write!(f, "host function {} failed at instruction {}: {}", label, ip, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for AlbusError {}
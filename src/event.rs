@@ -0,0 +1,71 @@
+use crate::{AlbusError, Charset, Insn, Vm};
+use std::{collections::VecDeque, io::Read};
+
+// Input that an embedder hands over as it becomes available. Reading never blocks: once
+// what was given runs out, the program waits for more unless the feed has been closed.
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Feed {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    // Marks the end of input, after which reading past it does what the Vm's `eof` says.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    // Whether there's enough input for the instruction, which for `inum` is a whole line.
+    fn ready(&self, insn: &Insn, charset: Charset) -> bool {
+        let wanted = match (insn, self.bytes.front()) {
+            (Insn::Inum, _) => return self.closed || self.bytes.contains(&b'\n'),
+            // A byte that can't start a character is read as one on its own.
+            (Insn::Ichr, Some(&lead)) => charset.width(lead).unwrap_or(1),
+            (Insn::Ichr, None) => 1,
+            _ => 0,
+        };
+        self.closed || self.bytes.len() >= wanted
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.bytes.read(buf)
+    }
+}
+
+// What happened when the program was stepped.
+#[derive(Debug)]
+pub enum StepEvent {
+    // An instruction ran without writing anything.
+    Executed,
+    // An instruction ran and wrote these bytes.
+    Output(Vec<u8>),
+    // The next instruction reads input that hasn't been given yet, so it wasn't run.
+    NeedsInput,
+    Halted,
+    // The instruction failed.
+    Trapped(AlbusError),
+}
+
+impl Vm<Feed, Vec<u8>> {
+    // Runs the next instruction unless it would wait on input, for embedders that drive
+    // the program themselves and handle its input and output as they see fit.
+    pub fn step_event(&mut self) -> StepEvent {
+        if let Some(insn) = self.current() {
+            if !self.input.ready(insn, self.charset) {
+                return StepEvent::NeedsInput;
+            }
+        }
+        match self.step() {
+            Ok(_) if !self.output.is_empty() => StepEvent::Output(std::mem::take(&mut self.output)),
+            Ok(true) => StepEvent::Executed,
+            Ok(false) => StepEvent::Halted,
+            Err(e) => StepEvent::Trapped(e),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// A C interface for embedding albus, declared in include/albus.h. What callers must
+// uphold is described there once rather than on every function.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::{disassemble, load_source, ParseOptions, Vm};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::{
+    ffi::CString,
+    io::Cursor,
+    os::raw::{c_char, c_int},
+    ptr, slice,
+};
+
+// A program being run, along with input given to it that it hasn't read yet, and the last
+// error and disassembly, kept so that they outlive the calls that returned them.
+pub struct AlbusVm {
+    vm: Vm<Cursor<Vec<u8>>, Vec<u8>>,
+    error: Option<CString>,
+    text: Option<CString>,
+}
+
+impl AlbusVm {
+    fn fail(&mut self, e: impl ToString) -> c_int {
+        self.error = CString::new(e.to_string()).ok();
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn albus_vm_new() -> *mut AlbusVm {
+    let vm = Vm::with_io(Vec::new(), HashMap::new(), Cursor::new(Vec::new()), Vec::new()).expect("nothing to resolve");
+    Box::into_raw(Box::new(AlbusVm { vm, error: None, text: None }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_free(vm: *mut AlbusVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+// Loads Whitespace source, replacing any program loaded before but keeping unread input.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_load(vm: *mut AlbusVm, src: *const u8, len: usize) -> c_int {
+    let vm = &mut *vm;
+    let parsed = match load_source(slice::from_raw_parts(src, len), &ParseOptions::default()) {
+        Ok(parsed) => parsed,
+        Err(e) => return vm.fail(e),
+    };
+    let input = std::mem::take(&mut vm.vm.input);
+    match Vm::with_io(parsed.insns, parsed.labels, input, Vec::new()) {
+        Ok(loaded) => {
+            vm.vm = loaded;
+            0
+        }
+        Err(e) => vm.fail(e),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_write_input(vm: *mut AlbusVm, data: *const u8, len: usize) {
+    (*vm).vm.input.get_mut().extend_from_slice(slice::from_raw_parts(data, len));
+}
+
+// Returns 1 if the program can carry on, 0 once it has halted and -1 if it failed.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_step(vm: *mut AlbusVm) -> c_int {
+    let vm = &mut *vm;
+    match vm.vm.step() {
+        Ok(running) => running as c_int,
+        Err(e) => vm.fail(e),
+    }
+}
+
+// Returns 0 once the program halts and -1 if it fails.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_run(vm: *mut AlbusVm) -> c_int {
+    let vm = &mut *vm;
+    match vm.vm.run() {
+        Ok(()) => 0,
+        Err(e) => vm.fail(e),
+    }
+}
+
+// Moves up to `cap` bytes of output not yet read into `buf`, returning how many.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_read_output(vm: *mut AlbusVm, buf: *mut u8, cap: usize) -> usize {
+    let output = &mut (*vm).vm.output;
+    let n = output.len().min(cap);
+    ptr::copy_nonoverlapping(output.as_ptr(), buf, n);
+    output.drain(..n);
+    n
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_stack_len(vm: *const AlbusVm) -> usize {
+    (*vm).vm.stack.len()
+}
+
+// Reads the value `i` places from the top of the stack, returning -1 if there is none or
+// it doesn't fit in 64 bits.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_stack_get(vm: *const AlbusVm, i: usize, out: *mut i64) -> c_int {
+    match (*vm).vm.stack.iter().rev().nth(i).and_then(|v| v.to_num().to_i64()) {
+        Some(n) => {
+            *out = n;
+            0
+        }
+        None => -1,
+    }
+}
+
+// Reads the heap at `key`, returning -1 if it was never stored to or doesn't fit.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_heap_get(vm: *const AlbusVm, key: i64, out: *mut i64) -> c_int {
+    match (*vm).vm.heap.get(&key.into()).and_then(|v| v.to_num().to_i64()) {
+        Some(n) => {
+            *out = n;
+            0
+        }
+        None => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_steps(vm: *const AlbusVm) -> u64 {
+    (*vm).vm.steps
+}
+
+// The message for the last failure, valid until the next one or until the Vm is freed, or
+// null if nothing has failed.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_error(vm: *const AlbusVm) -> *const c_char {
+    (*vm).error.as_ref().map_or(ptr::null(), |e| e.as_ptr())
+}
+
+// The loaded program's disassembly, valid until the next call for it or until the Vm is
+// freed.
+#[no_mangle]
+pub unsafe extern "C" fn albus_vm_disassemble(vm: *mut AlbusVm) -> *const c_char {
+    let vm = &mut *vm;
+    vm.text = CString::new(disassemble(vm.vm.insns())).ok();
+    vm.text.as_ref().map_or(ptr::null(), |text| text.as_ptr())
+}
@@ -0,0 +1,24 @@
+use crate::{emit, lex::Tokens, parse_source, AlbusError, ParseOptions, Result};
+
+// Rewrites source as nothing but spaces, tabs and linefeeds, whatever alphabet it was
+// written in. Canonical output also writes each number and label the one way `emit` does,
+// without leading zeroes or a negative zero. Either way the result is parsed again to check
+// that it's the same program, failing at the first instruction that came out different.
+pub fn format_source(src: &[u8], options: &ParseOptions, canonical: bool) -> Result<String> {
+    let parsed = parse_source(src, options)?;
+    let out = if canonical {
+        emit(&parsed.insns)
+    } else {
+        Tokens::new(src, &options.alphabet).map(char::from).collect()
+    };
+
+    let plain = ParseOptions { alphabet: Default::default(), ..options.clone() };
+    let again = parse_source(out.as_bytes(), &plain)?;
+    if again.insns != parsed.insns {
+        let i = parsed.insns.iter().zip(&again.insns).take_while(|(a, b)| a == b).count();
+        let offset = parsed.locations.get(i).map_or(src.len(), |at| at.offset);
+        return Err(AlbusError::ParseError { offset, reason: "formatting changed the instruction here" });
+    }
+
+    Ok(out)
+}
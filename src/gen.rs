@@ -0,0 +1,213 @@
+use crate::{minify::nth, Insn, Label, Num};
+
+// How many heap addresses generated programs use, all set to 0 before anything else runs.
+const HEAP_KEYS: i64 = 8;
+
+// How deeply loops, conditionals and calls nest, and how many times a loop runs at most,
+// which together bound how long a program can run.
+const MAX_NESTING: usize = 3;
+const MAX_ITERATIONS: i64 = 4;
+
+// SplitMix64, so that a seed always gives the same program wherever it's generated, and the
+// same random numbers to a program that asks for them.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + self.below((hi - lo + 1) as u64) as i64
+    }
+}
+
+struct Generator {
+    rng: Rng,
+    out: Vec<Insn>,
+    labels: usize,
+    // The subroutines' labels and bodies, emitted after the main program so it never falls
+    // into them. They don't call anything themselves, so none can recurse.
+    subroutines: Vec<(Label, Vec<Insn>)>,
+    in_subroutine: bool,
+    // How many instructions are left to generate.
+    budget: usize,
+}
+
+impl Generator {
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        nth(self.labels - 1)
+    }
+
+    fn push(&mut self, n: i64) {
+        self.out.push(Insn::Push(Num::from(n)));
+    }
+
+    fn number(&mut self) -> i64 {
+        match self.rng.below(8) {
+            0 => self.rng.range(-1_000_000, 1_000_000),
+            1 => self.rng.range(-128, -1),
+            _ => self.rng.range(0, 127),
+        }
+    }
+
+    // Generates instructions that leave the stack `depth` values above where it was when
+    // they started, never touching what was below.
+    fn block(&mut self, nesting: usize) {
+        let mut depth = 0;
+        while self.budget > 0 && self.rng.below(12) != 0 {
+            self.budget -= 1;
+            depth = self.insn(depth, nesting);
+        }
+        for _ in 0..depth {
+            self.out.push(Insn::Pop);
+        }
+    }
+
+    // Generates one instruction, or a few that only make sense together, for a stack
+    // `depth` values above the block's start, returning its depth afterwards.
+    fn insn(&mut self, depth: usize, nesting: usize) -> usize {
+        let depth = depth as i64;
+        let (insn, change) = match self.rng.below(22) {
+            0..=3 => {
+                let n = self.number();
+                self.push(n);
+                return depth as usize + 1;
+            }
+            4 if depth >= 1 => (Insn::Dup, 1),
+            5 if depth >= 2 => (Insn::Swap, 0),
+            6 if depth >= 1 => (Insn::Copy(Num::from(self.rng.range(0, depth - 1))), 1),
+            7 if depth >= 1 => {
+                let n = self.rng.range(0, depth - 1);
+                (Insn::Slide(Num::from(n)), -n)
+            }
+            8 if depth >= 1 => (Insn::Pop, -1),
+            9 if depth >= 2 => (Insn::Add, -1),
+            10 if depth >= 2 => (Insn::Sub, -1),
+            11 if depth >= 2 => (Insn::Mul, -1),
+            // Dividing by a constant other than 0 can't fail.
+            12 if depth >= 1 => {
+                let n = self.rng.range(1, 9) * if self.rng.below(2) == 0 { 1 } else { -1 };
+                self.push(n);
+                (if self.rng.below(2) == 0 { Insn::Div } else { Insn::Mod }, 0)
+            }
+            13 if depth >= 1 => {
+                let key = self.rng.range(0, HEAP_KEYS - 1);
+                self.push(key);
+                self.out.push(Insn::Swap);
+                (Insn::Store, -1)
+            }
+            14 => {
+                let key = self.rng.range(0, HEAP_KEYS - 1);
+                self.push(key);
+                (Insn::Load, 1)
+            }
+            15 if depth >= 1 => (Insn::Onum, -1),
+            16 => {
+                let c = self.rng.range(32, 126);
+                self.push(c);
+                (Insn::Ochr, 0)
+            }
+            17 | 18 if nesting < MAX_NESTING => {
+                self.repeat(nesting + 1);
+                return depth as usize;
+            }
+            19 if depth >= 1 && nesting < MAX_NESTING => {
+                self.branch(nesting + 1);
+                return depth as usize - 1;
+            }
+            20 if nesting < MAX_NESTING && !self.in_subroutine => {
+                self.call(nesting + 1);
+                return depth as usize;
+            }
+            _ => {
+                let n = self.number();
+                self.push(n);
+                return depth as usize + 1;
+            }
+        };
+        self.out.push(insn);
+        (depth + change) as usize
+    }
+
+    // A loop that runs a block a few times, counting down on the stack.
+    fn repeat(&mut self, nesting: usize) {
+        let (start, end) = (self.label(), self.label());
+        let n = self.rng.range(1, MAX_ITERATIONS);
+        self.push(n);
+        self.out.push(Insn::Label(start.clone()));
+        self.block(nesting);
+        self.push(1);
+        self.out.extend([Insn::Sub, Insn::Dup, Insn::Jz(end.clone()), Insn::Jump(start), Insn::Label(end), Insn::Pop]);
+    }
+
+    // A block that only runs if the value on top of the stack is zero, or is negative.
+    fn branch(&mut self, nesting: usize) {
+        let skip = self.label();
+        let taken = self.label();
+        let jump = if self.rng.below(2) == 0 { Insn::Jz(taken.clone()) } else { Insn::Jn(taken.clone()) };
+        self.out.extend([jump, Insn::Jump(skip.clone()), Insn::Label(taken)]);
+        self.block(nesting);
+        self.out.push(Insn::Label(skip));
+    }
+
+    // A call to a new subroutine, or to one already generated.
+    fn call(&mut self, nesting: usize) {
+        let existing = self.rng.below(self.subroutines.len() as u64 + 1) as usize;
+        if let Some((l, _)) = self.subroutines.get(existing) {
+            let call = Insn::Call(l.clone());
+            self.out.push(call);
+            return;
+        }
+
+        let l = self.label();
+        let caller = std::mem::take(&mut self.out);
+        self.in_subroutine = true;
+        self.block(nesting);
+        self.in_subroutine = false;
+        self.out.push(Insn::Ret);
+        let body = std::mem::replace(&mut self.out, caller);
+        self.subroutines.push((l.clone(), body));
+        self.out.push(Insn::Call(l));
+    }
+}
+
+// Generates a random program of about `size` instructions from `seed`. Every label it uses
+// is defined once, every loop runs a bounded number of times and nothing can fail, so the
+// program always halts the same way, which makes it suited to comparing implementations.
+pub fn generate(seed: u64, size: usize) -> Vec<Insn> {
+    let mut gen = Generator {
+        rng: Rng(seed),
+        out: Vec::new(),
+        labels: 0,
+        subroutines: Vec::new(),
+        in_subroutine: false,
+        budget: size,
+    };
+    for key in 0..HEAP_KEYS {
+        gen.push(key);
+        gen.push(0);
+        gen.out.push(Insn::Store);
+    }
+    while gen.budget > 0 {
+        gen.block(0);
+    }
+    gen.out.push(Insn::Exit);
+
+    let mut insns = gen.out;
+    for (l, body) in gen.subroutines {
+        insns.push(Insn::Label(l));
+        insns.extend(body);
+    }
+    insns
+}
@@ -0,0 +1,217 @@
+// Decompresses gzip, as RFC 1951 and 1952 describe, for sources shipped compressed.
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+// Reads bits from the least significant end of each byte first, as DEFLATE packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = *self.data.get(self.pos).ok_or("the data ends early")?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// A canonical Huffman code, as how many codes there are of each length and the symbols in
+// order of their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("a code is invalid")
+    }
+}
+
+// The lengths and distances each code stands for, before any extra bits are added.
+const LENGTHS: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// The order code length codes are given in for a block with its own codes.
+const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed() -> (Huffman, Huffman) {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let mut order = [0; 19];
+    for &i in &ORDER[..bits.bits(4)? as usize + 4] {
+        order[i] = bits.bits(3)? as u8;
+    }
+    let codes = Huffman::new(&order);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, times) = match codes.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths.last().ok_or("a length repeats before any is given")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, times as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err("code lengths run past the codes");
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn inflate(bits: &mut Bits, out: &mut Vec<u8>) -> Result<()> {
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.data.get(bits.pos..bits.pos + 4).ok_or("the data ends early")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let stored = bits.data.get(bits.pos + 4..bits.pos + 4 + len).ok_or("the data ends early")?;
+                out.extend_from_slice(stored);
+                bits.pos += 4 + len;
+            }
+            kind @ (1 | 2) => {
+                let (literals, distances) = if kind == 1 { fixed() } else { dynamic(bits)? };
+                loop {
+                    let symbol = literals.decode(bits)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    } else if symbol == 256 {
+                        break;
+                    }
+                    let i = symbol - 257;
+                    let len = *LENGTHS.get(i).ok_or("a length code is invalid")? as usize;
+                    let len = len + bits.bits(LENGTH_BITS[i] as u32)? as usize;
+                    let d = distances.decode(bits)? as usize;
+                    let distance = *DISTANCES.get(d).ok_or("a distance code is invalid")? as usize;
+                    let distance = distance + bits.bits(DISTANCE_BITS[d] as u32)? as usize;
+                    let start = out.len().checked_sub(distance).ok_or("a distance reaches too far back")?;
+                    for i in start..start + len {
+                        out.push(out[i]);
+                    }
+                }
+            }
+            _ => return Err("a block is of an unknown type"),
+        }
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub(crate) const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Decompresses every member of the data, one after another, checking each against its
+// checksum.
+pub(crate) fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 10).ok_or("the header ends early")?;
+        if header[..2] != MAGIC || header[2] != 8 {
+            return Err("a member isn't compressed with DEFLATE");
+        }
+        let flags = header[3];
+        pos += 10;
+        if flags & 4 != 0 {
+            let extra = data.get(pos..pos + 2).ok_or("the header ends early")?;
+            pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        // The file name and comment each end with a zero byte.
+        for flag in [8, 16] {
+            if flags & flag != 0 {
+                pos += data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or("the header ends early")? + 1;
+            }
+        }
+        if flags & 2 != 0 {
+            pos += 2;
+        }
+
+        let start = out.len();
+        let mut bits = Bits { data: data.get(pos..).ok_or("the header ends early")?, pos: 0, bit: 0 };
+        inflate(&mut bits, &mut out)?;
+        bits.align();
+        pos += bits.pos;
+        let trailer = data.get(pos..pos + 8).ok_or("the checksum is missing")?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err("the checksum doesn't match");
+        }
+        pos += 8;
+    }
+    Ok(out)
+}
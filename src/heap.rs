@@ -0,0 +1,187 @@
+use crate::Value;
+use hashbrown::HashMap;
+use std::{convert::TryFrom, ops::Index, str::FromStr};
+
+// How the heap is laid out. Programs that use it as a tape, at small addresses counting up
+// from 0, are best served by a dense one, while those that scatter values over addresses
+// far apart need a sparse one. Whatever the layout, addresses it doesn't cover are kept
+// sparsely.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeapKind {
+    // Dense for as long as the addresses used stay close together, and sparse past that.
+    #[default]
+    Auto,
+    Sparse,
+    // Dense up to DENSE_MAX, however few addresses are used, but only growing to take an
+    // address at most DENSE_GAP past where it reaches, so that one far off is kept sparsely
+    // until it's nearer.
+    Dense,
+    // In pages of PAGE addresses, for programs that use clusters of nearby addresses, which
+    // may be negative.
+    Paged,
+}
+
+impl FromStr for HeapKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<HeapKind, ()> {
+        match s {
+            "auto" => Ok(HeapKind::Auto),
+            "sparse" => Ok(HeapKind::Sparse),
+            "dense" => Ok(HeapKind::Dense),
+            "paged" => Ok(HeapKind::Paged),
+            _ => Err(()),
+        }
+    }
+}
+
+// The most addresses a dense heap keeps in order, and how many an automatic one starts
+// with. Past its start, an automatic heap only grows to cover addresses below twice how many
+// values it holds, so that it stays at least about half full however far apart they are.
+const DENSE_MAX: usize = 1 << 24;
+const DENSE_GAP: usize = 1 << 16;
+const AUTO_START: usize = 1024;
+const PAGE: i64 = 1024;
+
+// A slot holds the address too, so that every entry can be lent out as a pair of values.
+type Slot = Option<(Value, Value)>;
+
+#[derive(Clone, Debug, Default)]
+pub struct Heap {
+    kind: HeapKind,
+    dense: Vec<Slot>,
+    pages: HashMap<i64, Vec<Slot>>,
+    sparse: HashMap<Value, Value>,
+    // How many slots are filled, across the dense part and the pages.
+    filled: usize,
+}
+
+impl Heap {
+    pub fn new(kind: HeapKind) -> Heap {
+        Heap { kind, ..Heap::default() }
+    }
+
+    pub fn kind(&self) -> HeapKind {
+        self.kind
+    }
+
+    // Lays the heap out another way, keeping what's in it.
+    pub fn set_kind(&mut self, kind: HeapKind) {
+        if kind != self.kind {
+            let old = std::mem::replace(self, Heap::new(kind));
+            self.extend(old.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+
+    fn slot(&self, k: &Value) -> Option<&Slot> {
+        let &Value::Small(n) = k else { return None };
+        match self.kind {
+            HeapKind::Paged => self.pages.get(&n.div_euclid(PAGE)).map(|page| &page[n.rem_euclid(PAGE) as usize]),
+            HeapKind::Sparse => None,
+            _ => usize::try_from(n).ok().and_then(|i| self.dense.get(i)),
+        }
+    }
+
+    // The slot for an address about to be stored to, making room for it if the layout
+    // covers it.
+    fn slot_mut(&mut self, k: &Value) -> Option<&mut Slot> {
+        let &Value::Small(n) = k else { return None };
+        // Addresses below `reach` are kept densely, growing the dense part to at most `most`.
+        let (reach, most) = match self.kind {
+            HeapKind::Sparse => return None,
+            HeapKind::Paged => {
+                let page = self.pages.entry(n.div_euclid(PAGE)).or_insert_with(|| vec![None; PAGE as usize]);
+                return Some(&mut page[n.rem_euclid(PAGE) as usize]);
+            }
+            HeapKind::Dense => ((self.dense.len() + DENSE_GAP).min(DENSE_MAX), DENSE_MAX),
+            HeapKind::Auto => {
+                let reach = (2 * self.len()).clamp(AUTO_START, DENSE_MAX);
+                (reach, reach)
+            }
+        };
+        let i = usize::try_from(n).ok().filter(|&i| i < reach)?;
+        if i >= self.dense.len() {
+            self.grow((i + 1).max(2 * self.dense.len()).min(most));
+        }
+        Some(&mut self.dense[i])
+    }
+
+    // Extends the dense part, moving in anything kept sparsely that it now covers.
+    fn grow(&mut self, len: usize) {
+        let old = self.dense.len();
+        self.dense.resize(len, None);
+        let covered = |k: &Value| matches!(k, &Value::Small(n) if n >= old as i64 && n < len as i64);
+        let moved: Vec<_> = self.sparse.keys().filter(|k| covered(k)).cloned().collect();
+        for k in moved {
+            let v = self.sparse.remove(&k).expect("the key was just found");
+            let Value::Small(n) = k else { unreachable!() };
+            self.dense[n as usize] = Some((k, v));
+            self.filled += 1;
+        }
+    }
+
+    pub fn get(&self, k: &Value) -> Option<&Value> {
+        match self.slot(k) {
+            Some(slot) => slot.as_ref().map(|(_, v)| v),
+            None => self.sparse.get(k),
+        }
+    }
+
+    pub fn contains_key(&self, k: &Value) -> bool {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: Value, v: Value) -> Option<Value> {
+        match self.slot_mut(&k) {
+            Some(slot) => {
+                let old = slot.replace((k, v)).map(|(_, v)| v);
+                self.filled += old.is_none() as usize;
+                old
+            }
+            None => self.sparse.insert(k, v),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.filled + self.sparse.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Heap::new(self.kind);
+    }
+
+    // Every address and its value, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        let pages = self.pages.values().flatten();
+        let slots = self.dense.iter().chain(pages).flatten().map(|(k, v)| (k, v));
+        slots.chain(self.sparse.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl Extend<(Value, Value)> for Heap {
+    fn extend<I: IntoIterator<Item = (Value, Value)>>(&mut self, cells: I) {
+        for (k, v) in cells {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl Index<&Value> for Heap {
+    type Output = Value;
+
+    fn index(&self, k: &Value) -> &Value {
+        self.get(k).expect("nothing stored at the address")
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{Label, Value};
+use hashbrown::HashMap;
+
+// A native function is given its arguments in the order they were pushed, and returns the
+// values to push in their place or why it failed.
+pub type HostFn = Box<dyn FnMut(Vec<Value>) -> Result<Vec<Value>, String>>;
+
+// Native functions for a program to call as if they were subroutines at the labels they're
+// bound to, which the program needn't define. Calls to a bound label run the function in
+// place of any subroutine there.
+#[derive(Default)]
+pub struct Host {
+    pub(crate) labels: HashMap<Label, usize>,
+    pub(crate) functions: Vec<(Label, usize, HostFn)>,
+}
+
+impl Host {
+    pub fn new() -> Host {
+        Host::default()
+    }
+
+    // Binds a function taking `arity` values off the stack to a label, replacing any bound
+    // to it before.
+    pub fn bind(
+        &mut self,
+        label: Label,
+        arity: usize,
+        f: impl FnMut(Vec<Value>) -> Result<Vec<Value>, String> + 'static,
+    ) -> &mut Host {
+        match self.labels.get(&label) {
+            Some(&i) => self.functions[i] = (label, arity, Box::new(f)),
+            None => {
+                self.labels.insert(label.clone(), self.functions.len());
+                self.functions.push((label, arity, Box::new(f)));
+            }
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{Label, Num};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Insn {
+    None,
+
+    Push(Num),
+    Pop,
+    Dup,
+    Swap,
+    Copy(Num),
+    Slide(Num),
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+
+    Label(Label),
+    Call(Label),
+    Jump(Label),
+    Jz(Label),
+    Jn(Label),
+    Ret,
+
+    Store,
+    Load,
+    Ichr,
+    Inum,
+    Ochr,
+    Onum,
+    Exit,
+
+    // Extensions, parsed only when asked for, that write the stack or the heap to stderr and
+    // replace the top of the stack with a random number below it.
+    DumpStack,
+    DumpHeap,
+    Random,
+}
+
+impl Insn {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Insn::None => "none",
+            Insn::Push(_) => "push",
+            Insn::Pop => "pop",
+            Insn::Dup => "dup",
+            Insn::Swap => "swap",
+            Insn::Copy(_) => "copy",
+            Insn::Slide(_) => "slide",
+            Insn::Add => "add",
+            Insn::Sub => "sub",
+            Insn::Mul => "mul",
+            Insn::Div => "div",
+            Insn::Mod => "mod",
+            Insn::Label(_) => "label",
+            Insn::Call(_) => "call",
+            Insn::Jump(_) => "jump",
+            Insn::Jz(_) => "jz",
+            Insn::Jn(_) => "jn",
+            Insn::Ret => "ret",
+            Insn::Store => "store",
+            Insn::Load => "load",
+            Insn::Ichr => "ichr",
+            Insn::Inum => "inum",
+            Insn::Ochr => "ochr",
+            Insn::Onum => "onum",
+            Insn::Exit => "exit",
+            Insn::DumpStack => "dumpstack",
+            Insn::DumpHeap => "dumpheap",
+            Insn::Random => "random",
+        }
+    }
+
+    pub fn arg(&self) -> Option<&Num> {
+        match self {
+            Insn::Push(n) | Insn::Copy(n) | Insn::Slide(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> Option<&Label> {
+        match self {
+            Insn::Label(l) | Insn::Call(l) | Insn::Jump(l) | Insn::Jz(l) | Insn::Jn(l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(arg) = self.arg() {
+            write!(f, "{} {}", self.mnemonic(), arg)
+        } else if let Some(l) = self.label() {
+            write!(f, "{} {}", self.mnemonic(), l)
+        } else {
+            f.write_str(self.mnemonic())
+        }
+    }
+}
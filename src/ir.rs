@@ -0,0 +1,396 @@
+// A register form of a program, for analyses and backends that would rather not track the
+// stack themselves. Within each basic block, values are numbered registers that are each
+// assigned once, so `dup`, `swap`, `copy` and `slide` disappear into which register is
+// used where. Values only pass between blocks on the stack: a block reads what it finds
+// there with `arg`, and says at its end how many of those it consumed and what it leaves.
+//
+// Lowering folds constants, reuses a value already computed in the same block instead of
+// computing it again, and drops values nothing uses. The result behaves the same except
+// where the original would have run out of stack.
+
+use crate::{blocks, optimize::arith, AlbusError, Insn, Label, Num, Result};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::fmt;
+
+pub type Reg = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(Num),
+    // The value this many places from the top of the stack as the block was entered.
+    Arg(usize),
+    Binary(BinOp, Reg, Reg),
+    Load(Reg),
+    Random(Reg),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    Def(Reg, Expr),
+    // The address and then the value.
+    Store(Reg, Reg),
+    Ichr(Reg),
+    Inum(Reg),
+    Ochr(Reg),
+    Onum(Reg),
+    // The stack as it stands: what was there on entry less its top `pops` values, and then
+    // these.
+    DumpStack { pops: usize, values: Vec<Reg> },
+    DumpHeap,
+}
+
+// Where control goes at the end of a block, with a block number one past the last meaning
+// the end of the program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Terminator {
+    // Carries on into the next block.
+    Fall(usize),
+    Jump(usize),
+    // Goes to `then` if the register is zero, or negative for `jn`, and to `other` if not.
+    Jz { reg: Reg, then: usize, other: usize },
+    Jn { reg: Reg, then: usize, other: usize },
+    Call { target: usize, ret: usize },
+    Ret,
+    Exit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    // Where the block's instructions start in the program.
+    pub start: usize,
+    pub label: Option<Label>,
+    pub stmts: Vec<Stmt>,
+    // How many values the block takes off the stack it was entered with, and the registers
+    // it pushes in their place, bottom first.
+    pub pops: usize,
+    pub pushes: Vec<Reg>,
+    pub end: Terminator,
+}
+
+// The blocks of a program, with control starting in the first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub blocks: Vec<BasicBlock>,
+}
+
+// The state of the stack partway through a block, and what has been computed so far.
+struct Builder {
+    stmts: Vec<Stmt>,
+    stack: Vec<Reg>,
+    pops: usize,
+    regs: usize,
+    known: HashMap<Expr, Reg>,
+    consts: HashMap<Reg, Num>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        let (known, consts) = (HashMap::new(), HashMap::new());
+        Builder { stmts: Vec::new(), stack: Vec::new(), pops: 0, regs: 0, known, consts }
+    }
+
+    // The register holding `expr`, reusing one from earlier in the block where that's the
+    // same value.
+    fn def(&mut self, expr: Expr) -> Reg {
+        let expr = match expr {
+            Expr::Binary(op, a, b) => match (self.consts.get(&a), self.consts.get(&b)) {
+                (Some(a), Some(b)) => arith(&insn(op), a.clone(), b.clone()).map_or(expr, Expr::Const),
+                _ => expr,
+            },
+            _ => expr,
+        };
+        let pure = !matches!(expr, Expr::Random(_));
+        if let Some(&reg) = self.known.get(&expr).filter(|_| pure) {
+            return reg;
+        }
+
+        let reg = self.regs;
+        self.regs += 1;
+        if let Expr::Const(n) = &expr {
+            self.consts.insert(reg, n.clone());
+        }
+        if pure {
+            self.known.insert(expr.clone(), reg);
+        }
+        self.stmts.push(Stmt::Def(reg, expr));
+        reg
+    }
+
+    // The value `n` places from the top of the stack.
+    fn peek(&mut self, n: usize) -> Reg {
+        match self.stack.len().checked_sub(n + 1) {
+            Some(i) => self.stack[i],
+            None => self.def(Expr::Arg(self.pops + n - self.stack.len())),
+        }
+    }
+
+    fn pop(&mut self) -> Reg {
+        let reg = self.peek(0);
+        self.drop();
+        reg
+    }
+
+    fn drop(&mut self) {
+        if self.stack.pop().is_none() {
+            self.pops += 1;
+        }
+    }
+
+    // A store or input may change what any address holds.
+    fn clobber(&mut self) {
+        self.known.retain(|expr, _| !matches!(expr, Expr::Load(_)));
+    }
+
+    fn effect(&mut self, stmt: Stmt) {
+        if let Stmt::Store(..) | Stmt::Ichr(_) | Stmt::Inum(_) = stmt {
+            self.clobber();
+        }
+        self.stmts.push(stmt);
+    }
+
+    fn finish(mut self, start: usize, label: Option<Label>, end: Terminator) -> BasicBlock {
+        self.stmts = live(self.stmts, &self.stack, &end);
+        BasicBlock { start, label, stmts: self.stmts, pops: self.pops, pushes: self.stack, end }
+    }
+}
+
+fn insn(op: BinOp) -> Insn {
+    match op {
+        BinOp::Add => Insn::Add,
+        BinOp::Sub => Insn::Sub,
+        BinOp::Mul => Insn::Mul,
+        BinOp::Div => Insn::Div,
+        BinOp::Mod => Insn::Mod,
+    }
+}
+
+// Drops definitions nothing uses, keeping those that can fail or that advance the random
+// numbers.
+fn live(stmts: Vec<Stmt>, pushes: &[Reg], end: &Terminator) -> Vec<Stmt> {
+    let mut used: Vec<Reg> = pushes.to_vec();
+    if let Terminator::Jz { reg, .. } | Terminator::Jn { reg, .. } = end {
+        used.push(*reg);
+    }
+
+    let mut kept = Vec::with_capacity(stmts.len());
+    for stmt in stmts.into_iter().rev() {
+        let operands = match &stmt {
+            Stmt::Def(reg, expr) => {
+                let removable = matches!(
+                    expr,
+                    Expr::Const(_) | Expr::Arg(_) | Expr::Binary(BinOp::Add | BinOp::Sub | BinOp::Mul, ..)
+                );
+                if removable && !used.contains(reg) {
+                    continue;
+                }
+                match expr {
+                    Expr::Binary(_, a, b) => vec![*a, *b],
+                    Expr::Load(r) | Expr::Random(r) => vec![*r],
+                    Expr::Const(_) | Expr::Arg(_) => vec![],
+                }
+            }
+            Stmt::Store(k, v) => vec![*k, *v],
+            Stmt::Ichr(r) | Stmt::Inum(r) | Stmt::Ochr(r) | Stmt::Onum(r) => vec![*r],
+            Stmt::DumpStack { values, .. } => values.clone(),
+            Stmt::DumpHeap => vec![],
+        };
+        used.extend(operands);
+        kept.push(stmt);
+    }
+
+    kept.reverse();
+    kept
+}
+
+// Lowers a program to registers, failing on jumps to labels it doesn't define and on
+// `copy` and `slide` arguments that could never be in range.
+pub fn lower(insns: &[Insn], labels: &HashMap<Label, usize>) -> Result<Program> {
+    let blocks = blocks(insns);
+    let starts: HashMap<usize, usize> = blocks.iter().enumerate().map(|(i, b)| (b.range.start, i)).collect();
+    let mut out = Vec::with_capacity(blocks.len());
+
+    for (i, block) in blocks.iter().enumerate() {
+        let mut b = Builder::new();
+        let mut label = None;
+        let mut end = Terminator::Fall(i + 1);
+
+        for ip in block.range.clone() {
+            let target = |l: &Label| match labels.get(l) {
+                Some(t) => Ok(starts[t]),
+                None => Err(AlbusError::UndefinedLabel { ip, label: l.clone() }),
+            };
+            let reach = |n: &Num| n.to_usize().ok_or_else(|| AlbusError::BadArgument { ip, arg: n.clone() });
+
+            match &insns[ip] {
+                Insn::None => {}
+                Insn::Label(l) => label = Some(l.clone()),
+                Insn::Push(n) => {
+                    let reg = b.def(Expr::Const(n.clone()));
+                    b.stack.push(reg);
+                }
+                Insn::Pop => b.drop(),
+                Insn::Dup => {
+                    let reg = b.peek(0);
+                    b.stack.push(reg);
+                }
+                Insn::Swap => {
+                    let (top, under) = (b.pop(), b.pop());
+                    b.stack.extend([top, under]);
+                }
+                Insn::Copy(n) => {
+                    let reg = b.peek(reach(n)?);
+                    b.stack.push(reg);
+                }
+                Insn::Slide(n) => {
+                    let top = b.pop();
+                    for _ in 0..reach(n)? {
+                        b.drop();
+                    }
+                    b.stack.push(top);
+                }
+                Insn::Add | Insn::Sub | Insn::Mul | Insn::Div | Insn::Mod => {
+                    let op = match &insns[ip] {
+                        Insn::Add => BinOp::Add,
+                        Insn::Sub => BinOp::Sub,
+                        Insn::Mul => BinOp::Mul,
+                        Insn::Div => BinOp::Div,
+                        _ => BinOp::Mod,
+                    };
+                    let (r, l) = (b.pop(), b.pop());
+                    let reg = b.def(Expr::Binary(op, l, r));
+                    b.stack.push(reg);
+                }
+                Insn::Store => {
+                    let (v, k) = (b.pop(), b.pop());
+                    b.effect(Stmt::Store(k, v));
+                }
+                Insn::Load => {
+                    let k = b.pop();
+                    let reg = b.def(Expr::Load(k));
+                    b.stack.push(reg);
+                }
+                Insn::Random => {
+                    let n = b.pop();
+                    let reg = b.def(Expr::Random(n));
+                    b.stack.push(reg);
+                }
+                Insn::Ichr => {
+                    let k = b.pop();
+                    b.effect(Stmt::Ichr(k));
+                }
+                Insn::Inum => {
+                    let k = b.pop();
+                    b.effect(Stmt::Inum(k));
+                }
+                Insn::Ochr => {
+                    let v = b.pop();
+                    b.effect(Stmt::Ochr(v));
+                }
+                Insn::Onum => {
+                    let v = b.pop();
+                    b.effect(Stmt::Onum(v));
+                }
+                Insn::DumpStack => {
+                    let stmt = Stmt::DumpStack { pops: b.pops, values: b.stack.clone() };
+                    b.effect(stmt);
+                }
+                Insn::DumpHeap => b.effect(Stmt::DumpHeap),
+                Insn::Jump(l) => end = Terminator::Jump(target(l)?),
+                Insn::Jz(l) | Insn::Jn(l) => {
+                    let (reg, then) = (b.pop(), target(l)?);
+                    end = match &insns[ip] {
+                        Insn::Jz(_) => Terminator::Jz { reg, then, other: i + 1 },
+                        _ => Terminator::Jn { reg, then, other: i + 1 },
+                    };
+                }
+                Insn::Call(l) => end = Terminator::Call { target: target(l)?, ret: i + 1 },
+                Insn::Ret => end = Terminator::Ret,
+                Insn::Exit => end = Terminator::Exit,
+            }
+        }
+
+        out.push(b.finish(block.range.start, label, end));
+    }
+
+    Ok(Program { blocks: out })
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(insn(*self).mnemonic())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(n) => write!(f, "{}", n),
+            Expr::Arg(n) => write!(f, "arg {}", n),
+            Expr::Binary(op, a, b) => write!(f, "{} r{} r{}", op, a, b),
+            Expr::Load(r) => write!(f, "load r{}", r),
+            Expr::Random(r) => write!(f, "random r{}", r),
+        }
+    }
+}
+
+fn regs(regs: &[Reg]) -> String {
+    regs.iter().map(|r| format!(" r{}", r)).collect()
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Def(reg, expr) => write!(f, "r{} = {}", reg, expr),
+            Stmt::Store(k, v) => write!(f, "store r{} r{}", k, v),
+            Stmt::Ichr(r) => write!(f, "ichr r{}", r),
+            Stmt::Inum(r) => write!(f, "inum r{}", r),
+            Stmt::Ochr(r) => write!(f, "ochr r{}", r),
+            Stmt::Onum(r) => write!(f, "onum r{}", r),
+            Stmt::DumpStack { pops, values } => write!(f, "dumpstack -{}{}", pops, regs(values)),
+            Stmt::DumpHeap => f.write_str("dumpheap"),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Fall(b) => write!(f, "fall b{}", b),
+            Terminator::Jump(b) => write!(f, "jump b{}", b),
+            Terminator::Jz { reg, then, other } => write!(f, "jz r{} b{} else b{}", reg, then, other),
+            Terminator::Jn { reg, then, other } => write!(f, "jn r{} b{} else b{}", reg, then, other),
+            Terminator::Call { target, ret } => write!(f, "call b{} then b{}", target, ret),
+            Terminator::Ret => f.write_str("ret"),
+            Terminator::Exit => f.write_str("exit"),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            match &block.label {
+                Some(l) => writeln!(f, "b{} ({}, label {}):", i, block.start, l)?,
+                None => writeln!(f, "b{} ({}):", i, block.start)?,
+            }
+            for stmt in &block.stmts {
+                writeln!(f, "    {}", stmt)?;
+            }
+            if block.pops > 0 || !block.pushes.is_empty() {
+                writeln!(f, "    stack -{}{}", block.pops, regs(&block.pushes))?;
+            }
+            writeln!(f, "    {}", block.end)?;
+        }
+        Ok(())
+    }
+}
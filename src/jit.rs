@@ -0,0 +1,646 @@
+use crate::{block::blocks, vm::read_line, AlbusError, Charset, Eof, Insn, Label, Num, Result, Value, Vm};
+use cranelift_codegen::{
+    ir::{self, condcodes::IntCC, types::I64, AbiParam, BlockArg, InstBuilder, MemFlagsData},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::{
+    convert::TryFrom,
+    io::{Read, Write},
+};
+
+// Native code keeps values in i64 stack slots. Anything it can't handle exactly (overflow,
+// big constants, errors the interpreter reports) exits with BAIL before the instruction
+// runs, and the interpreter picks up from that state.
+const HALT: i64 = 0;
+const BAIL: i64 = 1;
+const ERROR: i64 = 2;
+
+const STACK_CAP: i64 = 1 << 20;
+const CALL_CAP: i64 = 1 << 16;
+
+// Slots of the state array shared with compiled code.
+const SP: i32 = 0;
+const CSP: i32 = 8;
+const STEPS: i32 = 16;
+const IP: i32 = 24;
+
+struct Host<'a> {
+    heap: HashMap<i64, i64>,
+    error: Option<AlbusError>,
+    pending: Option<(i64, Num)>,
+    eof: Eof,
+    charset: Charset,
+    // Set when input ran out and the program should halt rather than fail.
+    halted: bool,
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+}
+
+// Decides what to store when input runs out, or records why the program stops instead.
+fn host_eof(host: &mut Host, ip: i64) -> Option<i64> {
+    match host.eof {
+        Eof::Zero => Some(0),
+        Eof::MinusOne => Some(-1),
+        Eof::Error => {
+            host.error = Some(AlbusError::UnexpectedEof { ip: ip as usize });
+            None
+        }
+        Eof::Halt => {
+            host.halted = true;
+            None
+        }
+    }
+}
+
+extern "C" fn host_store(host: *mut Host, k: i64, v: i64) {
+    let host = unsafe { &mut *host };
+    host.heap.insert(k, v);
+}
+
+extern "C" fn host_load(host: *mut Host, k: i64, dst: *mut i64) -> i64 {
+    let host = unsafe { &mut *host };
+    match host.heap.get(&k) {
+        Some(&v) => {
+            unsafe { *dst = v };
+            1
+        }
+        None => 0,
+    }
+}
+
+extern "C" fn host_ichr(host: *mut Host, k: i64, ip: i64) -> i64 {
+    let host = unsafe { &mut *host };
+    host.output.flush().ok();
+    let v = match host.charset.read(&mut host.input) {
+        Ok(None) => host_eof(host, ip),
+        Ok(Some(c)) => Some(c.into()),
+        Err(error) => {
+            host.error = Some(AlbusError::IoError { ip: ip as usize, error });
+            None
+        }
+    };
+
+    match v {
+        Some(v) => {
+            host.heap.insert(k, v);
+            HALT
+        }
+        None => ERROR,
+    }
+}
+
+extern "C" fn host_inum(host: *mut Host, k: i64, ip: i64) -> i64 {
+    let host = unsafe { &mut *host };
+    host.output.flush().ok();
+    let n = read_line(&mut host.input);
+
+    if n.is_empty() {
+        return match host_eof(host, ip) {
+            Some(v) => {
+                host.heap.insert(k, v);
+                HALT
+            }
+            None => ERROR,
+        };
+    }
+    match n.trim_end().parse::<Num>() {
+        Ok(v) => match v.to_i64() {
+            Some(v) => {
+                host.heap.insert(k, v);
+                HALT
+            }
+            None => {
+                host.pending = Some((k, v));
+                BAIL
+            }
+        },
+        Err(_) => {
+            host.error = Some(AlbusError::BadInput { ip: ip as usize, input: n });
+            ERROR
+        }
+    }
+}
+
+// Output returns nonzero to leave it to the interpreter to report a bad character or a
+// failed write.
+extern "C" fn host_ochr(host: *mut Host, v: i64) -> i64 {
+    let host = unsafe { &mut *host };
+    match usize::try_from(v).ok().and_then(|n| host.charset.write(&mut host.output, n)) {
+        Some(result) => result.is_err() as i64,
+        None => 1,
+    }
+}
+
+extern "C" fn host_onum(host: *mut Host, v: i64) -> i64 {
+    let host = unsafe { &mut *host };
+    write!(host.output, "{}", v).is_err() as i64
+}
+
+struct Imports {
+    store: FuncId,
+    load: FuncId,
+    ichr: FuncId,
+    inum: FuncId,
+    ochr: FuncId,
+    onum: FuncId,
+}
+
+fn declare(module: &mut JITModule, name: &str, params: usize, returns: bool) -> FuncId {
+    let mut sig = module.make_signature();
+    for _ in 0..params {
+        sig.params.push(AbiParam::new(I64));
+    }
+    if returns {
+        sig.returns.push(AbiParam::new(I64));
+    }
+    module.declare_function(name, Linkage::Import, &sig).unwrap()
+}
+
+struct Codegen<'a, 'b> {
+    b: FunctionBuilder<'a>,
+    module: &'b mut JITModule,
+    exit: ir::Block,
+    params: [ir::Value; 4],
+    sp: Variable,
+}
+
+impl<'a, 'b> Codegen<'a, 'b> {
+    fn konst(&mut self, n: i64) -> ir::Value {
+        self.b.ins().iconst(I64, n)
+    }
+
+    fn exit(&mut self, status: i64, ip: ir::Value) {
+        let status = self.konst(status);
+        self.b.ins().jump(self.exit, &[BlockArg::Value(status), BlockArg::Value(ip)]);
+    }
+
+    // Leaves compiled code for the interpreter when `cond` holds.
+    fn guard(&mut self, cond: ir::Value, ip: usize) {
+        let cont = self.b.create_block();
+        let (status, ip) = (self.konst(BAIL), self.konst(ip as i64));
+        self.b.ins().brif(cond, self.exit, &[BlockArg::Value(status), BlockArg::Value(ip)], cont, &[]);
+        self.b.switch_to_block(cont);
+    }
+
+    fn bail(&mut self, ip: usize) {
+        let ip = self.konst(ip as i64);
+        self.exit(BAIL, ip);
+        let dead = self.b.create_block();
+        self.b.switch_to_block(dead);
+    }
+
+    fn need(&mut self, n: i64, ip: usize) {
+        let sp = self.b.use_var(self.sp);
+        let short = self.b.ins().icmp_imm_s(IntCC::SignedLessThan, sp, n);
+        self.guard(short, ip);
+    }
+
+    fn room(&mut self, ip: usize) {
+        let sp = self.b.use_var(self.sp);
+        let full = self.b.ins().icmp_imm_s(IntCC::SignedGreaterThanOrEqual, sp, STACK_CAP);
+        self.guard(full, ip);
+    }
+
+    // Address of the value `depth` below the top of the stack.
+    fn slot(&mut self, depth: i64) -> ir::Value {
+        let sp = self.b.use_var(self.sp);
+        let i = self.b.ins().iadd_imm_s(sp, -1 - depth);
+        let off = self.b.ins().ishl_imm_u(i, 3);
+        self.b.ins().iadd(self.params[1], off)
+    }
+
+    fn peek(&mut self, depth: i64) -> ir::Value {
+        let addr = self.slot(depth);
+        self.b.ins().load(I64, MemFlagsData::trusted(), addr, 0)
+    }
+
+    fn poke(&mut self, depth: i64, v: ir::Value) {
+        let addr = self.slot(depth);
+        self.b.ins().store(MemFlagsData::trusted(), v, addr, 0);
+    }
+
+    fn bump(&mut self, var: Variable, by: i64) {
+        let v = self.b.use_var(var);
+        let v = self.b.ins().iadd_imm_s(v, by);
+        self.b.def_var(var, v);
+    }
+
+    fn push(&mut self, v: ir::Value) {
+        self.bump(self.sp, 1);
+        self.poke(0, v);
+    }
+
+    fn call(&mut self, f: FuncId, args: &[ir::Value]) -> Option<ir::Value> {
+        let f = self.module.declare_func_in_func(f, self.b.func);
+        let mut full = vec![self.params[0]];
+        full.extend_from_slice(args);
+        let call = self.b.ins().call(f, &full);
+        self.b.inst_results(call).first().copied()
+    }
+}
+
+type Entry = extern "C" fn(*mut Host, *mut i64, *mut i64, *mut i64) -> i64;
+
+fn compile(module: &mut JITModule, insns: &[Insn], labels: &HashMap<Label, usize>, trunc_div: bool) -> FuncId {
+    let imports = Imports {
+        store: declare(module, "albus_store", 3, false),
+        load: declare(module, "albus_load", 3, true),
+        ichr: declare(module, "albus_ichr", 3, true),
+        inum: declare(module, "albus_inum", 3, true),
+        ochr: declare(module, "albus_ochr", 2, true),
+        onum: declare(module, "albus_onum", 2, true),
+    };
+
+    let mut ctx = module.make_context();
+    for _ in 0..4 {
+        ctx.func.signature.params.push(AbiParam::new(I64));
+    }
+    ctx.func.signature.returns.push(AbiParam::new(I64));
+    let id = module.declare_function("albus_main", Linkage::Export, &ctx.func.signature).unwrap();
+
+    let mut fctx = FunctionBuilderContext::new();
+    let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    let exit = b.create_block();
+    b.append_block_param(exit, I64);
+    b.append_block_param(exit, I64);
+
+    let bbs = blocks(insns);
+    let cl: Vec<_> = bbs.iter().map(|_| b.create_block()).collect();
+    let ids: HashMap<usize, usize> = bbs.iter().enumerate().map(|(id, bb)| (bb.range.start, id)).collect();
+
+    b.switch_to_block(entry);
+    let p = b.block_params(entry);
+    let params = [p[0], p[1], p[2], p[3]];
+    let (sp, csp, steps) = (b.declare_var(I64), b.declare_var(I64), b.declare_var(I64));
+    let mut g = Codegen { b, module, exit, params, sp };
+
+    let zero = g.konst(0);
+    g.b.def_var(sp, zero);
+    g.b.def_var(csp, zero);
+    g.b.def_var(steps, zero);
+    match cl.first() {
+        Some(&first) => {
+            g.b.ins().jump(first, &[]);
+        }
+        None => g.exit(HALT, zero),
+    }
+
+    // Vm::new has already checked that every label is defined.
+    let target = |l: &Label| cl[ids[&labels[l]]];
+    let mut sites = Vec::new();
+    for (k, bb) in bbs.iter().enumerate() {
+        g.b.switch_to_block(cl[k]);
+        let mut open = true;
+
+        for ip in bb.range.clone() {
+            let next = cl.get(k + 1).copied();
+
+            match &insns[ip] {
+                Insn::None | Insn::Label(_) => continue,
+                Insn::Push(n) => match n.to_i64() {
+                    Some(n) => {
+                        g.room(ip);
+                        let v = g.konst(n);
+                        g.push(v);
+                    }
+                    None => g.bail(ip),
+                },
+                Insn::Pop => {
+                    g.need(1, ip);
+                    g.bump(sp, -1);
+                }
+                Insn::Dup | Insn::Copy(_) => {
+                    let n = match &insns[ip] {
+                        Insn::Copy(n) => n.to_u32(),
+                        _ => Some(0),
+                    };
+                    match n {
+                        Some(n) => {
+                            g.need(n as i64 + 1, ip);
+                            g.room(ip);
+                            let v = g.peek(n as i64);
+                            g.push(v);
+                        }
+                        None => g.bail(ip),
+                    }
+                }
+                Insn::Slide(n) => match n.to_u32() {
+                    Some(n) => {
+                        g.need(n as i64 + 1, ip);
+                        let v = g.peek(0);
+                        g.bump(sp, -(n as i64));
+                        g.poke(0, v);
+                    }
+                    None => g.bail(ip),
+                },
+                Insn::Swap => {
+                    g.need(2, ip);
+                    let (a, c) = (g.peek(0), g.peek(1));
+                    g.poke(0, c);
+                    g.poke(1, a);
+                }
+                Insn::Add | Insn::Sub | Insn::Mul => {
+                    g.need(2, ip);
+                    let (r, l) = (g.peek(0), g.peek(1));
+                    let (v, overflow) = match &insns[ip] {
+                        Insn::Add => g.b.ins().sadd_overflow(l, r),
+                        Insn::Sub => g.b.ins().ssub_overflow(l, r),
+                        _ => g.b.ins().smul_overflow(l, r),
+                    };
+                    g.guard(overflow, ip);
+                    g.poke(1, v);
+                    g.bump(sp, -1);
+                }
+                Insn::Div | Insn::Mod => {
+                    g.need(2, ip);
+                    let (r, l) = (g.peek(0), g.peek(1));
+                    let zero = g.b.ins().icmp_imm_s(IntCC::Equal, r, 0);
+                    g.guard(zero, ip);
+                    let min = g.b.ins().icmp_imm_s(IntCC::Equal, l, i64::MIN);
+                    let neg = g.b.ins().icmp_imm_s(IntCC::Equal, r, -1);
+                    let overflow = g.b.ins().band(min, neg);
+                    g.guard(overflow, ip);
+                    let (q, m) = (g.b.ins().sdiv(l, r), g.b.ins().srem(l, r));
+                    let v = if trunc_div {
+                        if insns[ip] == Insn::Div {
+                            q
+                        } else {
+                            m
+                        }
+                    } else {
+                        // Floored results differ when the remainder is nonzero and its sign
+                        // differs from the divisor's.
+                        let inexact = g.b.ins().icmp_imm_s(IntCC::NotEqual, m, 0);
+                        let sign = g.b.ins().bxor(m, r);
+                        let differs = g.b.ins().icmp_imm_s(IntCC::SignedLessThan, sign, 0);
+                        let adjust = g.b.ins().band(inexact, differs);
+                        if insns[ip] == Insn::Div {
+                            let adjust = g.b.ins().uextend(I64, adjust);
+                            g.b.ins().isub(q, adjust)
+                        } else {
+                            let zero = g.konst(0);
+                            let add = g.b.ins().select(adjust, r, zero);
+                            g.b.ins().iadd(m, add)
+                        }
+                    };
+                    g.poke(1, v);
+                    g.bump(sp, -1);
+                }
+                Insn::Store => {
+                    g.need(2, ip);
+                    let (v, key) = (g.peek(0), g.peek(1));
+                    g.call(imports.store, &[key, v]);
+                    g.bump(sp, -2);
+                }
+                Insn::Load => {
+                    g.need(1, ip);
+                    let (key, addr) = (g.peek(0), g.slot(0));
+                    let found = g.call(imports.load, &[key, addr]).unwrap();
+                    let missing = g.b.ins().icmp_imm_s(IntCC::Equal, found, 0);
+                    g.guard(missing, ip);
+                }
+                Insn::Ichr => {
+                    g.need(1, ip);
+                    let key = g.peek(0);
+                    g.bump(sp, -1);
+                    g.bump(steps, 1);
+                    let at = g.konst(ip as i64);
+                    let status = g.call(imports.ichr, &[key, at]).unwrap();
+                    let cont = g.b.create_block();
+                    g.b.ins().brif(status, exit, &[BlockArg::Value(status), BlockArg::Value(at)], cont, &[]);
+                    g.b.switch_to_block(cont);
+                    continue;
+                }
+                Insn::Inum => {
+                    g.need(1, ip);
+                    let key = g.peek(0);
+                    g.bump(sp, -1);
+                    g.bump(steps, 1);
+                    let at = g.konst(ip as i64);
+                    let status = g.call(imports.inum, &[key, at]).unwrap();
+                    let (done, cont) = (g.b.create_block(), g.b.create_block());
+                    g.b.ins().brif(status, done, &[], cont, &[]);
+                    g.b.switch_to_block(done);
+                    let (failed, after) = (g.konst(ERROR), g.konst(ip as i64 + 1));
+                    let resume = g.b.ins().icmp_imm_s(IntCC::Equal, status, BAIL);
+                    let at = g.b.ins().select(resume, after, at);
+                    let status = g.b.ins().select(resume, status, failed);
+                    g.b.ins().jump(exit, &[BlockArg::Value(status), BlockArg::Value(at)]);
+                    g.b.switch_to_block(cont);
+                    continue;
+                }
+                Insn::Ochr => {
+                    g.need(1, ip);
+                    let v = g.peek(0);
+                    let bad = g.call(imports.ochr, &[v]).unwrap();
+                    g.guard(bad, ip);
+                    g.bump(sp, -1);
+                }
+                Insn::Onum => {
+                    g.need(1, ip);
+                    let v = g.peek(0);
+                    let failed = g.call(imports.onum, &[v]).unwrap();
+                    g.guard(failed, ip);
+                    g.bump(sp, -1);
+                }
+                Insn::Call(l) => {
+                    let depth = g.b.use_var(csp);
+                    let full = g.b.ins().icmp_imm_s(IntCC::SignedGreaterThanOrEqual, depth, CALL_CAP);
+                    g.guard(full, ip);
+                    let depth = g.b.use_var(csp);
+                    let off = g.b.ins().ishl_imm_u(depth, 3);
+                    let addr = g.b.ins().iadd(params[2], off);
+                    let at = g.konst(ip as i64);
+                    g.b.ins().store(MemFlagsData::trusted(), at, addr, 0);
+                    g.bump(csp, 1);
+                    g.bump(steps, 1);
+                    g.b.ins().jump(target(l), &[]);
+                    if let Some(next) = next {
+                        sites.push((ip, next));
+                    }
+                    open = false;
+                }
+                Insn::Jump(l) => {
+                    g.bump(steps, 1);
+                    g.b.ins().jump(target(l), &[]);
+                    open = false;
+                }
+                Insn::Jz(l) | Insn::Jn(l) => {
+                    g.need(1, ip);
+                    let v = g.peek(0);
+                    let cond = match &insns[ip] {
+                        Insn::Jz(_) => g.b.ins().icmp_imm_s(IntCC::Equal, v, 0),
+                        _ => g.b.ins().icmp_imm_s(IntCC::SignedLessThan, v, 0),
+                    };
+                    g.bump(sp, -1);
+                    g.bump(steps, 1);
+                    let cont = g.b.create_block();
+                    g.b.ins().brif(cond, target(l), &[], cont, &[]);
+                    g.b.switch_to_block(cont);
+                }
+                Insn::Ret => {
+                    let depth = g.b.use_var(csp);
+                    let empty = g.b.ins().icmp_imm_s(IntCC::Equal, depth, 0);
+                    g.guard(empty, ip);
+                    g.bump(csp, -1);
+                    g.bump(steps, 1);
+                    let depth = g.b.use_var(csp);
+                    let off = g.b.ins().ishl_imm_u(depth, 3);
+                    let addr = g.b.ins().iadd(params[2], off);
+                    let site = g.b.ins().load(I64, MemFlagsData::trusted(), addr, 0);
+                    let ret = g.b.create_block();
+                    sites.push((usize::MAX, ret));
+                    g.b.append_block_param(ret, I64);
+                    g.b.ins().jump(ret, &[BlockArg::Value(site)]);
+                    open = false;
+                }
+                // Extensions are rare enough that the interpreter can have the rest of the run.
+                Insn::DumpStack | Insn::DumpHeap | Insn::Random => g.bail(ip),
+                Insn::Exit => {
+                    g.bump(steps, 1);
+                    let at = g.konst(ip as i64);
+                    g.exit(HALT, at);
+                    open = false;
+                }
+            }
+            if open && !matches!(insns[ip], Insn::Jz(_) | Insn::Jn(_)) {
+                g.bump(steps, 1);
+            }
+        }
+
+        if open {
+            match cl.get(k + 1) {
+                Some(&next) => {
+                    g.b.ins().jump(next, &[]);
+                }
+                None => {
+                    let end = g.konst(insns.len() as i64);
+                    g.exit(HALT, end);
+                }
+            }
+        }
+    }
+
+    // Every return jumps here to dispatch on the recorded call site.
+    let (calls, rets): (Vec<_>, Vec<_>) = sites.into_iter().partition(|&(ip, _)| ip != usize::MAX);
+    for (_, ret) in rets {
+        g.b.switch_to_block(ret);
+        let site = g.b.block_params(ret)[0];
+        let fallback = g.b.create_block();
+        let mut switch = Switch::new();
+        for &(ip, next) in &calls {
+            switch.set_entry(ip as u128, next);
+        }
+        switch.emit(&mut g.b, site, fallback);
+        g.b.switch_to_block(fallback);
+        let after = g.b.ins().iadd_imm_s(site, 1);
+        g.exit(BAIL, after);
+    }
+
+    g.b.switch_to_block(exit);
+    let (status, ip) = (g.b.block_params(exit)[0], g.b.block_params(exit)[1]);
+    let state = params[3];
+    for (var, off) in [(sp, SP), (csp, CSP), (steps, STEPS)].iter() {
+        let v = g.b.use_var(*var);
+        g.b.ins().store(MemFlagsData::trusted(), v, state, *off);
+    }
+    g.b.ins().store(MemFlagsData::trusted(), ip, state, IP);
+    g.b.ins().return_(&[status]);
+
+    g.b.seal_all_blocks();
+    let config = g.module.target_config();
+    g.b.finalize(config);
+
+    module.define_function(id, &mut ctx).unwrap();
+    module.clear_context(&mut ctx);
+    id
+}
+
+// Runs a newly created Vm's program natively, starting from whatever is in its heap and
+// following its `eof`, `trunc_div` and `charset` settings and using its input and output,
+// and returns the Vm in the state the program finished in. The interpreter finishes the run
+// if compiled code bails out.
+pub fn run<R: Read, W: Write>(mut vm: Vm<R, W>) -> Result<Vm<R, W>> {
+    // Native code keeps the heap as i64s too, so one holding bignums to begin with is left
+    // to the interpreter, as are host functions, tail calls and runs that don't start from
+    // the top with nothing on the stack.
+    let small = |v: &Value| match v {
+        Value::Small(n) => Some(*n),
+        Value::Big(_) => None,
+    };
+    let heap: Option<HashMap<_, _>> = vm.heap.iter().map(|(k, v)| Some((small(k)?, small(v)?))).collect();
+    let fresh = vm.ip == 0 && vm.stack.is_empty() && vm.calls.is_empty();
+    let heap = match heap {
+        Some(heap) if !vm.hosted() && !vm.tail_calls && vm.int_width.is_none() && fresh => heap,
+        _ => {
+            vm.run()?;
+            return Ok(vm);
+        }
+    };
+    let mut flags = settings::builder();
+    flags.set("use_colocated_libcalls", "false").unwrap();
+    flags.set("is_pic", "false").unwrap();
+    flags.set("opt_level", "speed").unwrap();
+    let isa = cranelift_native::builder()
+        .expect("host machine is not supported by the JIT")
+        .finish(settings::Flags::new(flags))
+        .unwrap();
+
+    let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    builder.symbol("albus_store", host_store as *const u8);
+    builder.symbol("albus_load", host_load as *const u8);
+    builder.symbol("albus_ichr", host_ichr as *const u8);
+    builder.symbol("albus_inum", host_inum as *const u8);
+    builder.symbol("albus_ochr", host_ochr as *const u8);
+    builder.symbol("albus_onum", host_onum as *const u8);
+    let mut module = JITModule::new(builder);
+
+    let id = compile(&mut module, vm.insns(), vm.labels(), vm.trunc_div);
+    module.finalize_definitions().unwrap();
+    let entry: Entry = unsafe { std::mem::transmute(module.get_finalized_function(id)) };
+
+    let mut host = Host {
+        heap,
+        error: None,
+        pending: None,
+        eof: vm.eof,
+        charset: vm.charset,
+        halted: false,
+        input: &mut vm.input,
+        output: &mut vm.output,
+    };
+    let mut stack = vec![0i64; STACK_CAP as usize];
+    let mut calls = vec![0i64; CALL_CAP as usize];
+    let mut state = [0i64; 4];
+    let status = entry(&mut host, stack.as_mut_ptr(), calls.as_mut_ptr(), state.as_mut_ptr());
+
+    if status == ERROR && !host.halted {
+        return Err(host.error.take().unwrap());
+    }
+
+    let [sp, csp, steps, ip] = state;
+    vm.stack = stack[..sp as usize].iter().map(|&v| Value::Small(v)).collect();
+    vm.calls = calls[..csp as usize].iter().map(|&ip| ip as usize).collect();
+    vm.heap.clear();
+    vm.heap.extend(host.heap.into_iter().map(|(k, v)| (Value::Small(k), Value::Small(v))));
+    if let Some((k, v)) = host.pending {
+        vm.heap.insert(Value::Small(k), Value::from(v));
+    }
+    vm.ip = ip as usize;
+    vm.steps = steps as u64;
+
+    if status == HALT || host.halted {
+        vm.output.flush().ok();
+        vm.halted = true;
+    } else {
+        vm.run()?;
+    }
+    Ok(vm)
+}
@@ -0,0 +1,178 @@
+use crate::{Insn, Num};
+use hashbrown::HashMap;
+use num_traits::{Signed, Zero};
+use std::{fmt, str::FromStr};
+
+// A label is an arbitrary string of bits, one byte per bit: 0 for a space and 1 for a tab.
+// Leading zeroes are significant, so `0` and `00` are different labels.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(Vec<u8>);
+
+impl Label {
+    pub fn new(bits: Vec<u8>) -> Label {
+        debug_assert!(bits.iter().all(|&b| b < 2));
+        Label(bits)
+    }
+
+    pub fn bits(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// Labels that older versions read as numbers become a sign bit followed by the magnitude
+// with no leading zeroes, so every label that collapsed to the same number stays the same.
+impl From<&Num> for Label {
+    fn from(n: &Num) -> Label {
+        let mut bits = vec![n.is_negative() as u8];
+        if !n.is_zero() {
+            bits.extend(n.magnitude().to_str_radix(2).bytes().map(|b| b - b'0'));
+        }
+        Label(bits)
+    }
+}
+
+impl FromStr for Label {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Label, ()> {
+        s.bytes()
+            .map(|b| match b {
+                b'0' | b'1' => Ok(b - b'0'),
+                _ => Err(()),
+            })
+            .collect::<Result<_, _>>()
+            .map(Label)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("(empty)");
+        }
+        for &bit in &self.0 {
+            f.write_str(if bit == 1 { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+}
+
+// What the assembler knows of a program that its Whitespace doesn't say: the names labels
+// were given, so that they can be shown as written rather than as bits, and which line of
+// the assembly each instruction came from. A `.wsmap` file beside a program holds them, a
+// label's bits and its name on each line, then the source file and the lines in order.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    names: HashMap<Label, String>,
+    labels: HashMap<String, Label>,
+    source: Option<String>,
+    lines: Vec<usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn insert(&mut self, label: Label, name: String) {
+        if let Some(old) = self.names.insert(label.clone(), name.clone()) {
+            self.labels.remove(&old);
+        }
+        self.labels.insert(name, label);
+    }
+
+    pub fn name(&self, l: &Label) -> Option<&str> {
+        self.names.get(l).map(String::as_str)
+    }
+
+    pub fn label(&self, name: &str) -> Option<&Label> {
+        self.labels.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.lines.is_empty()
+    }
+
+    // Records the line each instruction came from, and the file if it was one.
+    pub fn set_lines(&mut self, source: Option<String>, lines: Vec<usize>) {
+        self.source = source;
+        self.lines = lines;
+    }
+
+    // Forgets the lines, for once the instructions no longer line up with them.
+    pub fn clear_lines(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn line(&self, ip: usize) -> Option<usize> {
+        self.lines.get(ip).copied()
+    }
+
+    // The first instruction from a line of the assembly.
+    pub fn ip(&self, line: usize) -> Option<usize> {
+        self.lines.iter().position(|&l| l == line)
+    }
+
+    // Where an instruction came from, as a line of the source file.
+    pub fn at(&self, ip: usize) -> Option<String> {
+        let line = self.line(ip)?;
+        Some(match &self.source {
+            Some(file) => format!("line {} of {}", line, file),
+            None => format!("line {} of the assembly", line),
+        })
+    }
+
+    // Reads a `.wsmap` file, or says which line of it, counting from 1, is malformed.
+    pub fn parse(src: &str) -> Result<Symbols, usize> {
+        let mut symbols = Symbols::new();
+        for (i, line) in src.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            if let Some(file) = line.strip_prefix("file ") {
+                symbols.source = Some(file.to_string());
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            if line.starts_with("lines") {
+                symbols.lines = fields.skip(1).map(str::parse).collect::<Result<_, _>>().map_err(|_| i + 1)?;
+                continue;
+            }
+            let label = match fields.next() {
+                Some("(empty)") => Ok(Label::default()),
+                bits => bits.unwrap_or_default().parse(),
+            };
+            match (label, fields.next(), fields.next()) {
+                (Ok(label), Some(name), None) => symbols.insert(label, name.to_string()),
+                _ => return Err(i + 1),
+            }
+        }
+        Ok(symbols)
+    }
+
+    // Shows an instruction with its label by name, if it has one.
+    pub fn show(&self, insn: &Insn) -> String {
+        match insn.label().and_then(|l| self.name(l)) {
+            Some(name) => format!("{} {}", insn.mnemonic(), name),
+            None => insn.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<_> = self.names.iter().collect();
+        names.sort();
+        for (label, name) in names {
+            writeln!(f, "{} {}", label, name)?;
+        }
+        if let Some(file) = &self.source {
+            writeln!(f, "file {}", file)?;
+        }
+        if !self.lines.is_empty() {
+            f.write_str("lines")?;
+            for line in &self.lines {
+                write!(f, " {}", line)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    fmt,
+    io::{self, BufReader, Read},
+    str::FromStr,
+};
+
+// Where a token, or the instruction it begins, is in the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    // The byte offset in the original file, comments included.
+    pub offset: usize,
+    // The index among only the space, tab and newline tokens.
+    pub token: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Location {
+    fn start() -> Location {
+        Location { offset: 0, token: 0, line: 1, column: 1 }
+    }
+
+    // Moves past a byte of the source.
+    fn advance(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xc0 != 0x80 {
+            self.column += 1;
+        }
+        self.offset += 1;
+    }
+}
+
+// Which characters stand for each token, for dialects that write them some other way or
+// that accept more than one character for a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alphabet {
+    // The token each byte stands for, as the space, tab or newline it's usually written as,
+    // or 0 for a comment.
+    tokens: Vec<u8>,
+}
+
+impl Alphabet {
+    // Takes the characters for spaces, tabs and linefeeds, which must all be ASCII and
+    // different.
+    pub fn new(space: &[u8], tab: &[u8], linefeed: &[u8]) -> Option<Alphabet> {
+        let mut tokens = vec![0; 256];
+        for (chars, token) in [(space, b' '), (tab, b'\t'), (linefeed, b'\n')] {
+            for &c in chars {
+                if !c.is_ascii() || tokens[c as usize] != 0 {
+                    return None;
+                }
+                tokens[c as usize] = token;
+            }
+        }
+        Some(Alphabet { tokens })
+    }
+
+    pub(crate) fn token(&self, byte: u8) -> Option<u8> {
+        Some(self.tokens[byte as usize]).filter(|&t| t != 0)
+    }
+}
+
+impl Default for Alphabet {
+    fn default() -> Alphabet {
+        Alphabet::new(b" ", b"\t", b"\n").expect("the usual tokens are distinct")
+    }
+}
+
+// Either three characters, one for each token, or three comma-separated groups of them.
+impl FromStr for Alphabet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Alphabet, String> {
+        let groups: Vec<&[u8]> = match s.len() {
+            3 => s.as_bytes().chunks(1).collect(),
+            _ => s.split(',').map(str::as_bytes).collect(),
+        };
+        match groups[..] {
+            [space, tab, linefeed] if !space.is_empty() && !tab.is_empty() && !linefeed.is_empty() => {
+                Alphabet::new(space, tab, linefeed)
+                    .ok_or_else(|| "the characters must be ASCII and not stand for two tokens".to_string())
+            }
+            _ => Err("expected the characters for space, tab and linefeed".to_string()),
+        }
+    }
+}
+
+// Where the parser gets its tokens from, which knows where the next one is and what the
+// last one was.
+pub(crate) trait Source: Iterator<Item = u8> {
+    fn location(&mut self) -> Location;
+    fn at_end(&mut self) -> bool;
+    fn last(&self) -> Option<u8>;
+}
+
+// The tokens of a source file, skipping everything else while keeping track of where the
+// next one is.
+pub(crate) struct Tokens<'a> {
+    src: &'a [u8],
+    alphabet: Alphabet,
+    at: Location,
+    last: Option<u8>,
+}
+
+impl<'a> Tokens<'a> {
+    pub(crate) fn new(src: &'a [u8], alphabet: &Alphabet) -> Tokens<'a> {
+        Tokens { src, alphabet: alphabet.clone(), at: Location::start(), last: None }
+    }
+}
+
+impl Source for Tokens<'_> {
+    fn location(&mut self) -> Location {
+        while self.at.offset < self.src.len() && self.alphabet.token(self.src[self.at.offset]).is_none() {
+            self.at.advance(self.src[self.at.offset]);
+        }
+        self.at
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.location().offset == self.src.len()
+    }
+
+    fn last(&self) -> Option<u8> {
+        self.last
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.location();
+        let byte = *self.src.get(self.at.offset)?;
+        self.at.advance(byte);
+        self.at.token += 1;
+        self.last = self.alphabet.token(byte);
+        self.last
+    }
+}
+
+// The tokens of source being read, a byte at a time from a buffer. A read that fails ends
+// the tokens, keeping the error for whoever is reading them.
+pub(crate) struct ReadTokens<R> {
+    input: io::Bytes<BufReader<R>>,
+    alphabet: Alphabet,
+    peeked: Option<u8>,
+    at: Location,
+    last: Option<u8>,
+    pub(crate) error: Option<io::Error>,
+}
+
+impl<R: Read> ReadTokens<R> {
+    pub(crate) fn new(input: R, alphabet: &Alphabet) -> ReadTokens<R> {
+        let (input, alphabet) = (BufReader::new(input).bytes(), alphabet.clone());
+        ReadTokens { input, alphabet, peeked: None, at: Location::start(), last: None, error: None }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        if self.peeked.is_none() && self.error.is_none() {
+            match self.input.next() {
+                Some(Ok(byte)) => self.peeked = Some(byte),
+                Some(Err(e)) => self.error = Some(e),
+                None => {}
+            }
+        }
+        self.peeked
+    }
+}
+
+impl<R: Read> Source for ReadTokens<R> {
+    fn location(&mut self) -> Location {
+        while let Some(byte) = self.peek().filter(|&b| self.alphabet.token(b).is_none()) {
+            self.at.advance(byte);
+            self.peeked = None;
+        }
+        self.at
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.location();
+        self.peeked.is_none()
+    }
+
+    fn last(&self) -> Option<u8> {
+        self.last
+    }
+}
+
+impl<R: Read> Iterator for ReadTokens<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        self.location();
+        let byte = self.peeked.take()?;
+        self.at.advance(byte);
+        self.at.token += 1;
+        self.last = self.alphabet.token(byte);
+        self.last
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Token {
+    Space,
+    Tab,
+    Linefeed,
+}
+
+impl Token {
+    pub fn byte(self) -> u8 {
+        match self {
+            Token::Space => b' ',
+            Token::Tab => b'\t',
+            Token::Linefeed => b'\n',
+        }
+    }
+}
+
+// Tokens are written S, T and L, as Whitespace is usually described.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Token::Space => "S",
+            Token::Tab => "T",
+            Token::Linefeed => "L",
+        })
+    }
+}
+
+// The tokens of a source file with where each one is, skipping everything that isn't one.
+pub struct Lexer<'a>(Tokens<'a>);
+
+impl Iterator for Lexer<'_> {
+    type Item = (Token, Location);
+
+    fn next(&mut self) -> Option<(Token, Location)> {
+        let at = self.0.location();
+        let token = match self.0.next()? {
+            b' ' => Token::Space,
+            b'\t' => Token::Tab,
+            _ => Token::Linefeed,
+        };
+        Some((token, at))
+    }
+}
+
+pub fn lex(src: &[u8]) -> Lexer<'_> {
+    Lexer(Tokens::new(src, &Alphabet::default()))
+}
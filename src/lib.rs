@@ -0,0 +1,83 @@
+mod asm;
+mod bf;
+mod block;
+mod cfg;
+mod check;
+mod dap;
+mod debug;
+mod disasm;
+mod emit;
+mod error;
+mod event;
+mod format;
+mod gen;
+mod gzip;
+mod heap;
+mod host;
+mod insn;
+mod label;
+mod lex;
+mod lsp;
+mod minify;
+mod optimize;
+mod parse;
+mod profile;
+mod repl;
+mod stego;
+#[cfg(unix)]
+mod tui;
+mod unit;
+mod value;
+mod vm;
+mod zstd;
+
+pub mod aio;
+pub mod bytecode;
+pub mod coverage;
+pub mod ffi;
+pub mod ir;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(all(feature = "jit", target_family = "wasm"))]
+compile_error!("the jit compiles to native code, so it can't be built for WebAssembly");
+pub mod json;
+pub mod playground;
+pub mod snapshot;
+pub mod transpile;
+pub mod wasm;
+
+pub use asm::{assemble, Assembler};
+pub use bf::from_bf;
+pub use block::{blocks, Block};
+pub use cfg::cfg;
+pub use check::{check, check_source, check_stack, Diagnostic, Severity};
+pub use dap::DapServer;
+pub use debug::{Breakpoints, Condition, Debugger, Subject};
+pub use disasm::{disassemble, disassemble_located, disassemble_named};
+pub use emit::emit;
+pub use error::{AlbusError, Result};
+pub use event::{Feed, StepEvent};
+pub use format::format_source;
+pub use gen::generate;
+pub use heap::{Heap, HeapKind};
+pub use host::{Host, HostFn};
+pub use insn::Insn;
+pub use label::{Label, Symbols};
+pub use lex::{lex, Alphabet, Lexer, Location, Token};
+pub use lsp::LspServer;
+pub use minify::minify;
+pub use optimize::{optimize, optimize_with_roots, strip_unreachable, tail_calls};
+pub use parse::{
+    load, load_source, load_with, parse, parse_source, parse_stream, parse_with, read_source, Dialect, ParseOptions, Parsed,
+    Stream,
+};
+pub use profile::Profiler;
+pub use repl::repl;
+pub use stego::{embed, extract};
+#[cfg(unix)]
+pub use tui::tui;
+pub use unit::{parse_units, UnitSuite, UnitTest};
+pub use value::Value;
+pub use vm::{interpret, Charset, Eof, ExitReason, Hook, IntWidth, Limits, RunResult, Vm};
+
+pub type Num = num_bigint::BigInt;
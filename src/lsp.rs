@@ -0,0 +1,297 @@
+use crate::{
+    asm::split,
+    check, check_stack,
+    dap::{recv, send},
+    json::Json,
+    optimize::labels,
+    AlbusError, Assembler, Insn, Severity,
+};
+use hashbrown::HashMap;
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+// What each mnemonic does, shown on hover.
+const DOCS: &[(&str, &str)] = &[
+    ("push", "push n\n\nPushes the number n, which may be a character such as 'A'."),
+    ("pushstr", "pushstr \"text\"\n\nPushes a zero and then the characters of the text last to first."),
+    ("pop", "pop\n\nDiscards the top of the stack."),
+    ("dup", "dup\n\nPushes a copy of the top of the stack."),
+    ("swap", "swap\n\nSwaps the top two values on the stack."),
+    ("copy", "copy n\n\nPushes a copy of the value n below the top of the stack."),
+    ("slide", "slide n\n\nDiscards n values below the top of the stack, keeping the top."),
+    ("add", "add\n\nPops b and a, then pushes a + b."),
+    ("sub", "sub\n\nPops b and a, then pushes a - b."),
+    ("mul", "mul\n\nPops b and a, then pushes a * b."),
+    ("div", "div\n\nPops b and a, then pushes a / b rounded down."),
+    ("mod", "mod\n\nPops b and a, then pushes the remainder of a / b, with the sign of b."),
+    ("store", "store\n\nPops a value and then an address, and stores the value in the heap there."),
+    ("load", "load\n\nPops an address and pushes the value in the heap there."),
+    ("label", "label l\n\nMarks a place for calls and jumps to go to."),
+    ("call", "call l\n\nCalls the subroutine at label l, which returns here with `ret`."),
+    ("jump", "jump l\n\nContinues at label l."),
+    ("jz", "jz l\n\nPops a value and continues at label l if it is zero."),
+    ("jn", "jn l\n\nPops a value and continues at label l if it is negative."),
+    ("ret", "ret\n\nReturns from the current subroutine."),
+    ("ichr", "ichr\n\nPops an address and stores the next character of input there."),
+    ("inum", "inum\n\nPops an address and stores the number on the next line of input there."),
+    ("ochr", "ochr\n\nPops a value and prints it as a character."),
+    ("onum", "onum\n\nPops a value and prints it as a number."),
+    ("exit", "exit\n\nEnds the program."),
+    ("dumpstack", "dumpstack\n\nWrites the stack to stderr, with `--extensions debug`."),
+    ("dumpheap", "dumpheap\n\nWrites the heap to stderr, with `--extensions debug`."),
+    ("random", "random\n\nPops n and pushes a random number from 0 to n - 1, with `--extensions random`."),
+];
+
+// The kinds of symbol the outline shows, as LSP numbers them.
+const FUNCTION: usize = 12;
+const OPERATOR: usize = 25;
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    Json::object(vec![("start", position(line, start)), ("end", position(line, end))])
+}
+
+// Turns a `file://` URI into a path, decoding any escaped bytes.
+fn path(uri: &str) -> PathBuf {
+    let encoded = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(&String::from_utf8_lossy(h), 16).ok());
+        match (encoded[i], hex) {
+            (b'%', Some(b)) => {
+                bytes.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                bytes.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// The word the cursor is on, along with where it starts.
+fn word_at(text: &str, line: usize, character: usize) -> Option<(&str, usize)> {
+    let line = text.lines().nth(line)?;
+    let is_word = |c: char| !c.is_whitespace() && c != ';';
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let at = character.min(chars.len().checked_sub(1)?);
+    if !is_word(chars[at].1) {
+        return None;
+    }
+    let start = (0..=at).rev().take_while(|&i| is_word(chars[i].1)).last()?;
+    let end = (at..chars.len()).find(|&i| !is_word(chars[i].1)).map_or(line.len(), |i| chars[i].0);
+    Some((&line[chars[start].0..end], start))
+}
+
+// Where each label and macro in a document is defined, by line and character.
+fn definitions(text: &str) -> Vec<(&str, usize, usize, usize)> {
+    let mut defs = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let words = match split(line) {
+            Ok(words) => words,
+            Err(_) => continue,
+        };
+        if let [op, name, ..] = words[..] {
+            let kind = match op.to_ascii_lowercase().as_str() {
+                "label" => FUNCTION,
+                "macro" => OPERATOR,
+                _ => continue,
+            };
+            let offset = line.find(op).unwrap_or(0) + op.len();
+            let start = line[offset..].find(name).map_or(0, |i| offset + i);
+            defs.push((name, kind, lineno, line[..start].chars().count()));
+        }
+    }
+
+    defs
+}
+
+// Serves the Language Server Protocol for assembly source, giving diagnostics from the
+// assembler and `check`, the definitions of labels, documentation for instructions and an
+// outline of labels and macros. Only whole documents are synchronized.
+pub struct LspServer<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    documents: HashMap<String, String>,
+}
+
+impl<'a> LspServer<'a> {
+    pub fn new(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> LspServer<'a> {
+        LspServer { input, output, documents: HashMap::new() }
+    }
+
+    fn respond(&mut self, request: &Json, result: Json) -> io::Result<()> {
+        let id = request.get("id").cloned().unwrap_or(Json::Null);
+        send(self.output, &Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", result)]))
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        send(self.output, &Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)]))
+    }
+
+    fn diagnostics(&self, uri: &str) -> Vec<Json> {
+        let text = &self.documents[uri];
+        let diagnostic = |line: usize, severity: Severity, message: String| {
+            let end = text.lines().nth(line).map_or(0, |l| l.chars().count());
+            let severity: usize = if severity == Severity::Error { 1 } else { 2 };
+            Json::object(vec![
+                ("range", range(line, 0, end)),
+                ("severity", severity.into()),
+                ("source", "albus".into()),
+                ("message", message.into()),
+            ])
+        };
+
+        let mut asm = Assembler::new();
+        let lines = match asm.file_lines(&path(uri), text) {
+            Ok(lines) => lines,
+            Err(AlbusError::AsmError { line, reason }) => {
+                return vec![diagnostic(line.saturating_sub(1), Severity::Error, reason)];
+            }
+            Err(e) => return vec![diagnostic(0, Severity::Error, e.to_string())],
+        };
+        let (lines, insns): (Vec<_>, Vec<_>) = lines.into_iter().unzip();
+        let labels = labels(&insns);
+        let last = text.lines().count().saturating_sub(1);
+        check(&insns, &labels)
+            .into_iter()
+            .chain(check_stack(&insns, &labels))
+            .map(|d| {
+                // Messages give labels as bits, which mean nothing next to the names in the source.
+                let message = match insns.get(d.ip) {
+                    Some(Insn::Call(l) | Insn::Jump(l) | Insn::Jz(l) | Insn::Jn(l)) => match asm.name(l) {
+                        Some(name) => d.message.replace(&l.to_string(), &format!("`{}`", name)),
+                        None => d.message,
+                    },
+                    _ => d.message,
+                };
+                diagnostic(lines.get(d.ip).map_or(last, |l| l - 1), d.severity, message)
+            })
+            .collect()
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self.diagnostics(uri);
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        )
+    }
+
+    // The document and cursor position a request is about.
+    fn cursor(&self, params: &Json) -> Option<(String, &str, usize, usize)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let at = params.get("position")?;
+        let text = self.documents.get(uri)?;
+        Some((uri.to_string(), text, at.get("line")?.as_u64()? as usize, at.get("character")?.as_u64()? as usize))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (_, text, line, character) = self.cursor(params)?;
+        let (word, start) = word_at(text, line, character)?;
+        let (_, doc) = DOCS.iter().find(|(name, _)| name.eq_ignore_ascii_case(word))?;
+        let contents = Json::object(vec![("kind", "markdown".into()), ("value", format!("```\n{}", doc).into())]);
+        Some(Json::object(vec![("contents", contents), ("range", range(line, start, start + word.chars().count()))]))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (uri, text, line, character) = self.cursor(params)?;
+        let (word, _) = word_at(text, line, character)?;
+        let (name, _, line, start) = definitions(text).into_iter().find(|&(name, ..)| name == word)?;
+        let range = range(line, start, start + name.chars().count());
+        Some(Json::object(vec![("uri", uri.into()), ("range", range)]))
+    }
+
+    fn symbols(&self, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let text = self.documents.get(uri)?;
+        let symbols = definitions(text)
+            .into_iter()
+            .map(|(name, kind, line, start)| {
+                let range = range(line, start, start + name.chars().count());
+                Json::object(vec![
+                    ("name", name.into()),
+                    ("kind", kind.into()),
+                    ("location", Json::object(vec![("uri", uri.into()), ("range", range)])),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Some(symbols.into())
+    }
+
+    // Serves requests until the client asks the server to exit or disconnects.
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(message) = recv(self.input)? {
+            let method = message.get("method").and_then(Json::as_str).unwrap_or("").to_string();
+            let params = message.get("params").cloned().unwrap_or(Json::Null);
+            let document = params.get("textDocument");
+            let uri = document.and_then(|d| d.get("uri")).and_then(Json::as_str).unwrap_or("").to_string();
+
+            match method.as_str() {
+                "initialize" => {
+                    let caps = Json::object(vec![
+                        ("textDocumentSync", 1usize.into()),
+                        ("hoverProvider", true.into()),
+                        ("definitionProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                    ]);
+                    let info = Json::object(vec![("name", "albus".into())]);
+                    self.respond(&message, Json::object(vec![("capabilities", caps), ("serverInfo", info)]))?;
+                }
+                "textDocument/didOpen" => {
+                    let text = document.and_then(|d| d.get("text")).and_then(Json::as_str).unwrap_or("");
+                    self.documents.insert(uri.clone(), text.to_string());
+                    self.publish(&uri)?;
+                }
+                "textDocument/didChange" => {
+                    let changes = params.get("contentChanges").and_then(Json::as_array).unwrap_or(&[]);
+                    if let Some(text) = changes.last().and_then(|c| c.get("text")).and_then(Json::as_str) {
+                        self.documents.insert(uri.clone(), text.to_string());
+                        self.publish(&uri)?;
+                    }
+                }
+                "textDocument/didClose" => {
+                    self.documents.remove(&uri);
+                    self.notify(
+                        "textDocument/publishDiagnostics",
+                        Json::object(vec![("uri", uri.as_str().into()), ("diagnostics", Vec::new().into())]),
+                    )?;
+                }
+                "textDocument/hover" => {
+                    let result = self.hover(&params).unwrap_or(Json::Null);
+                    self.respond(&message, result)?;
+                }
+                "textDocument/definition" => {
+                    let result = self.definition(&params).unwrap_or(Json::Null);
+                    self.respond(&message, result)?;
+                }
+                "textDocument/documentSymbol" => {
+                    let result = self.symbols(&params).unwrap_or(Json::Null);
+                    self.respond(&message, result)?;
+                }
+                "shutdown" => self.respond(&message, Json::Null)?,
+                "exit" => return Ok(()),
+                // Requests need an answer even when they aren't supported, unlike notifications.
+                _ if message.get("id").is_some() => {
+                    let error = Json::object(vec![
+                        ("code", Json::Num("-32601".into())),
+                        ("message", format!("unsupported method `{}`", method).into()),
+                    ]);
+                    let id = message.get("id").cloned().unwrap_or(Json::Null);
+                    send(self.output, &Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("error", error)]))?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
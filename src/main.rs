@@ -1,236 +1,1899 @@
-use hashbrown::HashMap;
-use num_bigint::BigInt;
-use num_traits::{ToPrimitive, Zero};
+use albus::{
+    assemble, bytecode, cfg, check_source, check_stack, coverage, disassemble_named, embed, emit, extract,
+    format_source, from_bf, generate, ir, json::Json, load_source, minify, optimize, optimize_with_roots, parse_source,
+    parse_units, repl, snapshot, transpile, wasm, AlbusError, Assembler, Charset, DapServer, Debugger, Eof, HeapKind,
+    Insn, IntWidth, Label, Limits, Location, LspServer, Num, ParseOptions, Parsed, Profiler, Severity, Symbols,
+    UnitTest, Value, Vm,
+};
 use std::{
-    env, fs,
-    io::{stdin, Read},
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::{self, File},
+    io::{stderr, stdin, stdout, BufRead, BufReader, BufWriter, Cursor, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
-type Num = BigInt;
-
-#[derive(PartialEq)]
-enum Insn {
-    None,
-
-    Push(Num),
-    Pop,
-    Dup,
-    Swap,
-    Copy(Num),
-    Slide(Num),
-
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Mod,
-
-    Label(Num),
-    Call(Num),
-    Jump(Num),
-    Jz(Num),
-    Jn(Num),
-    Ret,
-
-    Store,
-    Load,
-    Ichr,
-    Inum,
-    Ochr,
-    Onum,
-    Exit,
-}
-
-fn parse_arg(tokens: &mut std::str::Bytes) -> Num {
-    let mut n: Num = Zero::zero();
-    let neg = tokens.next().unwrap() == b'\t';
-
-    while let Some(byte) = tokens.next() {
-        if byte == b'\n' {
-            break;
-        } else {
-            n <<= 1;
-            if byte == b'\t' {
-                n += 1
-            }
-        }
-    }
+const USAGE: &str = "\
+usage: albus [run] [OPTIONS] FILE
+       albus trace [OPTIONS] FILE
+       albus resume [OPTIONS] SNAPSHOT
+       albus check [--stack] FILE
+       albus asm FILE [-o OUT] [--map F]
+       albus disasm [--locations] FILE
+       albus fmt [--canonical] FILE [-o OUT]
+       albus embed CARRIER FILE [-o OUT]
+       albus extract [OPTIONS] FILE [-o OUT]
+       albus cfg FILE
+       albus ir [-O] FILE
+       albus coverage FILE TRACEFILE...
+       albus optimize FILE [-o OUT]
+       albus minify FILE [-o OUT]
+       albus compile [--target albc|wasm] FILE [-o OUT]
+       albus transpile --target c|rust FILE
+       albus gen [--seed N] [--size N] [-o OUT]
+       albus from-bf FILE [-o OUT]
+       albus test [--update | --units] [OPTIONS] [DIR]
+       albus difftest --against CMD [OPTIONS] FILE
+       albus bench [--runs N | --for TIME] [--compare FLAGS] [OPTIONS] FILE
+       albus example [NAME [--print asm|ws]]
+       albus debug [--listen ADDR] [OPTIONS] FILE
+       albus attach ADDR
+       albus tui FILE
+       albus dap [--port PORT]
+       albus lsp
+       albus repl
 
-    if neg {
-        n * -1
-    } else {
-        n
-    }
-}
-
-fn parse(src: &mut String) -> (Vec<Insn>, HashMap<Num, usize>) {
-    let mut insns = Vec::<Insn>::new();
-    let mut labels = HashMap::new();
-    let mut code = 0u8;
-    let mut insn;
-
-    src.retain(|c| c == ' ' || c == '\t' || c == '\n');
-    let mut tokens = src.bytes();
-
-    while let Some(byte) = tokens.next() {
-        code = code * 4 + byte % 4 + 1;
-        insn = match code {
-            0b01_01 => Insn::Push(parse_arg(&mut tokens)),
-            0b01_10_01 => Insn::Copy(parse_arg(&mut tokens)),
-            0b01_10_11 => Insn::Slide(parse_arg(&mut tokens)),
-            0b11_01_10 => Insn::Call(parse_arg(&mut tokens)),
-            0b11_01_11 => Insn::Jump(parse_arg(&mut tokens)),
-            0b11_10_01 => Insn::Jz(parse_arg(&mut tokens)),
-            0b11_10_10 => Insn::Jn(parse_arg(&mut tokens)),
-            0b11_01_01 => {
-                let arg = parse_arg(&mut tokens);
-                labels.insert(arg.clone(), insns.len());
-                Insn::Label(arg)
-            }
-            0b01_11_11 => Insn::Pop,
-            0b01_11_01 => Insn::Dup,
-            0b01_11_10 => Insn::Swap,
-            0b10_01_01_01 => Insn::Add,
-            0b10_01_01_10 => Insn::Sub,
-            0b10_01_01_11 => Insn::Mul,
-            0b10_01_10_01 => Insn::Div,
-            0b10_01_10_10 => Insn::Mod,
-            0b10_10_01 => Insn::Store,
-            0b10_10_10 => Insn::Load,
-            0b11_10_11 => Insn::Ret,
-            0b10_11_10_01 => Insn::Ichr,
-            0b10_11_10_10 => Insn::Inum,
-            0b10_11_01_01 => Insn::Ochr,
-            0b10_11_01_10 => Insn::Onum,
-            0b11_11_11 => Insn::Exit,
-            _ => Insn::None,
-        };
+Run options:
+  --dump-state     print the final stack and heap to stderr
+  --dump-json      print the final state and why the program stopped to stderr as JSON
+  --stats          report to stderr how many of each instruction ran, how deep the stack
+                   and calls went, the heap size, bignum bytes made and the run time
+  --stats-format F report statistics as text or as json
+  --trace          print each instruction and the top of the stack to stderr as it runs
+  --trace-format F trace as text or as json, one object per instruction
+  --profile        report the most executed instructions and where time went to stderr
+  --coverage F     add how often each instruction ran to the lcov tracefile F
+  --flamegraph F   write how many instructions ran under each call stack to F, folded
+                   for flame graph tools
+  --max-steps N    stop with an error after executing N instructions
+  --max-stack N    limit the stack to N values
+  --max-heap N     limit the heap to N addresses
+  --max-calls N    limit subroutine calls to N deep, 10000000 by default or none for 0
+  --max-bytes N    limit values too large for 64 bits to N bytes in total
+  --checkpoint-every N
+                   save a snapshot every N instructions to FILE.snapshot, or the -o file
+  --detect-loops M warn about, or with M as abort stop at, a loop that keeps coming back to
+                   the same place with the same stack depth and top without any input,
+                   output or change to the heap
+  --entry L        start at label L, by its name in the map or as bits such as 0110, as if
+                   it had been called, so that the run finishes when it returns
+  --stack VALUES   start with the comma-separated VALUES on the stack, the last on top
+  --timeout TIME   stop with an error after TIME, such as 5s, 500ms or 2m
+  --input TEXT     give the program TEXT as its input instead of stdin
+  --input-file F   give the program the contents of F as its input
+  --output-file F  write what the program outputs to F instead of stdout
+  --heap-file F    start with the heap saved in F, and save it there once the program
+                   finishes without an error
+  --record-io F    save the input the program reads to F
+  --replay-io F    give the program the input saved in F by --record-io
+  --eof MODE       what reading past the end of input does: zero, minus-one, error or halt
+  --charset C      read and write characters as latin1, a byte each, as utf8, or as bytes
+                   that are written unchanged rather than as the code points up to 255
+  --trunc-div      round division toward zero instead of down, as albus used to
+  --clamp-args     limit copy and slide arguments to the stack instead of failing
+  --tail-calls     run a call that's followed by ret as a jump, so that tail recursion
+                   doesn't grow the call stack, without counting the skipped ret
+  --int-width W    keep integers to W bits as fixed-width interpreters do, wrapping on
+                   overflow, or saturating if W ends in -saturating, as in 64-saturating
+  --heap KIND      lay the heap out as sparse, dense for addresses counting up from 0,
+                   paged for clusters of addresses, or auto, which is dense while the
+                   addresses used stay close together and the default
+  --exit-code      exit with the value left on top of the stack, modulo 256
+  --unbuffered     write output a line at a time instead of in large blocks
+  --seed N         start the numbers the random extension gives from N, not the clock
+  -O               optimize the program before running it
+  -O2              optimize it and also run common pairs of instructions as one
+  --jit            compile the program to native code before running it
+  --locations      show source lines and columns in traces and disassembly
+  --map F          show labels by their names and errors by their lines in the assembly
+                   as saved in F, rather than FILE.wsmap if there is one; asm saves them
+                   to F, or beside the -o file
+
+Fmt options:
+  --canonical      also write numbers, and labels read as numbers, without leading zeroes
+
+Test options:
+  --update         write what each program outputs to its .out file instead of checking it
+  --units          run the tests in each FILE.toml instead, each a [[test]] that calls a
+                   subroutine in FILE.ws, or the `program` named at the top, with keys
+                   call = \"LABEL\", stack = [VALUES], input = \"TEXT\", and the
+                   expect_stack = [VALUES] and expect_output = \"TEXT\" to check
+
+Difftest options:
+  --against CMD    the interpreter to compare with, run as CMD FILE with the same input,
+                   its output and whether it fails compared with albus's
+
+Debug options:
+  --listen ADDR    take debugger commands from a client connecting to ADDR, such as
+                   127.0.0.1:4711 or albus attach, while the program keeps this terminal
+
+Gen options:
+  --seed N         generate the program for seed N rather than one picked from the clock
+  --size N         generate about N instructions, 100 by default
+
+Bench options:
+  --runs N         run the program N times, 10 by default
+  --for TIME       run the program repeatedly for TIME instead
+  --compare FLAGS  also run it with the run options in FLAGS, such as \"-O\", to compare
 
-        if insn != Insn::None {
-            insns.push(insn);
-            code = 0;
+Loading options:
+  --legacy-labels  read labels as signed numbers, as albus used to
+  --lenient        accept source that ends partway through an instruction
+  --strict-parse   fail on tokens that don't begin any opcode, instead of reading on
+                   until they and the tokens after them make one
+  --dialect V      read the program as Whitespace 0.2, which has no copy or slide, or as
+                   0.3, the default
+  --tokens T       read the program's spaces, tabs and linefeeds as the three characters
+                   in T, such as STL, or as any of the characters in each of three
+                   comma-separated groups, to also accept a carriage return as a linefeed
+  --extensions L   enable the comma-separated extensions in L; `debug` reads LLSS and
+                   LLST as dumpstack and dumpheap, which write the stack and heap to stderr,
+                   and `random` reads LLSL as random, which pops n and pushes a random
+                   number from 0 to n - 1
+
+A FILE of - reads the program from stdin, after which the program itself sees no input.
+Programs compressed with gzip or zstd are decompressed as they're loaded.
+Unless it's run with --jit, a program sent SIGUSR1 reports where it is on stderr and
+carries on, and one interrupted with Ctrl-C shows its state before exiting.
+
+Exit status is 0 on success, 1 for an error while running, 2 for bad usage, 3 for a
+program that fails to load, 4 for one stopped by a limit and 130 for one interrupted.";
+
+const COMMANDS: &[&str] = &[
+    "run", "trace", "resume", "check", "asm", "disasm", "fmt", "embed", "extract", "cfg", "ir", "coverage", "optimize", "minify", "compile", "transpile", "gen", "from-bf", "test", "difftest", "bench", "example", "debug", "attach", "tui",
+    "dap", "lsp", "repl",
+];
+
+#[derive(Clone, Default)]
+struct Options {
+    parse: ParseOptions,
+    dump_state: bool,
+    dump_json: bool,
+    stats: bool,
+    stats_json: bool,
+    trace: bool,
+    trace_json: bool,
+    profile: bool,
+    jit: bool,
+    optimize: bool,
+    fuse: bool,
+    locations: bool,
+    stack: bool,
+    limits: Limits,
+    eof: Eof,
+    charset: Charset,
+    trunc_div: bool,
+    clamp_args: bool,
+    tail_calls: bool,
+    int_width: Option<IntWidth>,
+    heap: HeapKind,
+    // Whether to watch for loops, and if so whether to stop the program on finding one.
+    loops: Option<bool>,
+    exit_code: bool,
+    input: Option<String>,
+    input_file: Option<String>,
+    record_io: Option<String>,
+    coverage: Option<String>,
+    flamegraph: Option<String>,
+    replay_io: Option<String>,
+    checkpoint_every: Option<u64>,
+    timeout: Option<Duration>,
+    target: Option<String>,
+    out: Option<String>,
+    port: Option<String>,
+    listen: Option<String>,
+    entry: Option<String>,
+    initial_stack: Option<Vec<Value>>,
+    seed: Option<u64>,
+    size: Option<usize>,
+    runs: Option<usize>,
+    bench_for: Option<Duration>,
+    compare: Option<String>,
+    print: Option<String>,
+    unbuffered: bool,
+    output_file: Option<String>,
+    heap_file: Option<String>,
+    map: Option<String>,
+    canonical: bool,
+    update: bool,
+    units: bool,
+    against: Option<String>,
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn stack_values(list: &str) -> Option<Vec<Value>> {
+    let list = list.trim();
+    if list.is_empty() {
+        return Some(Vec::new());
+    }
+    list.split(',').map(|n| n.trim().parse::<Num>().ok().map(Value::from)).collect()
+}
+
+fn duration(flag: &str, value: &str) -> Duration {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (n, unit) = value.split_at(split);
+    let scale = match unit {
+        "" | "s" => 1.0,
+        "ms" => 0.001,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => f64::NAN,
+    };
+    match n.parse::<f64>().map(|n| n * scale) {
+        Ok(secs) if secs.is_finite() => Duration::from_secs_f64(secs),
+        _ => {
+            eprintln!("albus: `{}` needs a duration such as 5s or 500ms", flag);
+            process::exit(2);
         }
     }
+}
 
-    (insns, labels)
+fn number<T: std::str::FromStr>(flag: &str, value: String) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("albus: `{}` needs a number", flag);
+        process::exit(2);
+    })
 }
 
-fn interpret(insns: Vec<Insn>, labels: HashMap<Num, usize>) -> (Vec<Num>, HashMap<Num, Num>, u32) {
-    let mut stack = Vec::<Num>::new();
-    let mut calls = Vec::<usize>::new();
-    let mut heap = HashMap::new();
-    let mut ip = 0;
-    let mut n = 0;
+// Separates flags from positional arguments, setting the flags on top of `options`. Flags
+// may appear anywhere, and those taking a value accept it either as the next argument or
+// after an `=`.
+fn parse_args(args: &[String], mut options: Options) -> (Options, Vec<&str>) {
+    let mut positional = Vec::new();
+    let mut args = args.iter().peekable();
 
-    while let Some(insn) = insns.get(ip) {
-        n += 1;
-        match insn {
-            Insn::Push(arg) => stack.push(arg.clone()),
-            Insn::Copy(arg) => {
-                let n = arg.to_usize().unwrap();
-                stack.push(stack[stack.len() - 1 - n].clone());
-            }
-            Insn::Slide(arg) => {
-                let n = stack.len() - 1;
-                stack.drain(n - arg.to_usize().unwrap()..n);
-            }
-            Insn::Label(_) | Insn::None => n -= 1,
-            Insn::Call(arg) => {
-                calls.push(ip);
-                ip = labels[arg];
-            }
-            Insn::Jump(arg) => ip = labels[arg],
-            Insn::Jz(arg) => {
-                if stack.pop().unwrap().is_zero() {
-                    ip = labels[arg];
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg.as_str());
+            continue;
+        }
+
+        let (flag, inline) = match arg.find('=') {
+            Some(i) => (&arg[..i], Some(arg[i + 1..].to_string())),
+            None => (arg.as_str(), None),
+        };
+        // For check, --stack takes no value, so the next argument is only the values to start
+        // the stack with if it reads as them.
+        if flag == "--stack" {
+            let values = match &inline {
+                Some(list) => Some(stack_values(list).unwrap_or_else(|| {
+                    eprintln!("albus: `--stack` needs comma-separated integers");
+                    process::exit(2);
+                })),
+                None => args.next_if(|next| stack_values(next).is_some()).and_then(|list| stack_values(list)),
+            };
+            match values {
+                Some(values) => options.initial_stack = Some(values),
+                None => options.stack = true,
+            }
+            continue;
+        }
+        let mut value = || match inline.clone().or_else(|| args.next().cloned()) {
+            Some(value) => value,
+            None => {
+                eprintln!("albus: `{}` needs a value", flag);
+                process::exit(2);
+            }
+        };
+
+        match flag {
+            "--dump-state" => options.dump_state = true,
+            "--dump-json" => options.dump_json = true,
+            // Still accepted from when the state was printed unless this was given.
+            "--quiet" | "-q" => options.dump_state = false,
+            "--stats" => options.stats = true,
+            "--stats-format" => {
+                options.stats = true;
+                options.stats_json = match value().as_str() {
+                    "text" => false,
+                    "json" => true,
+                    _ => {
+                        eprintln!("albus: `--stats-format` needs one of text or json");
+                        process::exit(2);
+                    }
                 }
             }
-            Insn::Jn(arg) => {
-                if stack.pop().unwrap() < Zero::zero() {
-                    ip = labels[arg];
+            "--unbuffered" => options.unbuffered = true,
+            "--trace" => options.trace = true,
+            "--trace-format" => {
+                options.trace = true;
+                options.trace_json = match value().as_str() {
+                    "text" => false,
+                    "json" => true,
+                    _ => {
+                        eprintln!("albus: `--trace-format` needs one of text or json");
+                        process::exit(2);
+                    }
+                }
+            }
+            "--profile" => options.profile = true,
+            "--jit" => options.jit = true,
+            "-O" => options.optimize = true,
+            "-O2" => {
+                options.optimize = true;
+                options.fuse = true;
+            }
+            "--trunc-div" => options.trunc_div = true,
+            "--clamp-args" => options.clamp_args = true,
+            "--tail-calls" => options.tail_calls = true,
+            "--detect-loops" => {
+                options.loops = match value().as_str() {
+                    "warn" => Some(false),
+                    "abort" => Some(true),
+                    _ => {
+                        eprintln!("albus: `--detect-loops` needs one of warn or abort");
+                        process::exit(2);
+                    }
                 }
             }
-            Insn::Pop => {
-                stack.pop();
+            "--heap" => {
+                options.heap = value().parse().unwrap_or_else(|_| {
+                    eprintln!("albus: `--heap` needs one of auto, sparse, dense or paged");
+                    process::exit(2);
+                })
             }
-            Insn::Dup => stack.push(stack.last().unwrap().clone()),
-            Insn::Swap => {
-                let n = stack.len();
-                stack.swap(n - 1, n - 2);
+            "--int-width" => {
+                options.int_width = Some(value().parse().unwrap_or_else(|_| {
+                    eprintln!("albus: `--int-width` needs a number of bits, such as 32 or 64-saturating");
+                    process::exit(2);
+                }))
             }
-            Insn::Add => {
-                let r = stack.pop().unwrap();
-                let n = stack.len() - 1;
-                stack[n] += r;
+            "--exit-code" => options.exit_code = true,
+            "--locations" => options.locations = true,
+            "--entry" => options.entry = Some(value()),
+            "--canonical" => options.canonical = true,
+            "--update" => options.update = true,
+            "--units" => options.units = true,
+            "--against" => options.against = Some(value()),
+            "--legacy-labels" => options.parse.legacy_labels = true,
+            "--lenient" => options.parse.lenient = true,
+            "--strict-parse" => options.parse.strict = true,
+            "--dialect" => {
+                options.parse.dialect = value().parse().unwrap_or_else(|_| {
+                    eprintln!("albus: `--dialect` needs one of 0.2 or 0.3");
+                    process::exit(2);
+                })
             }
-            Insn::Sub => {
-                let r = stack.pop().unwrap();
-                let n = stack.len() - 1;
-                stack[n] -= r;
+            "--tokens" => {
+                options.parse.alphabet = value().parse().unwrap_or_else(|e| {
+                    eprintln!("albus: bad --tokens: {}", e);
+                    process::exit(2);
+                })
             }
-            Insn::Mul => {
-                let r = stack.pop().unwrap();
-                let n = stack.len() - 1;
-                stack[n] *= r;
+            "--extensions" => {
+                for extension in value().split(',') {
+                    match extension {
+                        "debug" => options.parse.debug_opcodes = true,
+                        "random" => options.parse.random_opcode = true,
+                        _ => {
+                            eprintln!("albus: unknown extension `{}`; there are debug and random", extension);
+                            process::exit(2);
+                        }
+                    }
+                }
+            }
+            "--max-steps" => options.limits.max_steps = Some(number(flag, value())),
+            "--max-stack" => options.limits.max_stack = Some(number(flag, value())),
+            "--max-heap" => options.limits.max_heap = Some(number(flag, value())),
+            "--max-calls" => options.limits.max_calls = Some(number(flag, value())),
+            "--max-bytes" => options.limits.max_bytes = Some(number(flag, value())),
+            "--checkpoint-every" => options.checkpoint_every = Some(number(flag, value())).filter(|&n| n > 0),
+            "--timeout" => options.timeout = Some(duration(flag, &value())),
+            "--seed" => options.seed = Some(number(flag, value())),
+            "--size" => options.size = Some(number(flag, value())),
+            "--runs" => options.runs = Some(number(flag, value())).filter(|&n| n > 0),
+            "--for" => options.bench_for = Some(duration(flag, &value())),
+            "--compare" => options.compare = Some(value()),
+            "--print" => options.print = Some(value()),
+            "--charset" => {
+                options.charset = value().parse().unwrap_or_else(|_| {
+                    eprintln!("albus: `--charset` needs one of latin1, utf8 or bytes");
+                    process::exit(2);
+                })
+            }
+            "--eof" => {
+                options.eof = value().parse().unwrap_or_else(|_| {
+                    eprintln!("albus: `--eof` needs one of zero, minus-one, error or halt");
+                    process::exit(2);
+                })
             }
-            Insn::Div => {
-                let r = stack.pop().unwrap();
-                let n = stack.len() - 1;
-                stack[n] /= r;
+            "--input" => options.input = Some(value()),
+            "--input-file" => options.input_file = Some(value()),
+            "--output-file" => options.output_file = Some(value()),
+            "--heap-file" => options.heap_file = Some(value()),
+            "--map" => options.map = Some(value()),
+            "--record-io" => options.record_io = Some(value()),
+            "--coverage" => options.coverage = Some(value()),
+            "--flamegraph" => options.flamegraph = Some(value()),
+            "--replay-io" => options.replay_io = Some(value()),
+            "--target" => options.target = Some(value()),
+            "--output" | "-o" => options.out = Some(value()),
+            "--port" => options.port = Some(value()),
+            "--listen" => options.listen = Some(value()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
             }
-            Insn::Mod => {
-                let r = stack.pop().unwrap();
-                let n = stack.len() - 1;
-                stack[n] %= r;
+            _ => {
+                eprintln!("albus: unknown option `{}`", flag);
+                usage();
             }
+        }
+    }
+
+    (options, positional)
+}
+
+// The Vm as the CLI runs it, reading from the chosen input and writing to the chosen output.
+type Machine = Vm<Box<dyn Read>, BufWriter<Box<dyn Write>>>;
+
+// A buffer with no room writes everything straight through, which stdout sends on a line at
+// a time.
+fn output(options: &Options) -> BufWriter<Box<dyn Write>> {
+    let out: Box<dyn Write> = match &options.output_file {
+        Some(path) => Box::new(writing(File::create(path), path)),
+        None => Box::new(stdout()),
+    };
+    BufWriter::with_capacity(if options.unbuffered { 0 } else { 1 << 16 }, out)
+}
+
+// Scripted input is read before anything from --input-file, and either replaces stdin.
+// Replaying a session replaces all of them.
+fn input(options: &Options) -> Box<dyn Read> {
+    if let Some(path) = &options.replay_io {
+        return Box::new(Cursor::new(replay(path)));
+    }
+    let file = options.input_file.as_ref().map(|path| reading(File::open(path), path));
+    match (options.input.clone(), file) {
+        (Some(text), Some(file)) => Box::new(Cursor::new(text).chain(BufReader::new(file))),
+        (Some(text), None) => Box::new(Cursor::new(text)),
+        (None, Some(file)) => Box::new(BufReader::new(file)),
+        // Binary input comes in bulk, so it's worth reading more of it at once.
+        (None, None) if options.charset == Charset::Bytes => Box::new(BufReader::with_capacity(1 << 16, stdin())),
+        (None, None) => Box::new(stdin()),
+    }
+}
+
+// Passes input through while keeping a copy of every byte read.
+struct Recorder {
+    input: Box<dyn Read>,
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.input.read(buf)?;
+        self.bytes.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Sessions hold the input as a list of bytes, since it needn't be text.
+fn record(path: &str, bytes: &[u8]) {
+    let input = bytes.iter().map(|&b| Json::from(b as usize)).collect::<Vec<_>>();
+    let session = Json::object(vec![("input", input.into())]);
+    writing(fs::write(path, format!("{}\n", session)), path);
+}
+
+fn replay(path: &str) -> Vec<u8> {
+    let src = reading(fs::read_to_string(path), path);
+    let session = Json::parse(&src);
+    let input = session.as_ref().and_then(|s| s.get("input")).and_then(Json::as_array);
+    let byte = |b: &Json| b.as_u64().filter(|&b| b < 256).map(|b| b as u8);
+    input.and_then(|input| input.iter().map(byte).collect()).unwrap_or_else(|| {
+        eprintln!("albus: {} is not a session saved by --record-io", path);
+        process::exit(2);
+    })
+}
+
+fn load_file(path: &str, options: &Options) -> albus::Result<Parsed> {
+    load_source(&read(path), &options.parse)
+}
+
+// The names the assembler gave a program's labels, from the --map file or else the one it
+// saved beside the program, if either exists.
+fn symbols(path: &str, options: &Options) -> Symbols {
+    let map = match &options.map {
+        Some(map) => PathBuf::from(map),
+        None if path == "-" => return Symbols::new(),
+        None => Path::new(path).with_extension("wsmap"),
+    };
+    let src = match fs::read_to_string(&map) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && options.map.is_none() => return Symbols::new(),
+        result => reading(result, map.display()),
+    };
+    Symbols::parse(&src).unwrap_or_else(|line| {
+        eprintln!("albus: line {} of {} isn't a label and a name", line, map.display());
+        process::exit(2);
+    })
+}
+
+// A file that can't be read keeps the program from loading, so it's reported as a load
+// error rather than with a backtrace.
+fn reading<T>(result: std::io::Result<T>, path: impl std::fmt::Display) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("albus: unable to read {}: {}", path, e);
+        process::exit(EXIT_PARSE);
+    })
+}
+
+fn writing<T>(result: std::io::Result<T>, path: impl std::fmt::Display) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("albus: unable to write {}: {}", path, e);
+        process::exit(EXIT_RUNTIME);
+    })
+}
+
+// Reads a file, or all of stdin if the path is `-`.
+fn read(path: &str) -> Vec<u8> {
+    if path != "-" {
+        return reading(fs::read(path), path);
+    }
+    let mut bytes = Vec::new();
+    reading(stdin().read_to_end(&mut bytes), "stdin");
+    bytes
+}
+
+// Exit statuses, besides 0 for success and 2 for bad usage.
+const EXIT_RUNTIME: i32 = 1;
+const EXIT_PARSE: i32 = 3;
+const EXIT_LIMIT: i32 = 4;
+const EXIT_INTERRUPTED: i32 = 130;
+
+fn exit_code(e: &AlbusError) -> i32 {
+    match e {
+        AlbusError::ParseError { .. }
+        | AlbusError::TruncatedInstruction { .. }
+        | AlbusError::UnknownOpcode { .. }
+        | AlbusError::AsmError { .. }
+        | AlbusError::Decompression { .. }
+        | AlbusError::UndefinedLabel { .. } => EXIT_PARSE,
+        AlbusError::StepLimitExceeded { .. } | AlbusError::ResourceExhausted { .. } | AlbusError::TimedOut { .. } => {
+            EXIT_LIMIT
+        }
+        AlbusError::Interrupted { .. } => EXIT_INTERRUPTED,
+        _ => EXIT_RUNTIME,
+    }
+}
+
+// Reports an error and exits, saying where in the source it happened if that's known, as a
+// line of the assembly if it was assembled.
+fn fail(e: &AlbusError, locations: &[Location], symbols: &Symbols) -> ! {
+    stdout().flush().ok();
+    match (e.ip().and_then(|ip| symbols.at(ip)), e.ip().and_then(|ip| locations.get(ip))) {
+        (Some(at), _) => eprintln!("albus: {} ({})", e, at),
+        (None, Some(at)) => eprintln!("albus: {} ({})", e, at),
+        (None, None) => eprintln!("albus: {}", e),
+    }
+    process::exit(exit_code(e));
+}
+
+// Shows the state, with only the top `depth` values of the stack.
+fn dump(vm: &Machine, out: &mut dyn Write, depth: usize) -> std::io::Result<()> {
+    write!(out, "stack: [")?;
+    if vm.stack.len() > depth {
+        write!(out, ".., ")?;
+    }
+    for v in &vm.stack[vm.stack.len().saturating_sub(depth)..] {
+        write!(out, "{}, ", v)?;
+    }
+    write!(out, "]\nheap: {{")?;
+    let mut heap: Vec<_> = vm.heap.iter().collect();
+    heap.sort();
+    for (k, v) in heap {
+        write!(out, "{}: {}, ", k, v)?;
+    }
+    writeln!(out, "}}\ninsns: {}", vm.steps)
+}
+
+// Why a program stopped, as named in the JSON summary.
+fn exit_reason(vm: &Machine, error: Option<&AlbusError>) -> &'static str {
+    match (error, vm.insns().get(vm.ip)) {
+        (Some(e), _) if exit_code(e) == EXIT_LIMIT => "limit",
+        (Some(AlbusError::Interrupted { .. }), _) => "interrupted",
+        (Some(_), _) => "error",
+        (None, None) => "end",
+        (None, Some(Insn::Ichr)) | (None, Some(Insn::Inum)) => "eof",
+        (None, Some(_)) => "exit",
+    }
+}
+
+// A one-line summary of the final state for scripts to read instead of the text dump.
+fn dump_json(vm: &Machine, error: Option<&AlbusError>, jit: bool) {
+    let mut heap: Vec<_> = vm.heap.iter().collect();
+    heap.sort();
+    let mut fields = vec![
+        ("exit", exit_reason(vm, error).into()),
+        ("stack", vm.stack.iter().map(Json::from).collect::<Vec<_>>().into()),
+        ("heap", Json::Object(heap.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect())),
+        ("steps", vm.steps.into()),
+        // Native code doesn't keep track of this.
+        ("max_stack", if jit { Json::Null } else { vm.max_depth.into() }),
+    ];
+    if let Some(e) = error {
+        fields.insert(1, ("error", e.to_string().into()));
+    }
+    eprintln!("{}", Json::object(fields));
+}
+
+// How many times a loop may come back to the same state before it's taken to be stuck, and
+// how many states are remembered before starting over, so that a long computation that
+// never repeats one doesn't fill memory with them.
+const LOOP_REVISITS: u32 = 10_000;
+const LOOP_STATES: usize = 100_000;
+
+// Watches the jumps a program makes, which every loop has, for it coming back to one with
+// the stack as deep and the same value on top, time and again with no input or output or
+// change to the heap in between. That's not proof it will never stop, since what's further
+// down the stack may be changing, hence only a warning unless asked to abort.
+fn detect_loops(vm: &mut Machine, abort: bool, symbols: Symbols) {
+    let mut seen = HashMap::<(usize, usize, Option<Value>), u32>::new();
+    let mut warned = Vec::new();
+    vm.on_before_insn(move |vm, insn| {
+        match insn {
+            Insn::Ichr | Insn::Inum | Insn::Ochr | Insn::Onum => seen.clear(),
             Insn::Store => {
-                let v = stack.pop().unwrap();
-                let k = stack.pop().unwrap();
-                heap.insert(k, v);
+                let (k, v) = match vm.stack[..] {
+                    [.., ref k, ref v] => (k, v),
+                    _ => return ControlFlow::Continue(()),
+                };
+                if vm.heap.get(k) != Some(v) {
+                    seen.clear();
+                }
             }
-            Insn::Load => {
-                let k = stack.pop().unwrap();
-                stack.push(heap.get(&k).unwrap().clone());
+            Insn::Jump(_) | Insn::Jz(_) | Insn::Jn(_) => {
+                if seen.len() >= LOOP_STATES {
+                    seen.clear();
+                }
+                let visits = seen.entry((vm.ip, vm.stack.len(), vm.stack.last().cloned())).or_default();
+                *visits += 1;
+                if *visits == LOOP_REVISITS && !warned.contains(&vm.ip) {
+                    let message = format!("probable infinite loop at instruction {}", vm.ip);
+                    if abort {
+                        return ControlFlow::Break(message);
+                    }
+                    let at = symbols.at(vm.ip).map_or(String::new(), |at| format!(" ({})", at));
+                    eprintln!("albus: warning: {}{}", message, at);
+                    warned.push(vm.ip);
+                }
             }
-            Insn::Ret => ip = calls.pop().unwrap(),
-            Insn::Ichr => {
-                let k = stack.pop().unwrap();
-                let mut buf = [0u8];
-                stdin().read(&mut buf).ok();
-                heap.insert(k, Num::from(buf[0]));
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    });
+}
+
+// A label given by name or as bits.
+fn label(name: &str, symbols: &Symbols) -> Option<Label> {
+    symbols.label(name).cloned().or_else(|| name.parse::<Label>().ok())
+}
+
+// Starts at a label given by name or as bits, as if it had been called from past the end of
+// the program, so that a subroutine's return finishes the run.
+fn enter<R: Read, W: Write>(vm: &mut Vm<R, W>, entry: &str, symbols: &Symbols) -> bool {
+    let Some(ip) = label(entry, symbols).and_then(|label| vm.labels().get(&label).copied()) else { return false };
+    vm.calls.push(vm.insns().len());
+    vm.ip = ip;
+    true
+}
+
+// Calls deeper than this are more likely runaway recursion than anything a program means to
+// do, and stop it long before it runs out of memory.
+const MAX_CALLS: usize = 10_000_000;
+
+fn limits(options: &Options) -> Limits {
+    let max_calls = match options.limits.max_calls {
+        None => Some(MAX_CALLS),
+        Some(0) => None,
+        n => n,
+    };
+    Limits { max_calls, ..options.limits.clone() }
+}
+
+// Whether the options set limits that the JIT can't keep to. The default limit on calls, or
+// none at all, is as much as it keeps to.
+fn limited(options: &Options) -> bool {
+    let limits = limits(options);
+    let calls = matches!(limits.max_calls, None | Some(MAX_CALLS));
+    !calls || Limits { max_calls: None, ..limits } != Limits::default() || options.timeout.is_some()
+}
+
+// Sets a machine up as the options ask, with any timeout counted from now.
+fn configure<R: Read, W: Write>(vm: &mut Vm<R, W>, options: &Options) {
+    vm.eof = options.eof;
+    vm.trunc_div = options.trunc_div;
+    vm.clamp_args = options.clamp_args;
+    vm.tail_calls = options.tail_calls;
+    vm.int_width = options.int_width;
+    vm.heap.set_kind(options.heap);
+    vm.charset = options.charset;
+    vm.seed(seed(options));
+    vm.limits = limits(options);
+    vm.limits.deadline = options.timeout.map(|t| Instant::now() + t);
+}
+
+// How many of the innermost call sites, and of the values on top of the stack, are shown
+// when a program is stopped.
+const BACKTRACE_FRAMES: usize = 8;
+const STUCK_DEPTH: usize = 32;
+
+// Shows where the innermost calls were made from, with those made again and again from the
+// same place, as runaway recursion is, shown once with how many there were.
+fn backtrace(vm: &Machine, symbols: &Symbols) {
+    let mut frames: Vec<(usize, usize)> = Vec::new();
+    for &ip in vm.calls.iter().rev() {
+        match frames.last_mut() {
+            Some((last, n)) if *last == ip => *n += 1,
+            _ => frames.push((ip, 1)),
+        }
+    }
+    eprintln!("calls: {}", if frames.is_empty() { "none" } else { "innermost first" });
+    for &(ip, n) in frames.iter().take(BACKTRACE_FRAMES) {
+        let call = vm.insns().get(ip).map_or("?".into(), |insn| symbols.show(insn));
+        let at = symbols.at(ip).map_or(String::new(), |at| format!(", {}", at));
+        match n {
+            1 => eprintln!("  {:>6}  {}{}", ip, call, at),
+            n => eprintln!("  {:>6}  {}{} ({} times)", ip, call, at, n),
+        }
+    }
+    let rest: usize = frames.iter().skip(BACKTRACE_FRAMES).map(|&(_, n)| n).sum();
+    if rest > 0 {
+        eprintln!("  ... and {} calls below", rest);
+    }
+}
+
+// Reports how the run went, with counts of each instruction if they were kept, which
+// native code doesn't do, nor keep track of how deep the stacks went or what it allocated.
+fn stats(vm: &Machine, counts: Option<&[u64]>, elapsed: Duration, json: bool) {
+    let mut ops = BTreeMap::<&str, u64>::new();
+    for (insn, &n) in vm.insns().iter().zip(counts.unwrap_or_default()).filter(|(_, &n)| n > 0) {
+        *ops.entry(insn.mnemonic()).or_default() += n;
+    }
+    let mut ops: Vec<_> = ops.into_iter().collect();
+    ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let per_second = (vm.steps as f64 / elapsed.as_secs_f64().max(1e-9)) as u64;
+    let measured = |n: u64| counts.map(|_| n);
+
+    let figures = [
+        ("max_stack", measured(vm.max_depth as u64)),
+        ("max_calls", measured(vm.max_call_depth as u64)),
+        ("heap", Some(vm.heap.len() as u64)),
+        ("big_bytes", measured(vm.big_allocated)),
+    ];
+    if json {
+        let mut fields = vec![
+            ("steps", vm.steps.into()),
+            ("microseconds", (elapsed.as_micros() as u64).into()),
+            ("per_second", per_second.into()),
+        ];
+        fields.extend(figures.iter().map(|&(name, n)| (name, n.map_or(Json::Null, Json::from))));
+        let ops = ops.into_iter().map(|(op, n)| (op.to_string(), n.into())).collect();
+        fields.push(("ops", if counts.is_some() { Json::Object(ops) } else { Json::Null }));
+        eprintln!("{}", Json::object(fields));
+        return;
+    }
+    eprintln!("albus: {} instructions in {:.2?}, {} a second", vm.steps, elapsed, per_second);
+    let names = ["max stack depth", "max call depth", "heap entries", "bignum bytes"];
+    for (name, (_, n)) in names.iter().zip(figures) {
+        if let Some(n) = n {
+            eprintln!("  {:<16} {}", name, n);
+        }
+    }
+    for (op, n) in ops {
+        eprintln!("  {:<16} {}", op, n);
+    }
+}
+
+// How many values from the top of the stack each trace line shows.
+const TRACE_DEPTH: usize = 4;
+
+// Shows the instruction about to run alongside the values it will find on the stack.
+fn trace(vm: &Machine, locations: &[Location], symbols: &Symbols, json: bool) {
+    let insn = match vm.current() {
+        Some(Insn::Label(_)) | Some(Insn::None) | None => return,
+        Some(insn) => insn,
+    };
+    let top = &vm.stack[vm.stack.len().saturating_sub(TRACE_DEPTH)..];
+    if json {
+        let mut fields = vec![("ip", vm.ip.into()), ("op", insn.mnemonic().into())];
+        if let Some(n) = insn.arg() {
+            fields.push(("arg", n.into()));
+        } else if let Some(l) = insn.label() {
+            fields.push(("arg", l.to_string().into()));
+            if let Some(name) = symbols.name(l) {
+                fields.push(("name", name.into()));
             }
-            Insn::Inum => {
-                let k = stack.pop().unwrap();
-                let mut n = String::new();
-                stdin().read_line(&mut n).ok();
-                heap.insert(k, n.trim_end().parse::<Num>().unwrap());
+        }
+        if let Some(at) = locations.get(vm.ip) {
+            fields.extend([("line", at.line.into()), ("column", at.column.into())]);
+        }
+        fields.push(("depth", vm.stack.len().into()));
+        fields.push(("top", top.iter().map(Json::from).collect::<Vec<_>>().into()));
+        eprintln!("{}", Json::object(fields));
+        return;
+    }
+    let mut values: Vec<_> = top.iter().map(ToString::to_string).collect();
+    if top.len() < vm.stack.len() {
+        values.insert(0, "..".into());
+    }
+    match locations.get(vm.ip) {
+        Some(at) => {
+            let at = format!("{}:{}", at.line, at.column);
+            eprintln!("{:>6}  {:>9}  {:<16} [{}]", vm.ip, at, symbols.show(insn), values.join(", "))
+        }
+        None => eprintln!("{:>6}  {:<16} [{}]", vm.ip, symbols.show(insn), values.join(", ")),
+    }
+}
+
+// Saves a snapshot by replacing the file, so that stopping partway through writing one
+// never loses the last.
+fn checkpoint(vm: &mut Machine, path: &Path) -> albus::Result<()> {
+    vm.output.flush().ok();
+    let tmp = path.with_extension("tmp");
+    writing(fs::write(&tmp, snapshot::save(vm)?), tmp.display());
+    writing(fs::rename(&tmp, path), path.display());
+
+    Ok(())
+}
+
+// Set on SIGINT, so that an interrupted program can say where it was before it exits, and
+// on SIGUSR1, to ask a running one where it is.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INSPECT: AtomicBool = AtomicBool::new(false);
+
+// Reads aren't restarted after an interrupt, so a program waiting on input stops too, while
+// one waiting on input when asked where it is answers once it has read some.
+#[cfg(unix)]
+fn catch_signals() {
+    extern "C" fn interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn inspect(_: libc::c_int) {
+        INSPECT.store(true, Ordering::Relaxed);
+    }
+    let handler: extern "C" fn(libc::c_int) = interrupt;
+    let handlers = [(libc::SIGINT, handler, 0), (libc::SIGUSR1, inspect, libc::SA_RESTART)];
+    for (signal, handler, flags) in handlers {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = flags;
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_signals() {}
+
+fn interpret(
+    vm: &mut Machine,
+    options: &Options,
+    locations: &[Location],
+    symbols: &Symbols,
+    mut profiler: Option<&mut Profiler>,
+    snapshot: &Path,
+) -> albus::Result<()> {
+    let mut checkpointed = vm.steps;
+    loop {
+        if options.trace {
+            trace(vm, locations, symbols, options.trace_json);
+        }
+        // Labels aren't counted as instructions run.
+        let counted = !matches!(vm.current(), Some(Insn::Label(_)) | Some(Insn::None) | None);
+        if let Some(profiler) = profiler.as_deref_mut().filter(|_| counted && options.flamegraph.is_some()) {
+            profiler.record_calls(&vm.calls);
+        }
+        let (ip, steps, start) = (vm.ip, vm.steps, Instant::now());
+        let result = vm.step();
+        // An instruction that fails still ran, as far as coverage is concerned. Both halves
+        // of a fused pair ran, with the time going to the first.
+        if let Some(profiler) = profiler.as_deref_mut().filter(|_| vm.steps > steps) {
+            profiler.record(ip, start.elapsed());
+            if vm.steps > steps + 1 {
+                profiler.record(ip + 1, Duration::ZERO);
             }
-            Insn::Ochr => {
-                print!("{}", stack.pop().unwrap().to_u8().unwrap() as char);
+        }
+        // Input instructions that were waiting when it came fail, which is just as well.
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            return Err(AlbusError::Interrupted { ip: vm.ip, steps: vm.steps });
+        }
+        if INSPECT.swap(false, Ordering::Relaxed) {
+            eprintln!(
+                "albus: at instruction {} after {} instructions, stack depth {}, heap size {}",
+                vm.ip,
+                vm.steps,
+                vm.stack.len(),
+                vm.heap.len()
+            );
+        }
+        let running = result?;
+        // Fused pairs take two steps at once, so a count can be passed over without landing
+        // on it.
+        if options.checkpoint_every.is_some_and(|n| vm.steps - checkpointed >= n) {
+            checkpoint(vm, snapshot)?;
+            checkpointed = vm.steps;
+        }
+        if !running {
+            return Ok(());
+        }
+    }
+}
+
+// The program's input, and what it has read so far if that is being recorded.
+fn recorded_input(options: &Options) -> (Box<dyn Read>, Rc<RefCell<Vec<u8>>>) {
+    let recorded = Rc::new(RefCell::new(Vec::new()));
+    let input = input(options);
+    if options.record_io.is_none() {
+        return (input, recorded);
+    }
+    (Box::new(Recorder { input, bytes: recorded.clone() }), recorded)
+}
+
+fn run(path: &str, options: &Options) -> albus::Result<()> {
+    launch(load_file(path, options)?, path, options)
+}
+
+// Runs a loaded program, with `path` saying where it came from.
+fn launch(parsed: Parsed, path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { mut insns, mut labels, mut locations, .. } = parsed;
+    // Optimized instructions no longer line up with where they came from, and a run that
+    // starts elsewhere needs what it reaches kept.
+    if options.optimize {
+        let entry = options.entry.as_deref().and_then(|entry| label(entry, &symbols(path, options)));
+        (insns, labels) = optimize_with_roots(&insns, entry.as_slice());
+        locations.clear();
+    }
+    let (input, recorded) = recorded_input(options);
+    let vm = Vm::with_io(insns, labels, input, output(options));
+    let mut vm = vm.unwrap_or_else(|e| fail(&e, &locations, &Symbols::new()));
+    if options.fuse {
+        vm.fuse();
+    }
+    let snapshot = match options.out.as_deref() {
+        Some(out) => PathBuf::from(out),
+        None if path == "-" => PathBuf::from("albus.snapshot"),
+        None => Path::new(path).with_extension("snapshot"),
+    };
+    execute(vm, options, path, &locations, &recorded, &snapshot)
+}
+
+// Carries on running a program from a snapshot, by default saving later ones over it.
+fn resume(path: &str, options: &Options) -> albus::Result<()> {
+    let (input, recorded) = recorded_input(options);
+    let vm = snapshot::restore(&read(path), input, output(options))?;
+    // Random numbers carry on from where they had got to unless given a new --seed.
+    let options = &Options { seed: options.seed.or(Some(vm.rng_state())), ..options.clone() };
+    execute(vm, options, path, &[], &recorded, Path::new(options.out.as_deref().unwrap_or(path)))
+}
+
+// A heap file has an address and its value on each line. One that doesn't exist yet is an
+// empty heap, so that the first run can create it.
+fn load_heap(path: &str) -> Vec<(Value, Value)> {
+    let src = match fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        result => reading(result, path),
+    };
+    let cell = |line: &str| {
+        let mut fields = line.split_whitespace().map(|f| f.parse::<Num>().map(Value::from));
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(k)), Some(Ok(v)), None) => Some((k, v)),
+            _ => None,
+        }
+    };
+    let lines = src.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    lines
+        .map(|(i, line)| {
+            cell(line).unwrap_or_else(|| {
+                eprintln!("albus: line {} of {} isn't an address and a value", i + 1, path);
+                process::exit(2);
+            })
+        })
+        .collect()
+}
+
+// Written only once the program has finished without an error, and by replacing the file,
+// so that a failed run never leaves state half updated.
+fn save_heap(path: &str, vm: &Machine) {
+    let mut cells: Vec<_> = vm.heap.iter().collect();
+    cells.sort();
+    let text: String = cells.into_iter().map(|(k, v)| format!("{} {}\n", k, v)).collect();
+    let tmp = format!("{}.tmp", path);
+    writing(fs::write(&tmp, text), &tmp);
+    writing(fs::rename(&tmp, path), path);
+}
+
+// The program is named by `source` where it's reported on.
+fn execute(
+    mut vm: Machine,
+    options: &Options,
+    source: &str,
+    locations: &[Location],
+    recorded: &RefCell<Vec<u8>>,
+    snapshot: &Path,
+) -> albus::Result<()> {
+    let start = Instant::now();
+    // The session is saved however the program stops, since that is when it's wanted most.
+    let save = || {
+        if let Some(path) = &options.record_io {
+            record(path, &recorded.borrow());
+        }
+    };
+    configure(&mut vm, options);
+    if let Some(path) = &options.heap_file {
+        vm.heap.extend(load_heap(path));
+    }
+
+    let mut symbols = symbols(source, options);
+    // Optimized instructions no longer line up with the lines they came from.
+    if options.optimize {
+        symbols.clear_lines();
+    }
+    if let Some(entry) = &options.entry {
+        if !enter(&mut vm, entry, &symbols) {
+            eprintln!("albus: there's no label `{}` to start at", entry);
+            process::exit(2);
+        }
+    }
+    if let Some(values) = &options.initial_stack {
+        vm.stack.extend(values.iter().cloned());
+    }
+
+    let mut profiler = Profiler::new(vm.insns().len());
+    let mut vm = if options.jit {
+        let limited = limited(options);
+        let measured = options.profile || options.coverage.is_some() || options.flamegraph.is_some();
+        if options.trace || measured || limited || options.checkpoint_every.is_some() || options.loops.is_some() {
+            eprintln!("albus: --jit can't be combined with tracing, profiling, coverage, limits, checkpoints or loop detection");
+            process::exit(2);
+        }
+        let result = native(vm);
+        save();
+        result.unwrap_or_else(|e| fail(&e, locations, &symbols))
+    } else {
+        catch_signals();
+        if let Some(abort) = options.loops {
+            detect_loops(&mut vm, abort, symbols.clone());
+        }
+        let traced = if options.locations { locations } else { &[] };
+        let measured = options.profile || options.coverage.is_some() || options.flamegraph.is_some() || options.stats;
+        let profiling = Some(&mut profiler).filter(|_| measured);
+        let result = interpret(&mut vm, options, traced, &symbols, profiling, snapshot);
+        save();
+        if let Some(path) = &options.coverage {
+            cover(path, vm.insns(), &profiler.counts, source);
+        }
+        if let Some(path) = &options.flamegraph {
+            writing(fs::write(path, profiler.folded(vm.insns())), path);
+        }
+        if options.profile {
+            vm.output.flush().ok();
+            profiler.report(vm.insns(), &mut stderr()).ok();
+        }
+        // A program stopped by a limit or interrupted is likely stuck, so show where and in
+        // what state.
+        if let Err(AlbusError::StepLimitExceeded { .. })
+        | Err(AlbusError::ResourceExhausted { .. })
+        | Err(AlbusError::TimedOut { .. })
+        | Err(AlbusError::Interrupted { .. }) = result
+        {
+            vm.output.flush().ok();
+            eprintln!("ip: {}", vm.ip);
+            backtrace(&vm, &symbols);
+            // Runaway recursion tends to leave a deep stack, of which the top is enough.
+            dump(&vm, &mut stderr(), STUCK_DEPTH).ok();
+        }
+        if let Err(e) = result {
+            vm.output.flush().ok();
+            if options.dump_json {
+                dump_json(&vm, Some(&e), false);
             }
-            Insn::Onum => print!("{}", stack.pop().unwrap()),
-            Insn::Exit => break,
+            fail(&e, locations, &symbols);
         }
-        ip += 1;
+        vm
+    };
+
+    vm.output.flush().ok();
+    if let Some(path) = &options.heap_file {
+        save_heap(path, &vm);
+    }
+    if options.dump_state {
+        dump(&vm, &mut stderr(), usize::MAX).ok();
+    }
+    if options.dump_json {
+        dump_json(&vm, None, options.jit);
+    }
+    if options.stats {
+        let counts = Some(&profiler.counts[..]).filter(|_| !options.jit);
+        stats(&vm, counts, start.elapsed(), options.stats_json);
+    }
+    // Only the low byte of a status survives, so reduce it here rather than leave it to the
+    // platform.
+    if let Some(top) = vm.stack.last().filter(|_| options.exit_code) {
+        let status = top.mod_floor(&Value::Small(256));
+        process::exit(status.to_usize().unwrap_or(0) as i32);
     }
 
-    (stack, heap, n)
+    Ok(())
 }
 
-fn main() {
-    let path = env::args().nth(1).expect("usage: albus FILE");
-    let mut src = fs::read_to_string(path).expect("unable to read file!");
-    let (insns, labels) = parse(&mut src);
+#[cfg(feature = "jit")]
+fn native<R: Read, W: Write>(vm: Vm<R, W>) -> albus::Result<Vm<R, W>> {
+    albus::jit::run(vm)
+}
+
+#[cfg(not(feature = "jit"))]
+fn native<R: Read, W: Write>(_: Vm<R, W>) -> albus::Result<Vm<R, W>> {
+    eprintln!("albus: this build does not include the JIT (rebuild with --features jit)");
+    process::exit(2);
+}
 
-    let (stack, heap, n) = interpret(insns, labels);
+// Counts are added to those already in the tracefile, so that it covers a set of runs.
+fn cover(path: &str, insns: &[Insn], counts: &[u64], source: &str) {
+    let mut total = fs::read_to_string(path).map_or_else(|_| vec![0; insns.len()], |src| coverage::read_lcov(insns, &src));
+    for (total, n) in total.iter_mut().zip(counts) {
+        *total += n;
+    }
+    writing(fs::write(path, coverage::lcov(insns, &total, source)), path);
+}
 
-    print!("stack: [");
-    for v in stack {
-        print!("{}, ", v);
+// Shows which instructions the runs in the tracefiles executed.
+fn covered(path: &str, traces: &[&str], options: &Options) -> albus::Result<()> {
+    let Parsed { insns, .. } = load_file(path, options)?;
+    let mut counts = vec![0; insns.len()];
+    for trace in traces {
+        let src = String::from_utf8_lossy(&read(trace)).into_owned();
+        for (total, n) in counts.iter_mut().zip(coverage::read_lcov(&insns, &src)) {
+            *total += n;
+        }
     }
-    print!("]\nheap: {{");
-    for (k, v) in heap {
-        print!("{}: {}, ", k, v);
+    print!("{}", coverage::annotate(&insns, &counts));
+    let (hit, total) = coverage::summary(&insns, &counts);
+    let percent = if total == 0 { 100.0 } else { hit as f64 * 100.0 / total as f64 };
+    println!("{} of {} instructions executed ({:.1}%)", hit, total, percent);
+
+    Ok(())
+}
+
+// The runs of a program under one configuration.
+struct Bench {
+    times: Vec<Duration>,
+    steps: u64,
+    max_depth: usize,
+    heap: usize,
+}
+
+const BENCH_RUNS: usize = 10;
+
+const BENCH_ROWS: [&str; 10] =
+    ["runs", "instructions", "instructions/s", "min", "p50", "p90", "p99", "max", "peak stack", "heap addresses"];
+
+impl Bench {
+    // What's reported for these runs, in the order of BENCH_ROWS.
+    fn column(&self) -> Vec<String> {
+        let runs = self.times.len();
+        let total: Duration = self.times.iter().sum();
+        let percentile = |q: f64| format!("{:.2?}", self.times[((runs - 1) as f64 * q).round() as usize]);
+        vec![
+            runs.to_string(),
+            self.steps.to_string(),
+            format!("{:.0}", (self.steps * runs as u64) as f64 / total.as_secs_f64()),
+            percentile(0.0),
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(1.0),
+            self.max_depth.to_string(),
+            self.heap.to_string(),
+        ]
+    }
+}
+
+// Runs a program on the given input, returning what it wrote and the error that stopped it
+// if one did. Loading it failing is an error in itself.
+fn capture(path: &Path, input: Vec<u8>, options: &Options) -> albus::Result<(Vec<u8>, Option<AlbusError>)> {
+    let mut vm = prepare(path, input, options, &[])?;
+    let error = vm.run().err();
+
+    Ok((vm.output, error))
+}
+
+// Optimizing keeps whatever can be reached from the start or from any of the `roots`.
+fn prepare(
+    path: &Path,
+    input: Vec<u8>,
+    options: &Options,
+    roots: &[Label],
+) -> albus::Result<Vm<Cursor<Vec<u8>>, Vec<u8>>> {
+    let src = reading(fs::read(path), path.display());
+    let Parsed { mut insns, mut labels, .. } = load_source(&src, &options.parse)?;
+    if options.optimize {
+        (insns, labels) = optimize_with_roots(&insns, roots);
+    }
+    let mut vm = Vm::with_io(insns, labels, Cursor::new(input), Vec::new())?;
+    configure(&mut vm, options);
+    if options.fuse {
+        vm.fuse();
+    }
+
+    Ok(vm)
+}
+
+// Where the output first differs from what was expected, as the line and both versions of it.
+fn difference(expected: &[u8], got: &[u8]) -> String {
+    let (mut expected, mut got) = (expected.split(|&b| b == b'\n'), got.split(|&b| b == b'\n'));
+    for line in 1.. {
+        match (expected.next(), got.next()) {
+            (Some(a), Some(b)) if a == b => continue,
+            (a, b) => {
+                let show = |l: Option<&[u8]>| {
+                    l.map_or("end of output".into(), |l| format!("{:?}", String::from_utf8_lossy(l)))
+                };
+                return format!("line {}: expected {}, got {}", line, show(a), show(b));
+            }
+        }
+    }
+    unreachable!()
+}
+
+// Runs every `.ws` file in the directory, given the `.in` file beside it if there is one, and
+// checks what each writes against the `.out` file beside it, or with --update saves what each
+// writes there instead.
+fn golden(dir: &str, options: &Options) -> albus::Result<()> {
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("albus: unable to read {}: {}", dir, e);
+        process::exit(2);
+    });
+    let mut programs: Vec<_> =
+        entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "ws")).collect();
+    programs.sort();
+
+    let mut failed = 0;
+    for path in &programs {
+        let expected = path.with_extension("out");
+        let input = fs::read(path.with_extension("in")).unwrap_or_default();
+        let verdict = match (capture(path, input, options), fs::read(&expected)) {
+            (Err(e), _) | (Ok((_, Some(e))), _) => Err(e.to_string()),
+            (Ok((got, None)), _) if options.update => {
+                writing(fs::write(&expected, got), expected.display());
+                Ok("updated")
+            }
+            (Ok(_), Err(_)) => Err(format!("no {} to check against; run with --update", expected.display())),
+            (Ok((got, None)), Ok(want)) if got == want => Ok("ok"),
+            (Ok((got, None)), Ok(want)) => Err(difference(&want, &got)),
+        };
+        match verdict {
+            Ok(verdict) => println!("{} ... {}", path.display(), verdict),
+            Err(reason) => {
+                failed += 1;
+                println!("{} ... FAILED\n    {}", path.display(), reason);
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", programs.len() - failed, failed);
+    if failed > 0 {
+        process::exit(EXIT_RUNTIME);
+    }
+    Ok(())
+}
+
+// Runs the subroutine tests in each FILE.toml in the directory, checking the stack each
+// call leaves and what it writes.
+fn units(dir: &str, options: &Options) -> albus::Result<()> {
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("albus: unable to read {}: {}", dir, e);
+        process::exit(2);
+    });
+    let mut files: Vec<_> =
+        entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "toml")).collect();
+    files.sort();
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        let src = reading(fs::read_to_string(file), file.display());
+        let suite = match parse_units(&src) {
+            Ok(suite) => suite,
+            Err(e) => {
+                failed += 1;
+                println!("{} ... FAILED\n    {}", file.display(), e);
+                continue;
+            }
+        };
+        let program = match &suite.program {
+            Some(program) => file.with_file_name(program),
+            None => file.with_extension("ws"),
+        };
+        let symbols = symbols(&program.to_string_lossy(), options);
+        for (i, test) in suite.tests.iter().enumerate() {
+            let name = if test.name.is_empty() { format!("test {}", i + 1) } else { test.name.clone() };
+            match unit(&program, test, &symbols, options) {
+                Ok(()) => {
+                    passed += 1;
+                    println!("{}: {} ... ok", file.display(), name);
+                }
+                Err(reason) => {
+                    failed += 1;
+                    println!("{}: {} ... FAILED\n    {}", file.display(), name, reason);
+                }
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        process::exit(EXIT_RUNTIME);
+    }
+    Ok(())
+}
+
+fn unit(program: &Path, test: &UnitTest, symbols: &Symbols, options: &Options) -> Result<(), String> {
+    let roots: Vec<_> = label(&test.call, symbols).into_iter().collect();
+    let mut vm = prepare(program, test.input.clone().into_bytes(), options, &roots).map_err(|e| e.to_string())?;
+    if !enter(&mut vm, &test.call, symbols) {
+        return Err(format!("there's no label `{}` to call", test.call));
+    }
+    vm.stack = test.stack.iter().map(Value::from).collect();
+    vm.run().map_err(|e| e.to_string())?;
+
+    if let Some(want) = &test.expect_output {
+        if want.as_bytes() != vm.output {
+            return Err(difference(want.as_bytes(), &vm.output));
+        }
+    }
+    let show = |stack: &[Num]| stack.iter().map(Num::to_string).collect::<Vec<_>>().join(", ");
+    let stack: Vec<Num> = vm.stack.into_iter().map(Num::from).collect();
+    match &test.expect_stack {
+        Some(want) if *want != stack => Err(format!("expected the stack [{}], got [{}]", show(want), show(&stack))),
+        _ => Ok(()),
+    }
+}
+
+// Runs the program both here and with another interpreter, given `--against` as a command
+// to run with the program's path after it, on the same input, and reports whether what they
+// write or whether they fail differs.
+fn difftest(path: &str, options: &Options) -> albus::Result<()> {
+    let Some(against) = &options.against else {
+        eprintln!("albus: difftest needs an interpreter to compare against with --against");
+        process::exit(2);
+    };
+    let mut input = Vec::new();
+    if let Err(e) = self::input(options).read_to_end(&mut input) {
+        eprintln!("albus: unable to read input: {}", e);
+        process::exit(1);
+    }
+    let (ours, error) = capture(Path::new(path), input.clone(), options)?;
+
+    let mut words = against.split_whitespace();
+    let program = words.next().unwrap_or_else(|| usage());
+    let mut child = process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| {
+            eprintln!("albus: unable to run {}: {}", program, e);
+            process::exit(2);
+        });
+    // Written from another thread so that neither side waits on the other with a pipe full.
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let theirs = child.wait_with_output().expect("unable to wait for the interpreter!");
+    writer.join().ok();
+
+    let mut diverged = false;
+    if ours != theirs.stdout {
+        diverged = true;
+        println!("output differs at {}", difference(&theirs.stdout, &ours));
+    }
+    let status = theirs.status.code().map_or("was killed by a signal".into(), |code| format!("exited with {}", code));
+    match (&error, theirs.status.success()) {
+        (None, false) => println!("albus finished, but {} {}", program, status),
+        (Some(e), true) => println!("albus stopped with `{}`, but {} {}", e, program, status),
+        _ => {}
+    }
+    diverged |= error.is_some() == theirs.status.success();
+    if diverged {
+        process::exit(EXIT_RUNTIME);
+    }
+    println!("albus and {} agree", program);
+
+    Ok(())
+}
+
+// Runs a program the number of times or for as long as `runs` asks, with the same input
+// each time and its output thrown away.
+fn measure(path: &str, options: &Options, runs: &Options, input: &[u8]) -> albus::Result<Bench> {
+    if options.jit && limited(options) {
+        eprintln!("albus: --jit can't be combined with limits");
+        process::exit(2);
+    }
+    let Parsed { mut insns, mut labels, .. } = load_file(path, options)?;
+    if options.optimize {
+        (insns, labels) = optimize(&insns);
+    }
+    let deadline = runs.bench_for.map(|t| Instant::now() + t);
+    let mut bench = Bench { times: Vec::new(), steps: 0, max_depth: 0, heap: 0 };
+
+    loop {
+        let mut vm = Vm::with_io(insns.clone(), labels.clone(), Cursor::new(input), std::io::sink())?;
+        configure(&mut vm, options);
+        if options.fuse {
+            vm.fuse();
+        }
+        let start = Instant::now();
+        let vm = if options.jit {
+            native(vm)?
+        } else {
+            vm.run()?;
+            vm
+        };
+        bench.times.push(start.elapsed());
+        bench.steps = vm.steps;
+        bench.max_depth = bench.max_depth.max(vm.max_depth);
+        bench.heap = bench.heap.max(vm.heap.len());
+
+        let done = match deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => bench.times.len() >= runs.runs.unwrap_or(BENCH_RUNS),
+        };
+        if done {
+            bench.times.sort();
+            return Ok(bench);
+        }
+    }
+}
+
+// The whole process's peak resident memory in bytes, which covers every run so far.
+#[cfg(unix)]
+fn peak_memory() -> Option<u64> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    let scale = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    (unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } == 0).then(|| usage.ru_maxrss as u64 * scale)
+}
+
+#[cfg(not(unix))]
+fn peak_memory() -> Option<u64> {
+    None
+}
+
+// Times a program over several runs, and alongside it the same program run with the flags
+// given to --compare, which default to those of the first apart from the input.
+fn bench(path: &str, options: &Options) -> albus::Result<()> {
+    let mut input = Vec::new();
+    if let Err(e) = self::input(options).read_to_end(&mut input) {
+        eprintln!("albus: unable to read input: {}", e);
+        process::exit(1);
+    }
+
+    let mut configs = vec![("default".to_string(), measure(path, options, options, &input)?)];
+    if let Some(flags) = &options.compare {
+        let args: Vec<_> = flags.split_whitespace().map(String::from).collect();
+        let (compared, _) = parse_args(&args, options.clone());
+        configs.push((flags.clone(), measure(path, &compared, options, &input)?));
+    }
+
+    let header: String = configs.iter().map(|(name, _)| format!(" {:>14}", name)).collect();
+    println!("{:<16}{}", "", header);
+    let columns: Vec<_> = configs.iter().map(|(_, b)| b.column()).collect();
+    for (row, name) in BENCH_ROWS.iter().enumerate() {
+        let cells: String = columns.iter().map(|column| format!(" {:>14}", column[row])).collect();
+        println!("{:<16}{}", name, cells);
+    }
+    if let Some(bytes) = peak_memory() {
+        println!("peak memory: {:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+    }
+
+    Ok(())
+}
+
+// Loads the program and resolves its labels without running it.
+fn check(path: &str, options: &Options) -> albus::Result<()> {
+    let bytes = read(path);
+    let mut diagnostics = check_source(&bytes, &options.parse)?;
+    let lenient = ParseOptions { lenient: true, ..options.parse.clone() };
+    let Parsed { insns, labels, .. } = load_source(&bytes, &lenient)?;
+    if options.stack {
+        diagnostics.extend(check_stack(&insns, &labels));
+        diagnostics.sort_by_key(|d| d.ip);
+    }
+    for d in &diagnostics {
+        eprintln!("{}: {}", path, d);
+    }
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        process::exit(1);
+    }
+
+    println!("{}: ok, {} instructions", path, insns.len());
+
+    Ok(())
+}
+
+// Saves the names labels were given and the lines instructions came from beside the output,
+// so that running, debugging or disassembling it can show them.
+fn asm(path: &str, options: &Options) -> albus::Result<()> {
+    let src = String::from_utf8_lossy(&read(path)).into_owned();
+    let mut assembler = Assembler::new();
+    // Includes in a program from stdin are relative to the current directory.
+    let lines = if path == "-" { assembler.source_lines(&src)? } else { assembler.file_lines(Path::new(path), &src)? };
+    let (lines, insns): (Vec<_>, Vec<_>) = lines.into_iter().unzip();
+    write_source(&emit(&insns), options);
+    let beside = |out: &str| Path::new(out).with_extension("wsmap");
+    let map = options.map.as_ref().map(PathBuf::from).or_else(|| options.out.as_deref().map(beside));
+    if let Some(map) = map {
+        let mut symbols = assembler.symbols();
+        symbols.set_lines(Some(path.to_string()).filter(|p| p != "-"), lines);
+        writing(fs::write(&map, symbols.to_string()), map.display());
+    }
+
+    Ok(())
+}
+
+fn disasm(path: &str, options: &Options) -> albus::Result<()> {
+    let parsed = load_file(path, options)?;
+    let locations = if options.locations { &parsed.locations[..] } else { &[] };
+    print!("{}", disassemble_named(&parsed.insns, locations, &symbols(path, options)));
+
+    Ok(())
+}
+
+// Writes the source as plain whitespace, checking the program stays the same.
+fn format(path: &str, options: &Options) -> albus::Result<()> {
+    let src = format_source(&read(path), &options.parse, options.canonical)?;
+    write_source(&src, options);
+
+    Ok(())
+}
+
+// Hides the program at the ends of the carrier's lines.
+fn hide(carrier: &str, path: &str, options: &Options) -> albus::Result<()> {
+    let program = format_source(&read(path), &options.parse, false)?;
+    write_source(&embed(&String::from_utf8_lossy(&read(carrier)), program.as_bytes()), options);
+
+    Ok(())
+}
+
+// Writes out the program hidden in a file if there's somewhere to write it, and otherwise
+// runs it.
+fn reveal(path: &str, options: &Options) -> albus::Result<()> {
+    let program = extract(&String::from_utf8_lossy(&read(path)))?;
+    if options.out.is_some() {
+        write_source(&String::from_utf8_lossy(&program), options);
+        return Ok(());
+    }
+    let parse = ParseOptions { alphabet: Default::default(), ..options.parse.clone() };
+    launch(parse_source(&program, &parse)?, path, options)
+}
+
+fn graph(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    print!("{}", cfg(&insns, &labels));
+
+    Ok(())
+}
+
+fn registers(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { mut insns, mut labels, .. } = load_file(path, options)?;
+    if options.optimize {
+        (insns, labels) = optimize(&insns);
+    }
+    print!("{}", ir::lower(&insns, &labels)?);
+
+    Ok(())
+}
+
+// Writes generated Whitespace to the output file, or stdout if none was given.
+fn write_source(src: &str, options: &Options) {
+    match options.out.as_deref() {
+        Some(out) => writing(fs::write(out, src), out),
+        None => print!("{}", src),
+    }
+}
+
+fn optimized(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns: before, .. } = load_file(path, options)?;
+    let (insns, _) = optimize(&before);
+    let src = emit(&insns);
+    write_source(&src, options);
+    let saved = emit(&before).len().saturating_sub(src.len());
+    eprintln!("albus: removed {} instructions, saving {} bytes", before.len() - insns.len(), saved);
+
+    Ok(())
+}
+
+fn minified(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    write_source(&emit(&minify(&insns, &labels)), options);
+
+    Ok(())
+}
+
+// Programs to start from, as assembly so they can be read.
+const EXAMPLES: [(&str, &str, &str); 4] = [
+    ("hello", "print a greeting", include_str!("../examples/hello.wsa")),
+    ("count", "count from 1 to 10", include_str!("../examples/count.wsa")),
+    ("factorial", "print factorials past 64 bits", include_str!("../examples/factorial.wsa")),
+    ("hanoi", "solve the towers of Hanoi", include_str!("../examples/hanoi.wsa")),
+];
+
+// Runs an example, or prints it as assembly or Whitespace.
+fn example(name: &str, options: &Options) -> albus::Result<()> {
+    let src = match EXAMPLES.iter().find(|(n, _, _)| *n == name) {
+        Some((_, _, src)) => src,
+        None => {
+            let names: Vec<_> = EXAMPLES.iter().map(|(n, _, _)| *n).collect();
+            eprintln!("albus: no example called `{}`; try one of {}", name, names.join(", "));
+            process::exit(2);
+        }
+    };
+    let ws = emit(&assemble(src)?);
+    match options.print.as_deref() {
+        None => launch(load_source(ws.as_bytes(), &options.parse)?, name, options),
+        Some("asm") => {
+            write_source(src, options);
+            Ok(())
+        }
+        Some("ws") => {
+            write_source(&ws, options);
+            Ok(())
+        }
+        Some(format) => {
+            eprintln!("albus: can't print an example as `{}`; use asm or ws", format);
+            process::exit(2);
+        }
+    }
+}
+
+// The seed given, or else one picked from the clock.
+fn seed(options: &Options) -> u64 {
+    let now = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    options.seed.unwrap_or_else(|| now().as_nanos() as u64)
+}
+
+// A seed picked from the clock is reported so the program can be made again.
+fn gen(options: &Options) -> albus::Result<()> {
+    let seed = seed(options);
+    if options.seed.is_none() {
+        eprintln!("albus: seed {}", seed);
+    }
+    write_source(&emit(&generate(seed, options.size.unwrap_or(100))), options);
+
+    Ok(())
+}
+
+// Compiles Brainfuck to Whitespace.
+fn brainfuck(path: &str, options: &Options) -> albus::Result<()> {
+    write_source(&emit(&from_bf(&read(path))?), options);
+
+    Ok(())
+}
+
+fn compile(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    let (ext, bytes) = match options.target.as_deref().unwrap_or("albc") {
+        "albc" => ("albc", bytecode::encode(&insns, &labels)?),
+        "wasm" => ("wasm", wasm::compile(&insns, &labels)?),
+        target => {
+            eprintln!("albus: unknown compile target `{}`", target);
+            process::exit(2);
+        }
+    };
+    // A program read from stdin has no name to derive one for its output from.
+    match options.out.as_deref() {
+        None if path == "-" => writing(stdout().write_all(&bytes), "stdout"),
+        out => {
+            let out = out.map_or_else(|| Path::new(path).with_extension(ext), PathBuf::from);
+            writing(fs::write(&out, bytes), out.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn transpile(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    match options.target.as_deref() {
+        Some("c") => print!("{}", transpile::c(&insns, &labels)),
+        Some("rust") => print!("{}", transpile::rust(&insns, &labels)),
+        Some(target) => {
+            eprintln!("albus: unknown transpile target `{}`", target);
+            process::exit(2);
+        }
+        None => {
+            eprintln!("albus: transpile needs a --target");
+            process::exit(2);
+        }
+    }
+
+    Ok(())
+}
+
+fn debug(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    let mut vm = Vm::with_io(insns, labels, input(options), stdout())?;
+    configure(&mut vm, options);
+    let mut debugger = Debugger::new(vm);
+    debugger.symbols = symbols(path, options);
+    match &options.listen {
+        Some(addr) => {
+            let listener = listen(addr, "debugger");
+            let (stream, client) = listener.accept().expect("unable to accept connection");
+            eprintln!("albus: debugger attached from {}", client);
+            let mut input = BufReader::new(&stream);
+            debugger.session(&mut |line| input.read_line(line), &mut &stream).ok();
+        }
+        None => {
+            debugger.session(&mut |line| stdin().read_line(line), &mut stdout()).ok();
+        }
+    }
+
+    Ok(())
+}
+
+// The client only passes lines along, so that the session looks the same as a local one,
+// and stops when the debugger hangs up.
+fn attach(addr: &str) -> albus::Result<()> {
+    let stream = TcpStream::connect(addr).unwrap_or_else(|e| {
+        eprintln!("albus: unable to attach to {}: {}", addr, e);
+        process::exit(1);
+    });
+    let mut commands = stream.try_clone().expect("unable to share the connection");
+    std::thread::spawn(move || {
+        std::io::copy(&mut stdin(), &mut commands).ok();
+        commands.shutdown(Shutdown::Write).ok();
+    });
+    std::io::copy(&mut &stream, &mut stdout()).ok();
+
+    Ok(())
+}
+
+// The terminal is for keys, so the program only has input if it's given some.
+#[cfg(unix)]
+fn tui(path: &str, options: &Options) -> albus::Result<()> {
+    let Parsed { insns, labels, .. } = load_file(path, options)?;
+    let given = options.input.is_some() || options.input_file.is_some() || options.replay_io.is_some();
+    let input = if given { input(options) } else { Box::new(std::io::empty()) };
+    let mut vm = Vm::with_io(insns, labels, input, Vec::new())?;
+    configure(&mut vm, options);
+    let mut debugger = Debugger::new(vm);
+    debugger.symbols = symbols(path, options);
+    if let Err(e) = albus::tui(&mut debugger) {
+        eprintln!("albus: unable to use the terminal: {}", e);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn tui(_: &str, _: &Options) -> albus::Result<()> {
+    eprintln!("albus: the tui is only available on Unix");
+    process::exit(2);
+}
+
+// WASI programs can't open sockets of their own, only accept connections on one the runtime
+// opened for them, as `wasmtime run --tcplisten ADDR` does, which comes straight after
+// stdin, stdout and stderr.
+#[cfg(target_os = "wasi")]
+fn bind(_: &str) -> std::io::Result<TcpListener> {
+    use std::os::wasi::io::FromRawFd;
+    Ok(unsafe { TcpListener::from_raw_fd(3) })
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn bind(addr: &str) -> std::io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
+
+fn listen(addr: &str, what: &str) -> TcpListener {
+    let listener = bind(addr).unwrap_or_else(|e| {
+        eprintln!("albus: unable to listen on {}: {}", addr, e);
+        process::exit(2);
+    });
+    match listener.local_addr() {
+        Ok(local) => eprintln!("albus: {} listening on {}", what, local),
+        Err(_) => eprintln!("albus: {} listening", what),
+    }
+    listener
+}
+
+// DAP traffic uses a socket so that stdin and stdout remain the program's own.
+fn dap(options: &Options) -> albus::Result<()> {
+    let port = options.port.as_deref().unwrap_or("4711");
+    let listener = listen(&format!("127.0.0.1:{}", port), "debug adapter");
+
+    let (stream, _) = listener.accept().expect("unable to accept connection");
+    let mut input = BufReader::new(&stream);
+    let mut output = &stream;
+    DapServer::new(&mut input, &mut output).serve().ok();
+
+    Ok(())
+}
+
+// The language server talks over stdin and stdout, as editors expect.
+fn lsp() -> albus::Result<()> {
+    let stdin = stdin();
+    let mut input = stdin.lock();
+    LspServer::new(&mut input, &mut stdout()).serve().ok();
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (mut options, args) = parse_args(&args, Options::default());
+
+    let result = match args.as_slice() {
+        ["run", path] => run(path, &options),
+        ["trace", path] => {
+            options.trace = true;
+            run(path, &options)
+        }
+        ["resume", path] => resume(path, &options),
+        ["check", path] => check(path, &options),
+        ["asm", path] => asm(path, &options),
+        ["disasm", path] => disasm(path, &options),
+        ["fmt", path] => format(path, &options),
+        ["embed", carrier, path] => hide(carrier, path, &options),
+        ["extract", path] => reveal(path, &options),
+        ["cfg", path] => graph(path, &options),
+        ["ir", path] => registers(path, &options),
+        ["coverage", path, traces @ ..] if !traces.is_empty() => covered(path, traces, &options),
+        ["optimize", path] => optimized(path, &options),
+        ["minify", path] => minified(path, &options),
+        ["compile", path] => compile(path, &options),
+        ["transpile", path] => transpile(path, &options),
+        ["gen"] => gen(&options),
+        ["from-bf", path] => brainfuck(path, &options),
+        ["test"] if options.units => units("tests", &options),
+        ["test", dir] if options.units => units(dir, &options),
+        ["test"] => golden("tests", &options),
+        ["test", dir] => golden(dir, &options),
+        ["difftest", path] => difftest(path, &options),
+        ["bench", path] => bench(path, &options),
+        ["example"] => {
+            for (name, about, _) in EXAMPLES {
+                println!("{:<12}{}", name, about);
+            }
+            Ok(())
+        }
+        ["example", name] => example(name, &options),
+        ["debug", path] => debug(path, &options),
+        ["attach", addr] => attach(addr),
+        ["tui", path] => tui(path, &options),
+        ["dap"] => dap(&options),
+        ["lsp"] => lsp(),
+        ["repl"] => {
+            repl(&mut |line| stdin().read_line(line), &mut stdout()).ok();
+            Ok(())
+        }
+        [path] if !COMMANDS.contains(path) => run(path, &options),
+        _ => usage(),
+    };
+
+    if let Err(e) = result {
+        fail(&e, &[], &Symbols::new());
     }
-    println!("}}\ninsns: {}", n);
 }
@@ -0,0 +1,41 @@
+use crate::{Insn, Label};
+use hashbrown::HashMap;
+
+// The `n`th shortest label: the empty one, then `0` and `1`, then `00` and so on.
+pub(crate) fn nth(n: usize) -> Label {
+    let n = n + 1;
+    let len = (usize::BITS - 1 - n.leading_zeros()) as usize;
+    Label::new((0..len).rev().map(|i| (n >> i & 1) as u8).collect())
+}
+
+// Renames labels so that the most used get the shortest names, and drops definitions that
+// nothing jumps to. Emitting the result gives the smallest source for the same program, as
+// long as it isn't read with labels as numbers.
+pub fn minify(insns: &[Insn], labels: &HashMap<Label, usize>) -> Vec<Insn> {
+    let jumps = insns.iter().filter(|insn| !matches!(insn, Insn::Label(_))).filter_map(Insn::label);
+    let mut uses: HashMap<&Label, (usize, usize)> = HashMap::new();
+    for (first, l) in jumps.enumerate() {
+        uses.entry(l).or_insert((0, first)).0 += 1;
+    }
+    // Only the definition jumps go to is kept, and it counts as a use too.
+    let keep = |ip: usize, l: &Label| uses.contains_key(l) && labels.get(l) == Some(&ip);
+
+    let mut order: Vec<_> =
+        uses.iter().map(|(&l, &(count, first))| (l, count + labels.contains_key(l) as usize, first)).collect();
+    order.sort_by_key(|&(_, count, first)| (usize::MAX - count, first));
+    let names: HashMap<&Label, Label> = order.iter().enumerate().map(|(i, &(l, _, _))| (l, nth(i))).collect();
+
+    insns
+        .iter()
+        .enumerate()
+        .filter_map(|(ip, insn)| match insn {
+            Insn::Label(l) if keep(ip, l) => Some(Insn::Label(names[l].clone())),
+            Insn::Label(_) | Insn::None => None,
+            Insn::Call(l) => Some(Insn::Call(names[l].clone())),
+            Insn::Jump(l) => Some(Insn::Jump(names[l].clone())),
+            Insn::Jz(l) => Some(Insn::Jz(names[l].clone())),
+            Insn::Jn(l) => Some(Insn::Jn(names[l].clone())),
+            insn => Some(insn.clone()),
+        })
+        .collect()
+}
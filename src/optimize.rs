@@ -0,0 +1,170 @@
+use crate::{check::reachable_from, emit, Insn, Label, Num};
+use hashbrown::HashMap;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+
+// Rewrites the end of `out` while it ends in a sequence with a shorter equivalent. Labels
+// are instructions too, so no sequence spans a place that control can jump into.
+fn reduce(out: &mut Vec<Insn>) {
+    loop {
+        let n = out.len();
+        match &out[n.saturating_sub(2)..] {
+            [Insn::Push(_), Insn::Pop] | [Insn::Dup, Insn::Pop] | [Insn::Swap, Insn::Swap] => out.truncate(n - 2),
+            // Both values are the same, so there is nothing to swap.
+            [Insn::Dup, Insn::Swap] => out.truncate(n - 1),
+            _ => return,
+        }
+    }
+}
+
+// How far back from the end of the program folding looks for a run of instructions to
+// replace with the constants it leaves.
+const WINDOW: usize = 16;
+
+// Division is only folded where rounding down and toward zero agree, so that the result
+// doesn't depend on how the program is run.
+pub(crate) fn arith(insn: &Insn, a: Num, b: Num) -> Option<Num> {
+    match insn {
+        Insn::Add => Some(a + b),
+        Insn::Sub => Some(a - b),
+        Insn::Mul => Some(a * b),
+        Insn::Div if !b.is_zero() => Some(a.div_floor(&b)).filter(|q| *q == &a / &b),
+        Insn::Mod if !b.is_zero() => Some(a.mod_floor(&b)).filter(|m| *m == &a % &b),
+        _ => None,
+    }
+}
+
+// Runs instructions on an empty stack, returning the values they leave if they only ever
+// work with values they pushed themselves.
+fn eval(insns: &[Insn]) -> Option<Vec<Num>> {
+    let mut stack = Vec::new();
+    for insn in insns {
+        match insn {
+            Insn::Push(n) => stack.push(n.clone()),
+            Insn::Pop => {
+                stack.pop()?;
+            }
+            Insn::Dup => stack.push(stack.last()?.clone()),
+            Insn::Swap => {
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                stack.extend([b, a]);
+            }
+            Insn::Copy(n) => stack.push(stack.iter().rev().nth(n.to_usize()?)?.clone()),
+            Insn::Slide(n) => {
+                let top = stack.pop()?;
+                let rest = stack.len().checked_sub(n.to_usize()?)?;
+                stack.truncate(rest);
+                stack.push(top);
+            }
+            Insn::Add | Insn::Sub | Insn::Mul | Insn::Div | Insn::Mod => {
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                stack.push(arith(insn, a, b)?);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(stack)
+}
+
+// Replaces the longest run at the end of `out` that computes only constants with pushes of
+// them, if that makes it smaller. Returns whether anything changed.
+fn fold(out: &mut Vec<Insn>) -> bool {
+    let cost = |insns: &[Insn]| (emit(insns).len(), insns.len());
+    for start in out.len().saturating_sub(WINDOW)..out.len() {
+        let pushes: Vec<_> = match eval(&out[start..]) {
+            Some(values) => values.into_iter().map(Insn::Push).collect(),
+            None => continue,
+        };
+        if cost(&pushes) < cost(&out[start..]) {
+            out.truncate(start);
+            out.extend(pushes);
+            return true;
+        }
+    }
+
+    false
+}
+
+// Where each label in a program is defined.
+pub(crate) fn labels(insns: &[Insn]) -> HashMap<Label, usize> {
+    let mut labels = HashMap::new();
+    for (ip, insn) in insns.iter().enumerate() {
+        if let Insn::Label(l) = insn {
+            labels.insert(l.clone(), ip);
+        }
+    }
+
+    labels
+}
+
+// Whether running on from `ip` reaches a `ret` before doing anything else.
+fn returns(insns: &[Insn], labels: &HashMap<Label, usize>, mut ip: usize) -> bool {
+    for _ in 0..insns.len() {
+        match insns.get(ip) {
+            Some(Insn::Label(_) | Insn::None) => ip += 1,
+            Some(Insn::Jump(l)) => match labels.get(l) {
+                Some(&target) => ip = target,
+                None => return false,
+            },
+            Some(Insn::Ret) => return true,
+            _ => return false,
+        }
+    }
+    false
+}
+
+// Turns each call that would return only to return again into a jump, so that the
+// subroutine's own `ret` goes straight back to the caller's caller. Whatever follows the
+// call is left in place for anything else that reaches it.
+pub fn tail_calls(insns: &[Insn], labels: &HashMap<Label, usize>) -> Vec<Insn> {
+    let call = |(ip, insn): (usize, &Insn)| match insn {
+        Insn::Call(l) if returns(insns, labels, ip + 1) => Insn::Jump(l.clone()),
+        _ => insn.clone(),
+    };
+    insns.iter().enumerate().map(call).collect()
+}
+
+// Removes the instructions that can't be reached from the start of the program, returning
+// what's left along with its labels' new positions.
+pub fn strip_unreachable(insns: &[Insn], labels: &HashMap<Label, usize>) -> (Vec<Insn>, HashMap<Label, usize>) {
+    strip(insns, labels, &[])
+}
+
+fn strip(insns: &[Insn], labels: &HashMap<Label, usize>, roots: &[Label]) -> (Vec<Insn>, HashMap<Label, usize>) {
+    let starts = std::iter::once(0).chain(roots.iter().filter_map(|l| labels.get(l).copied()));
+    let seen = reachable_from(insns, labels, starts.collect());
+    let insns: Vec<_> = insns.iter().zip(seen).filter(|(_, seen)| *seen).map(|(insn, _)| insn.clone()).collect();
+    let labels = self::labels(&insns);
+
+    (insns, labels)
+}
+
+// Applies peephole rewrites, constant folding and tail calls to a program and then strips
+// its unreachable code, returning it along with its labels' new positions. The result behaves
+// the same except where the original would have run out of stack, since a `dup` or `swap`
+// that disappears can no longer fail, or gone past a limit on calls, and it takes fewer
+// steps.
+pub fn optimize(insns: &[Insn]) -> (Vec<Insn>, HashMap<Label, usize>) {
+    optimize_with_roots(insns, &[])
+}
+
+// Optimizes a program as `optimize` does, also keeping whatever can be reached from the
+// given labels, for a run that starts at one of them rather than at the beginning.
+pub fn optimize_with_roots(insns: &[Insn], roots: &[Label]) -> (Vec<Insn>, HashMap<Label, usize>) {
+    let mut out = Vec::with_capacity(insns.len());
+    for insn in insns {
+        if *insn != Insn::None {
+            out.push(insn.clone());
+            reduce(&mut out);
+            while fold(&mut out) {
+                reduce(&mut out);
+            }
+        }
+    }
+
+    let out = tail_calls(&out, &labels(&out));
+    strip(&out, &labels(&out), roots)
+}
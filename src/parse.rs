@@ -0,0 +1,354 @@
+use crate::{
+    bytecode, gzip,
+    lex::{Alphabet, ReadTokens, Source, Tokens},
+    zstd, AlbusError, Insn, Label, Location, Num, Result,
+};
+use hashbrown::HashMap;
+use num_traits::Zero;
+use std::{io::Read, str::FromStr};
+
+// Reads a number, or returns None if the source ends before it starts.
+fn parse_arg(tokens: &mut impl Source) -> Option<Num> {
+    let mut n: Num = Zero::zero();
+    let neg = tokens.next()? == b'\t';
+
+    for byte in tokens.by_ref() {
+        if byte == b'\n' {
+            break;
+        } else {
+            n <<= 1;
+            if byte == b'\t' {
+                n += 1
+            }
+        }
+    }
+
+    Some(if neg { n * -1 } else { n })
+}
+
+fn parse_label(tokens: &mut impl Source, options: &ParseOptions) -> Option<Label> {
+    if options.legacy_labels {
+        return parse_arg(tokens).map(|n| Label::from(&n));
+    }
+    if tokens.at_end() {
+        return None;
+    }
+
+    let bits = tokens.by_ref().take_while(|&b| b != b'\n').map(|b| (b == b'\t') as u8).collect();
+    Some(Label::new(bits))
+}
+
+// Which version of Whitespace the source was written for. Copy and slide came in 0.3, so a
+// program written for 0.2 has no use for STS and STL, which then begin no opcode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    V0_2,
+    #[default]
+    V0_3,
+}
+
+impl FromStr for Dialect {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Dialect, ()> {
+        match s {
+            "0.2" => Ok(Dialect::V0_2),
+            "0.3" => Ok(Dialect::V0_3),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    // Reads labels as signed numbers like earlier versions did, so that labels differing
+    // only in leading zeroes are the same label.
+    pub legacy_labels: bool,
+    // Accepts source that ends partway through an instruction, dropping an unfinished
+    // opcode and keeping whatever digits an unfinished argument has.
+    pub lenient: bool,
+    // Reads LLSS and LLST as `dumpstack` and `dumpheap`, which other implementations also
+    // provide for debugging, rather than as unknown opcodes.
+    pub debug_opcodes: bool,
+    // Reads LLSL as `random`.
+    pub random_opcode: bool,
+    // Rejects tokens that can't begin any opcode, rather than reading on until they and
+    // those after them happen to make one.
+    pub strict: bool,
+    // The characters the source writes its tokens as.
+    pub alphabet: Alphabet,
+    pub dialect: Dialect,
+}
+
+pub fn parse(src: &mut String) -> Result<(Vec<Insn>, HashMap<Label, usize>)> {
+    parse_with(src, &ParseOptions::default())
+}
+
+pub fn parse_with(src: &mut String, options: &ParseOptions) -> Result<(Vec<Insn>, HashMap<Label, usize>)> {
+    let parsed = parse_source(src.as_bytes(), options)?;
+    src.retain(|c| c.is_ascii() && options.alphabet.token(c as u8).is_some());
+    Ok((parsed.insns, parsed.labels))
+}
+
+// A parsed program along with where each of its instructions came from.
+#[derive(Clone, Debug, Default)]
+pub struct Parsed {
+    pub insns: Vec<Insn>,
+    pub labels: HashMap<Label, usize>,
+    // Parallel to `insns`, or empty for programs loaded from bytecode.
+    pub locations: Vec<Location>,
+    // Where the last instruction starts if the end of the source cut it short, which is
+    // only ever set when parsing leniently.
+    pub truncated: Option<Location>,
+}
+
+// What follows an opcode.
+enum Shape {
+    Plain(Insn),
+    Arg(fn(Num) -> Insn),
+    Label(fn(Label) -> Insn),
+}
+
+// The instruction an opcode begins, with each token adding a digit from 1 to 3.
+fn shape(code: u8, options: &ParseOptions) -> Option<Shape> {
+    use Shape::*;
+    Some(match code {
+        0b01_01 => Arg(Insn::Push),
+        0b01_10_01 if options.dialect != Dialect::V0_2 => Arg(Insn::Copy),
+        0b01_10_11 if options.dialect != Dialect::V0_2 => Arg(Insn::Slide),
+        0b11_01_10 => Label(Insn::Call),
+        0b11_01_11 => Label(Insn::Jump),
+        0b11_10_01 => Label(Insn::Jz),
+        0b11_10_10 => Label(Insn::Jn),
+        0b11_01_01 => Label(Insn::Label),
+        0b01_11_11 => Plain(Insn::Pop),
+        0b01_11_01 => Plain(Insn::Dup),
+        0b01_11_10 => Plain(Insn::Swap),
+        0b10_01_01_01 => Plain(Insn::Add),
+        0b10_01_01_10 => Plain(Insn::Sub),
+        0b10_01_01_11 => Plain(Insn::Mul),
+        0b10_01_10_01 => Plain(Insn::Div),
+        0b10_01_10_10 => Plain(Insn::Mod),
+        0b10_10_01 => Plain(Insn::Store),
+        0b10_10_10 => Plain(Insn::Load),
+        0b11_10_11 => Plain(Insn::Ret),
+        0b10_11_10_01 => Plain(Insn::Ichr),
+        0b10_11_10_10 => Plain(Insn::Inum),
+        0b10_11_01_01 => Plain(Insn::Ochr),
+        0b10_11_01_10 => Plain(Insn::Onum),
+        0b11_11_11 => Plain(Insn::Exit),
+        0b11_11_01_01 if options.debug_opcodes => Plain(Insn::DumpStack),
+        0b11_11_01_10 if options.debug_opcodes => Plain(Insn::DumpHeap),
+        0b11_11_01_11 if options.random_opcode => Plain(Insn::Random),
+        _ => return None,
+    })
+}
+
+// Whether more tokens could still turn `code` into an opcode. Dropping an opcode's last
+// digits leaves each of its prefixes.
+fn viable(code: u8, options: &ParseOptions) -> bool {
+    (1..=u8::MAX).any(|op| shape(op, options).is_some() && (0..4).any(|digits| op >> (2 * digits) == code))
+}
+
+// Spells out an opcode's tokens, as S, T and L.
+fn spell(mut code: u8) -> String {
+    let mut tokens = Vec::new();
+    while code != 0 {
+        tokens.push(["S", "T", "L"][(code % 4 - 1) as usize]);
+        code /= 4;
+    }
+    tokens.reverse();
+    tokens.concat()
+}
+
+// The opcode read so far and where it started, between instructions or partway through
+// one when the source runs out.
+#[derive(Default)]
+struct Decoder {
+    code: u8,
+    start: Location,
+}
+
+impl Decoder {
+    // Reads the next instruction along with where it starts, or returns None at the end of
+    // the source.
+    fn next(&mut self, tokens: &mut impl Source, options: &ParseOptions) -> Result<Option<(Insn, Location)>> {
+        loop {
+            if self.code == 0 {
+                self.start = tokens.location();
+            }
+            let byte = match tokens.next() {
+                Some(byte) => byte,
+                None => return Ok(None),
+            };
+            // Outside strict parsing, the opcode carries on as long as it takes to match,
+            // keeping only its last four tokens.
+            self.code = self.code.wrapping_mul(4).wrapping_add(byte % 4 + 1);
+            let insn = match shape(self.code, options) {
+                Some(Shape::Plain(insn)) => insn,
+                // A missing argument means the source ended right after the opcode.
+                Some(Shape::Arg(f)) => match parse_arg(tokens) {
+                    Some(n) => f(n),
+                    None => return Ok(None),
+                },
+                Some(Shape::Label(f)) => match parse_label(tokens, options) {
+                    Some(l) => f(l),
+                    None => return Ok(None),
+                },
+                None if options.strict && !viable(self.code, options) => {
+                    return Err(AlbusError::UnknownOpcode { offset: self.start.offset, tokens: spell(self.code) });
+                }
+                None => continue,
+            };
+            self.code = 0;
+            return Ok(Some((insn, self.start)));
+        }
+    }
+
+    // Where the instruction the source ended partway through starts, if it did.
+    fn unfinished(&self) -> Option<Location> {
+        Some(self.start).filter(|_| self.code != 0)
+    }
+}
+
+// Arguments end at a newline, so one followed by anything else was cut off by the end of
+// the source.
+fn cut_short(insn: &Insn, tokens: &impl Source) -> bool {
+    (insn.arg().is_some() || insn.label().is_some()) && tokens.last() != Some(b'\n')
+}
+
+pub fn parse_source(src: &[u8], options: &ParseOptions) -> Result<Parsed> {
+    let mut insns = Vec::<Insn>::new();
+    let mut labels = HashMap::new();
+    let mut locations = Vec::new();
+    let mut decoder = Decoder::default();
+    let mut tokens = Tokens::new(src, &options.alphabet);
+
+    while let Some((insn, start)) = decoder.next(&mut tokens, options)? {
+        if let Insn::Label(l) = &insn {
+            labels.insert(l.clone(), insns.len());
+        }
+        insns.push(insn);
+        locations.push(start);
+    }
+
+    let cut = match (decoder.unfinished(), insns.last()) {
+        (None, Some(last)) if cut_short(last, &tokens) => locations.last().copied(),
+        (unfinished, _) => unfinished,
+    };
+    match cut {
+        None => Ok(Parsed { insns, labels, locations, truncated: None }),
+        Some(start) if !options.lenient => Err(AlbusError::TruncatedInstruction { offset: start.offset }),
+        Some(start) => Ok(Parsed { insns, labels, locations, truncated: Some(start) }),
+    }
+}
+
+// Parses source as it's read rather than all at once, yielding each instruction with where
+// it starts as soon as it has been decoded, so that a program can be taken from a pipe or
+// a socket without waiting for the end. Failing to read and, unless parsing leniently,
+// source that ends partway through an instruction end it with an error.
+pub struct Stream<R> {
+    tokens: ReadTokens<R>,
+    decoder: Decoder,
+    options: ParseOptions,
+    count: usize,
+    done: bool,
+}
+
+pub fn parse_stream<R: Read>(input: R, options: &ParseOptions) -> Stream<R> {
+    let (decoder, options) = (Decoder::default(), options.clone());
+    Stream { tokens: ReadTokens::new(input, &options.alphabet), decoder, options, count: 0, done: false }
+}
+
+impl<R: Read> Iterator for Stream<R> {
+    type Item = Result<(Insn, Location)>;
+
+    fn next(&mut self) -> Option<Result<(Insn, Location)>> {
+        if self.done {
+            return None;
+        }
+        let truncated = |start: Location| AlbusError::TruncatedInstruction { offset: start.offset };
+
+        let next = self.decoder.next(&mut self.tokens, &self.options);
+        if let Some(error) = self.tokens.error.take() {
+            self.done = true;
+            return Some(Err(AlbusError::IoError { ip: self.count, error }));
+        }
+        match next {
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+            Ok(Some((insn, start))) => {
+                self.count += 1;
+                if cut_short(&insn, &self.tokens) {
+                    self.done = true;
+                    if !self.options.lenient {
+                        return Some(Err(truncated(start)));
+                    }
+                }
+                Some(Ok((insn, start)))
+            }
+            Ok(None) => {
+                self.done = true;
+                self.decoder.unfinished().filter(|_| !self.options.lenient).map(|start| Err(truncated(start)))
+            }
+        }
+    }
+}
+
+// Parses everything `input` gives, much as `parse_source` would have had it been read
+// first.
+pub fn read_source<R: Read>(input: R, options: &ParseOptions) -> Result<Parsed> {
+    let mut parsed = Parsed::default();
+    let mut stream = parse_stream(input, options);
+    for next in stream.by_ref() {
+        let (insn, start) = next?;
+        if let Insn::Label(l) = &insn {
+            parsed.labels.insert(l.clone(), parsed.insns.len());
+        }
+        parsed.insns.push(insn);
+        parsed.locations.push(start);
+    }
+    if options.lenient {
+        let cut = parsed.insns.last().filter(|last| cut_short(last, &stream.tokens)).and(parsed.locations.last());
+        parsed.truncated = stream.decoder.unfinished().or_else(|| cut.copied());
+    }
+
+    Ok(parsed)
+}
+
+// Accepts either Whitespace source or compiled bytecode, compressed or not.
+pub fn load(bytes: &[u8]) -> Result<(Vec<Insn>, HashMap<Label, usize>)> {
+    load_with(bytes, &ParseOptions::default())
+}
+
+pub fn load_with(bytes: &[u8], options: &ParseOptions) -> Result<(Vec<Insn>, HashMap<Label, usize>)> {
+    load_source(bytes, options).map(|parsed| (parsed.insns, parsed.labels))
+}
+
+// Source compressed with gzip or zstd, told apart by how it starts, decompressed, or None
+// for source that isn't.
+fn decompress(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (format, result) = if bytes.starts_with(&gzip::MAGIC) {
+        ("gzip", gzip::gunzip(bytes))
+    } else if bytes.starts_with(&zstd::MAGIC) {
+        ("zstd", zstd::unzstd(bytes))
+    } else {
+        return Ok(None);
+    };
+    result.map(Some).map_err(|reason| AlbusError::Decompression { format, reason })
+}
+
+// Accepts Whitespace source or compiled bytecode, either of which may be compressed.
+pub fn load_source(bytes: &[u8], options: &ParseOptions) -> Result<Parsed> {
+    if let Some(bytes) = decompress(bytes)? {
+        return load_source(&bytes, options);
+    }
+    if bytecode::is_bytecode(bytes) {
+        let (insns, labels) = bytecode::decode(bytes)?;
+        Ok(Parsed { insns, labels, ..Parsed::default() })
+    } else {
+        parse_source(bytes, options)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{json::Json, load_source, ParseOptions, Result, Vm};
+use std::io::Cursor;
+
+// An interface for hosts that can only pass strings back and forth, such as a web page
+// running albus compiled to WebAssembly. Programs are Whitespace source, and results are
+// JSON so they can be handed straight to `JSON.parse`.
+
+type Machine = Vm<Cursor<Vec<u8>>, Vec<u8>>;
+
+// The state of a program as JSON, with the output it wrote since the state was last taken.
+fn state(vm: &mut Machine, error: Option<String>) -> String {
+    let output = String::from_utf8_lossy(&std::mem::take(&mut vm.output)).into_owned();
+    let mut heap: Vec<_> = vm.heap.iter().collect();
+    heap.sort();
+    let mut fields = vec![
+        ("output", output.into()),
+        ("stack", vm.stack.iter().map(Json::from).collect::<Vec<_>>().into()),
+        ("heap", Json::Object(heap.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect())),
+        ("ip", vm.ip.into()),
+        ("steps", vm.steps.into()),
+        ("halted", vm.halted.into()),
+    ];
+    if let Some(e) = error {
+        fields.push(("error", e.into()));
+    }
+
+    Json::object(fields).to_string()
+}
+
+fn load(source: &str, input: &str) -> Result<Machine> {
+    let parsed = load_source(source.as_bytes(), &ParseOptions::default())?;
+    Vm::with_io(parsed.insns, parsed.labels, Cursor::new(input.as_bytes().to_vec()), Vec::new())
+}
+
+// Runs a program to the end, returning its output and final stack and heap, along with
+// the error that stopped it if one did.
+pub fn run(source: &str, input: &str) -> String {
+    match load(source, input) {
+        Ok(mut vm) => {
+            let error = vm.run().err().map(|e| e.to_string());
+            state(&mut vm, error)
+        }
+        Err(e) => Json::object(vec![("error", e.to_string().into())]).to_string(),
+    }
+}
+
+// A program being stepped through.
+pub struct Session {
+    vm: Machine,
+}
+
+impl Session {
+    pub fn new(source: &str, input: &str) -> Result<Session> {
+        Ok(Session { vm: load(source, input)? })
+    }
+
+    // Executes up to `n` instructions, stopping early if the program halts or fails, and
+    // returns the state it's left in.
+    pub fn step(&mut self, n: u64) -> String {
+        let mut error = None;
+        for _ in 0..n {
+            match self.vm.step() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        state(&mut self.vm, error)
+    }
+
+    pub fn state(&mut self) -> String {
+        state(&mut self.vm, None)
+    }
+}
+
+// Exports for WebAssembly, where strings are passed as a pointer and length into memory
+// the host got from `albus_alloc`, and results are read from the buffer that
+// `albus_result` and `albus_result_len` describe until the next call.
+#[cfg(target_arch = "wasm32")]
+mod wasm32 {
+    use super::{run, Session};
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RESULT: RefCell<String> = RefCell::new(String::new());
+        static SESSIONS: RefCell<Vec<Option<Session>>> = RefCell::new(Vec::new());
+    }
+
+    unsafe fn string(ptr: *const u8, len: usize) -> String {
+        String::from_utf8_lossy(std::slice::from_raw_parts(ptr, len)).into_owned()
+    }
+
+    fn result(s: String) {
+        RESULT.with(|r| *r.borrow_mut() = s);
+    }
+
+    #[no_mangle]
+    pub extern "C" fn albus_alloc(len: usize) -> *mut u8 {
+        let mut buf = Vec::<u8>::with_capacity(len);
+        let ptr = buf.as_mut_ptr();
+        std::mem::forget(buf);
+        ptr
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn albus_free(ptr: *mut u8, len: usize) {
+        drop(Vec::from_raw_parts(ptr, 0, len));
+    }
+
+    #[no_mangle]
+    pub extern "C" fn albus_result() -> *const u8 {
+        RESULT.with(|r| r.borrow().as_ptr())
+    }
+
+    #[no_mangle]
+    pub extern "C" fn albus_result_len() -> usize {
+        RESULT.with(|r| r.borrow().len())
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn albus_run(source: *const u8, source_len: usize, input: *const u8, input_len: usize) {
+        result(run(&string(source, source_len), &string(input, input_len)));
+    }
+
+    // Returns the new session's id, or -1 with the error as the result if the program
+    // doesn't load.
+    #[no_mangle]
+    pub unsafe extern "C" fn albus_session_new(
+        source: *const u8,
+        source_len: usize,
+        input: *const u8,
+        input_len: usize,
+    ) -> i32 {
+        match Session::new(&string(source, source_len), &string(input, input_len)) {
+            Ok(session) => SESSIONS.with(|s| {
+                let mut sessions = s.borrow_mut();
+                sessions.push(Some(session));
+                sessions.len() as i32 - 1
+            }),
+            Err(e) => {
+                result(crate::json::Json::object(vec![("error", e.to_string().into())]).to_string());
+                -1
+            }
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn albus_session_step(id: usize, n: u64) {
+        let state = SESSIONS.with(|s| s.borrow_mut().get_mut(id)?.as_mut().map(|session| session.step(n)));
+        result(state.unwrap_or_default());
+    }
+
+    #[no_mangle]
+    pub extern "C" fn albus_session_free(id: usize) {
+        SESSIONS.with(|s| s.borrow_mut().get_mut(id).map(Option::take));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::Insn;
+use hashbrown::HashMap;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+    time::Duration,
+};
+
+// How many of the most executed instructions the report lists.
+const HOTTEST: usize = 10;
+
+// Execution counts and time spent per instruction index, filled in by the caller as it
+// steps the Vm, and optionally how many instructions ran under each call stack.
+pub struct Profiler {
+    pub counts: Vec<u64>,
+    pub time: Vec<Duration>,
+    pub stacks: HashMap<Vec<usize>, u64>,
+}
+
+impl Profiler {
+    pub fn new(len: usize) -> Profiler {
+        Profiler {
+            counts: vec![0; len],
+            time: vec![Duration::ZERO; len],
+            stacks: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, ip: usize, elapsed: Duration) {
+        if ip >= self.counts.len() {
+            self.counts.resize(ip + 1, 0);
+            self.time.resize(ip + 1, Duration::ZERO);
+        }
+        self.counts[ip] += 1;
+        self.time[ip] += elapsed;
+    }
+
+    // Counts an instruction run with the call stack as `calls`, where a Vm's subroutines
+    // were called from.
+    pub fn record_calls(&mut self, calls: &[usize]) {
+        match self.stacks.get_mut(calls) {
+            Some(n) => *n += 1,
+            None => {
+                self.stacks.insert(calls.to_vec(), 1);
+            }
+        }
+    }
+
+    // The call stacks in the folded format flame graph tools read, one per line with its
+    // frames from the outermost and separated by semicolons, then its count. Subroutines
+    // are named by their labels, and stacks that only differ in where calls came from are
+    // merged.
+    pub fn folded(&self, insns: &[Insn]) -> String {
+        let mut folded = BTreeMap::<String, u64>::new();
+        for (calls, &n) in &self.stacks {
+            let mut stack = String::from("(entry)");
+            for &ip in calls {
+                match insns.get(ip) {
+                    Some(Insn::Call(l)) => write!(stack, ";label {}", l).unwrap(),
+                    _ => stack.push_str(";(unknown)"),
+                }
+            }
+            *folded.entry(stack).or_default() += n;
+        }
+
+        folded.into_iter().map(|(stack, n)| format!("{} {}\n", stack, n)).collect()
+    }
+
+    // Instructions are attributed to the nearest label before them in the program, or to
+    // the entry point if no label precedes them.
+    fn regions(&self, insns: &[Insn]) -> Vec<(String, u64, Duration)> {
+        let mut regions = vec![("(entry)".to_string(), 0, Duration::ZERO)];
+        for (ip, insn) in insns.iter().enumerate() {
+            if let Insn::Label(l) = insn {
+                regions.push((format!("label {}", l), 0, Duration::ZERO));
+            }
+            let region = regions.last_mut().unwrap();
+            region.1 += self.counts.get(ip).copied().unwrap_or(0);
+            region.2 += self.time.get(ip).copied().unwrap_or(Duration::ZERO);
+        }
+        regions.retain(|r| r.1 > 0);
+        regions.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        regions
+    }
+
+    pub fn report(&self, insns: &[Insn], out: &mut dyn Write) -> io::Result<()> {
+        let total: u64 = self.counts.iter().sum();
+        let percent = |n: u64| if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 };
+
+        let mut hot: Vec<_> = (0..self.counts.len()).filter(|&ip| self.counts[ip] > 0).collect();
+        hot.sort_by(|&a, &b| self.counts[b].cmp(&self.counts[a]).then(a.cmp(&b)));
+        writeln!(out, "hottest instructions:")?;
+        for &ip in hot.iter().take(HOTTEST) {
+            let insn = insns.get(ip).map(ToString::to_string).unwrap_or_default();
+            let (n, time) = (self.counts[ip], self.time[ip]);
+            writeln!(out, "  {:>6}  {:<16} {:>10} {:>6.2}% {:>10.2?}", ip, insn, n, percent(n), time)?;
+        }
+
+        let mut ops = HashMap::<&str, u64>::new();
+        for (ip, &n) in self.counts.iter().enumerate() {
+            if let Some(insn) = insns.get(ip).filter(|_| n > 0) {
+                *ops.entry(insn.mnemonic()).or_default() += n;
+            }
+        }
+        let mut ops: Vec<_> = ops.into_iter().collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        writeln!(out, "by opcode:")?;
+        for (op, n) in ops {
+            writeln!(out, "  {:<8} {:>10} {:>6.2}%", op, n, percent(n))?;
+        }
+
+        writeln!(out, "by label:")?;
+        for (name, n, time) in self.regions(insns) {
+            writeln!(out, "  {:<24} {:>10} {:>10.2?}", name, n, time)?;
+        }
+
+        writeln!(out, "total: {} instructions", total)
+    }
+}
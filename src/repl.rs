@@ -0,0 +1,68 @@
+use crate::{Assembler, Value, Vm};
+use hashbrown::HashMap;
+use std::io::{self, Write};
+
+fn show_stack(vm: &Vm, out: &mut dyn Write) -> io::Result<()> {
+    let stack: Vec<_> = vm.stack.iter().map(Value::to_string).collect();
+    writeln!(out, "[{}]", stack.join(", "))
+}
+
+// Each line is appended to a growing program and executed from its first new instruction,
+// so labels defined earlier stay reachable from later lines.
+pub fn repl(read_line: &mut dyn FnMut(&mut String) -> io::Result<usize>, out: &mut dyn Write) -> io::Result<()> {
+    let mut asm = Assembler::new();
+    let mut vm = Vm::new(Vec::new(), HashMap::new()).expect("an empty program is always valid");
+    let mut line = String::new();
+
+    for lineno in 1.. {
+        write!(out, "> ")?;
+        out.flush()?;
+
+        line.clear();
+        if read_line(&mut line)? == 0 {
+            break;
+        }
+
+        match line.trim() {
+            ":q" | ":quit" => break,
+            ":heap" => {
+                let mut heap: Vec<_> = vm.heap.iter().collect();
+                heap.sort();
+                for (k, v) in heap {
+                    writeln!(out, "{}: {}", k, v)?;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let insns = match asm.line(&line, lineno) {
+            Ok(insns) if insns.is_empty() => continue,
+            Ok(insns) => insns,
+            Err(e) => {
+                writeln!(out, "error: {}", e)?;
+                continue;
+            }
+        };
+
+        let end = vm.insns().len();
+        if let Err(e) = vm.append(insns) {
+            writeln!(out, "error: {}", e)?;
+            continue;
+        }
+        vm.ip = end;
+        vm.halted = false;
+
+        let result = vm.run();
+        vm.output.flush()?;
+        if let Err(e) = result {
+            writeln!(out, "error: {}", e)?;
+            vm.ip = vm.insns().len();
+        } else if vm.ip < vm.insns().len() {
+            break;
+        }
+        show_stack(&vm, out)?;
+    }
+
+    Ok(())
+}
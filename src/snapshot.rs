@@ -0,0 +1,97 @@
+use crate::{
+    bytecode::{self, write_num, write_varint, Reader},
+    AlbusError, Num, Result, Value, Vm,
+};
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use std::io::{Read, Write};
+
+pub const MAGIC: &[u8] = b"ALBS\x01";
+
+// Saves everything needed to carry on running a program later: the program itself as
+// bytecode, then where it is, how far it has come, where its random numbers have got to and
+// its stack, calls and heap. Limits and options such as `eof` aren't saved, nor is anything
+// about its input and output.
+pub fn save<R: Read, W: Write>(vm: &Vm<R, W>) -> Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    let program = bytecode::encode(vm.insns(), vm.labels())?;
+    write_varint(&mut out, BigUint::from(program.len()));
+    out.extend(program);
+
+    for n in [vm.ip as u64, vm.steps, vm.max_depth as u64, vm.halted as u64, vm.rng.0, vm.big_allocated] {
+        write_varint(&mut out, BigUint::from(n));
+    }
+    write_varint(&mut out, BigUint::from(vm.stack.len()));
+    for v in &vm.stack {
+        write_num(&mut out, &v.to_num());
+    }
+    write_varint(&mut out, BigUint::from(vm.calls.len()));
+    for &ip in &vm.calls {
+        write_varint(&mut out, BigUint::from(ip));
+    }
+    // Sorted so that the same state always saves the same way.
+    let mut heap: Vec<_> = vm.heap.iter().collect();
+    heap.sort();
+    write_varint(&mut out, BigUint::from(heap.len()));
+    for (k, v) in heap {
+        write_num(&mut out, &k.to_num());
+        write_num(&mut out, &v.to_num());
+    }
+
+    Ok(out)
+}
+
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC[..4])
+}
+
+// Rebuilds a Vm saved by `save`, reading and writing with the given input and output.
+pub fn restore<R: Read, W: Write>(bytes: &[u8], input: R, output: W) -> Result<Vm<R, W>> {
+    let mut r = Reader { bytes, pos: 0 };
+    if !is_snapshot(bytes) {
+        return Err(r.error("not an albus snapshot"));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return Err(r.error("unsupported snapshot version"));
+    }
+    r.pos = MAGIC.len();
+
+    let count = |r: &mut Reader| -> Result<usize> {
+        r.varint()?.to_usize().ok_or_else(|| r.error("count out of range"))
+    };
+    let len = count(&mut r)?;
+    let program = r.pos.checked_add(len).and_then(|end| bytes.get(r.pos..end)).ok_or_else(|| r.error("unexpected end of snapshot"))?;
+    let (insns, labels) = bytecode::decode(program)?;
+    r.pos += len;
+
+    let mut vm = Vm::with_io(insns, labels, input, output)?;
+    vm.ip = count(&mut r)?;
+    vm.steps = r.varint()?.to_u64().ok_or_else(|| r.error("step count out of range"))?;
+    vm.max_depth = count(&mut r)?;
+    vm.halted = count(&mut r)? != 0;
+    vm.rng.0 = r.varint()?.to_u64().ok_or_else(|| r.error("random state out of range"))?;
+    vm.big_allocated = r.varint()?.to_u64().ok_or_else(|| r.error("allocation count out of range"))?;
+    for _ in 0..count(&mut r)? {
+        vm.stack.push(Value::from(r.num()?));
+    }
+    for _ in 0..count(&mut r)? {
+        let ip = count(&mut r)?;
+        if ip > vm.insns().len() {
+            return Err(r.error("return address out of range"));
+        }
+        vm.calls.push(ip);
+    }
+    vm.max_call_depth = vm.calls.len();
+    for _ in 0..count(&mut r)? {
+        let (k, v): (Num, Num) = (r.num()?, r.num()?);
+        vm.heap.insert(Value::from(k), Value::from(v));
+    }
+    if vm.ip > vm.insns().len() {
+        return Err(AlbusError::ParseError { offset: r.pos, reason: "instruction pointer out of range" });
+    }
+    // Everything that was charged for has been restored at once.
+    let values = vm.stack.iter().chain(vm.heap.keys()).chain(vm.heap.values());
+    vm.charged = values.map(Value::big_bytes).sum();
+
+    Ok(vm)
+}
@@ -0,0 +1,66 @@
+use crate::{AlbusError, Result};
+
+// Each token is hidden as a pair of spaces and tabs at the ends of the carrier's lines,
+// and a pair of tabs marks where the program stops.
+const PAIRS: [(&str, u8); 3] = [("  ", b' '), (" \t", b'\t'), ("\t ", b'\n')];
+const END: &str = "\t\t";
+
+fn ending(line: &str) -> (&str, &str) {
+    let body = line.strip_suffix('\n').unwrap_or(line);
+    let body = body.strip_suffix('\r').unwrap_or(body);
+    line.split_at(body.len())
+}
+
+fn trailing(body: &str) -> usize {
+    body.trim_end_matches([' ', '\t']).len()
+}
+
+// Hides a program in text by spreading its tokens over the ends of the lines, after taking
+// away whatever spaces and tabs they ended with. The program's other characters are left
+// out, and the text otherwise reads the same.
+pub fn embed(carrier: &str, program: &[u8]) -> String {
+    let mut hidden = String::new();
+    for &b in program {
+        if let Some((pair, _)) = PAIRS.iter().find(|(_, token)| *token == b) {
+            hidden.push_str(pair);
+        }
+    }
+    hidden.push_str(END);
+
+    let lines: Vec<_> = carrier.split_inclusive('\n').collect();
+    let per_line = 2 * (hidden.len() / 2).div_ceil(lines.len().max(1));
+    let mut chunks = hidden.as_bytes().chunks(per_line);
+    let mut out = String::new();
+    for line in &lines {
+        let (body, end) = ending(line);
+        out.push_str(&body[..trailing(body)]);
+        out.extend(chunks.next().into_iter().flatten().map(|&b| b as char));
+        out.push_str(end);
+    }
+    // An empty carrier still gets the program.
+    for chunk in chunks {
+        out.extend(chunk.iter().map(|&b| b as char));
+    }
+    out
+}
+
+// Recovers a program hidden by `embed`, as plain Whitespace.
+pub fn extract(text: &str) -> Result<Vec<u8>> {
+    let mut hidden = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let (body, _) = ending(line);
+        hidden.extend_from_slice(&body.as_bytes()[trailing(body)..]);
+    }
+
+    let mut program = Vec::new();
+    for pair in hidden.chunks(2) {
+        if pair == END.as_bytes() {
+            return Ok(program);
+        }
+        match PAIRS.iter().find(|(p, _)| p.as_bytes() == pair) {
+            Some(&(_, token)) => program.push(token),
+            None => break,
+        }
+    }
+    Err(AlbusError::ParseError { offset: text.len(), reason: "no program is hidden in the text" })
+}
@@ -0,0 +1,5 @@
+mod c;
+mod rust;
+
+pub use c::c;
+pub use rust::rust;
@@ -0,0 +1,300 @@
+use crate::{Insn, Label, Num};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::fmt::Write;
+
+const PRELUDE: &str = r#"#include <gmp.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+static mpz_t *stack;
+static size_t sp, cap;
+static size_t *calls;
+static size_t csp, ccap;
+
+struct cell { mpz_t key, val; int used; };
+static struct cell *heap;
+static size_t hlen, hcap;
+
+static void fail(const char *msg, long ip) {
+    fflush(stdout);
+    fprintf(stderr, "albus: %s at instruction %ld\n", msg, ip);
+    exit(1);
+}
+
+static void need(size_t n, long ip) {
+    if (sp < n) fail("stack underflow", ip);
+}
+
+static mpz_ptr push(void) {
+    if (sp == cap) {
+        size_t i;
+        cap = cap ? cap * 2 : 64;
+        stack = realloc(stack, cap * sizeof *stack);
+        for (i = sp; i < cap; i++) mpz_init(stack[i]);
+    }
+    return stack[sp++];
+}
+
+static mpz_ptr pop(long ip) {
+    need(1, ip);
+    return stack[--sp];
+}
+
+/* mpz_sgn is a macro that may evaluate its argument twice. */
+static int pop_sign(long ip) {
+    mpz_ptr v = pop(ip);
+    return mpz_sgn(v);
+}
+
+static void call(size_t site) {
+    if (csp == ccap) {
+        ccap = ccap ? ccap * 2 : 64;
+        calls = realloc(calls, ccap * sizeof *calls);
+    }
+    calls[csp++] = site;
+}
+
+static size_t ret(long ip) {
+    if (csp == 0) fail("return outside of a call", ip);
+    return calls[--csp];
+}
+
+static struct cell *find(mpz_srcptr key) {
+    size_t i = (mpz_getlimbn(key, 0) * 2654435761u + (mpz_sgn(key) < 0)) & (hcap - 1);
+    while (heap[i].used && mpz_cmp(heap[i].key, key)) i = (i + 1) & (hcap - 1);
+    return &heap[i];
+}
+
+static void store(mpz_srcptr key, mpz_srcptr val) {
+    struct cell *c;
+    if (2 * (hlen + 1) > hcap) {
+        struct cell *old = heap;
+        size_t i, n = hcap;
+        hcap = hcap ? hcap * 2 : 64;
+        heap = calloc(hcap, sizeof *heap);
+        for (i = 0; i < n; i++) {
+            if (old[i].used) *find(old[i].key) = old[i];
+        }
+        free(old);
+    }
+    c = find(key);
+    if (!c->used) {
+        mpz_init_set(c->key, key);
+        mpz_init(c->val);
+        c->used = 1;
+        hlen++;
+    }
+    mpz_set(c->val, val);
+}
+
+static void op_copy(size_t n, long ip) {
+    mpz_ptr v;
+    need(n + 1, ip);
+    v = push();
+    mpz_set(v, stack[sp - 2 - n]);
+}
+
+static void op_slide(size_t n, long ip) {
+    need(1, ip);
+    if (n > sp - 1) fail("argument out of range", ip);
+    mpz_swap(stack[sp - 1 - n], stack[sp - 1]);
+    sp -= n;
+}
+
+static void op_swap(long ip) {
+    need(2, ip);
+    mpz_swap(stack[sp - 1], stack[sp - 2]);
+}
+
+static void op_arith(char op, long ip) {
+    mpz_ptr l, r;
+    need(2, ip);
+    r = stack[--sp];
+    l = stack[sp - 1];
+    if ((op == '/' || op == '%') && mpz_sgn(r) == 0) fail("division by zero", ip);
+    switch (op) {
+    case '+': mpz_add(l, l, r); break;
+    case '-': mpz_sub(l, l, r); break;
+    case '*': mpz_mul(l, l, r); break;
+    case '/': mpz_fdiv_q(l, l, r); break;
+    case '%': mpz_fdiv_r(l, l, r); break;
+    }
+}
+
+static void op_store(long ip) {
+    need(2, ip);
+    sp -= 2;
+    store(stack[sp], stack[sp + 1]);
+}
+
+static void op_load(long ip) {
+    mpz_ptr k = pop(ip);
+    struct cell *c = hcap ? find(k) : NULL;
+    if (!c || !c->used) fail("load from uninitialized heap address", ip);
+    mpz_set(push(), c->val);
+}
+
+static void op_ichr(long ip) {
+    mpz_ptr k = pop(ip);
+    int c = getchar();
+    mpz_t v;
+    mpz_init_set_ui(v, c == EOF ? 0 : c);
+    store(k, v);
+    mpz_clear(v);
+}
+
+static void op_inum(long ip) {
+    mpz_ptr k = pop(ip);
+    char *line = NULL, *s;
+    size_t len = 0, size = 0;
+    int c;
+    mpz_t v;
+    while ((c = getchar()) != EOF) {
+        if (len + 2 > size) line = realloc(line, size = size * 2 + 64);
+        line[len++] = c;
+        if (c == '\n') break;
+    }
+    if (!line) line = calloc(1, 1);
+    line[len] = 0;
+    while (len && strchr(" \t\r\n", line[len - 1])) line[--len] = 0;
+    s = line + (line[0] == '+');
+    if (!*s || mpz_init_set_str(v, s, 10)) fail("invalid number", ip);
+    store(k, v);
+    mpz_clear(v);
+    free(line);
+}
+
+static void op_ochr(long ip) {
+    mpz_ptr v = pop(ip);
+    unsigned long c;
+    if (mpz_sgn(v) < 0 || mpz_cmp_ui(v, 255) > 0) fail("value is not a character", ip);
+    c = mpz_get_ui(v);
+    if (c < 0x80) {
+        putchar(c);
+    } else {
+        putchar(0xc0 | c >> 6);
+        putchar(0x80 | (c & 0x3f));
+    }
+}
+
+static void op_onum(long ip) {
+    mpz_out_str(stdout, 10, pop(ip));
+}
+
+/* SplitMix64, seeded from the clock. */
+static unsigned long long rng;
+
+static void op_random(long ip) {
+    unsigned long long z;
+    need(1, ip);
+    if (mpz_sgn(stack[sp - 1]) <= 0 || !mpz_fits_ulong_p(stack[sp - 1])) fail("argument out of range", ip);
+    z = rng += 0x9e3779b97f4a7c15ull;
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ull;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebull;
+    z ^= z >> 31;
+    mpz_set_ui(stack[sp - 1], z % mpz_get_ui(stack[sp - 1]));
+}
+
+static void op_dumpstack(void) {
+    size_t i;
+    fflush(stdout);
+    fputs("stack: [", stderr);
+    for (i = 0; i < sp; i++) gmp_fprintf(stderr, i ? ", %Zd" : "%Zd", stack[i]);
+    fputs("]\n", stderr);
+}
+
+static int by_key(const void *a, const void *b) {
+    return mpz_cmp((*(struct cell *const *)a)->key, (*(struct cell *const *)b)->key);
+}
+
+static void op_dumpheap(void) {
+    struct cell **cells = malloc((hlen + 1) * sizeof *cells);
+    size_t i, n = 0;
+    for (i = 0; i < hcap; i++) {
+        if (heap[i].used) cells[n++] = &heap[i];
+    }
+    qsort(cells, n, sizeof *cells, by_key);
+    fflush(stdout);
+    fputs("heap: {", stderr);
+    for (i = 0; i < n; i++) gmp_fprintf(stderr, i ? ", %Zd: %Zd" : "%Zd: %Zd", cells[i]->key, cells[i]->val);
+    fputs("}\n", stderr);
+    free(cells);
+}
+
+int main(void) {
+    size_t site;
+    long rip = 0;
+    rng = time(NULL);
+"#;
+
+fn push(out: &mut String, n: &Num) {
+    match n.to_i32() {
+        Some(n) => writeln!(out, "    mpz_set_si(push(), {});", n),
+        None => writeln!(out, "    mpz_set_str(push(), \"{}\", 10);", n),
+    }
+    .unwrap();
+}
+
+// Emits a single `main` where labels become C labels and returns dispatch on the call site.
+pub fn c(insns: &[Insn], labels: &HashMap<Label, usize>) -> String {
+    let mut out = String::from(PRELUDE);
+    let mut sites = 0;
+
+    for (ip, insn) in insns.iter().enumerate() {
+        writeln!(out, "    /* {}: {} */", ip, insn).unwrap();
+
+        let target = |l: &Label| match labels.get(l) {
+            Some(t) => format!("goto l{}", t),
+            None => format!("fail(\"undefined label\", {})", ip),
+        };
+        let arg = |op: &str, n: &Num| match n.to_usize() {
+            Some(n) => format!("{}({}, {})", op, n, ip),
+            None => format!("fail(\"argument out of range\", {})", ip),
+        };
+
+        match insn {
+            Insn::None => {}
+            Insn::Push(n) => push(&mut out, n),
+            Insn::Pop => writeln!(out, "    pop({});", ip).unwrap(),
+            Insn::Dup => writeln!(out, "    op_copy(0, {});", ip).unwrap(),
+            Insn::Swap => writeln!(out, "    op_swap({});", ip).unwrap(),
+            Insn::Copy(n) => writeln!(out, "    {};", arg("op_copy", n)).unwrap(),
+            Insn::Slide(n) => writeln!(out, "    {};", arg("op_slide", n)).unwrap(),
+            Insn::Add => writeln!(out, "    op_arith('+', {});", ip).unwrap(),
+            Insn::Sub => writeln!(out, "    op_arith('-', {});", ip).unwrap(),
+            Insn::Mul => writeln!(out, "    op_arith('*', {});", ip).unwrap(),
+            Insn::Div => writeln!(out, "    op_arith('/', {});", ip).unwrap(),
+            Insn::Mod => writeln!(out, "    op_arith('%', {});", ip).unwrap(),
+            Insn::Label(_) => writeln!(out, "l{}:;", ip).unwrap(),
+            Insn::Call(l) => {
+                writeln!(out, "    call({});\n    {};\nr{}:;", sites, target(l), sites).unwrap();
+                sites += 1;
+            }
+            Insn::Jump(l) => writeln!(out, "    {};", target(l)).unwrap(),
+            Insn::Jz(l) => writeln!(out, "    if (pop_sign({}) == 0) {};", ip, target(l)).unwrap(),
+            Insn::Jn(l) => writeln!(out, "    if (pop_sign({}) < 0) {};", ip, target(l)).unwrap(),
+            Insn::Ret => writeln!(out, "    rip = {};\n    goto ret;", ip).unwrap(),
+            Insn::Store => writeln!(out, "    op_store({});", ip).unwrap(),
+            Insn::Load => writeln!(out, "    op_load({});", ip).unwrap(),
+            Insn::Ichr => writeln!(out, "    fflush(stdout);\n    op_ichr({});", ip).unwrap(),
+            Insn::Inum => writeln!(out, "    fflush(stdout);\n    op_inum({});", ip).unwrap(),
+            Insn::Ochr => writeln!(out, "    op_ochr({});", ip).unwrap(),
+            Insn::Onum => writeln!(out, "    op_onum({});", ip).unwrap(),
+            Insn::Exit => writeln!(out, "    goto end;").unwrap(),
+            Insn::DumpStack => writeln!(out, "    op_dumpstack();").unwrap(),
+            Insn::DumpHeap => writeln!(out, "    op_dumpheap();").unwrap(),
+            Insn::Random => writeln!(out, "    op_random({});", ip).unwrap(),
+        }
+    }
+
+    out.push_str("    goto end;\nret:\n    site = ret(rip);\n    switch (site) {\n");
+    for site in 0..sites {
+        writeln!(out, "    case {}: goto r{};", site, site).unwrap();
+    }
+    out.push_str("    }\nend:\n    fflush(stdout);\n    return 0;\n}\n");
+
+    out
+}
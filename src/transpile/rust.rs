@@ -0,0 +1,276 @@
+use crate::{block::blocks, Insn, Label, Num};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+use std::fmt::Write;
+
+const PRELUDE: &str = r#"// Generated by albus. Build with these dependencies:
+//
+//     [dependencies]
+//     num-bigint = "0.3"
+//     num-integer = "0.1"
+//     num-traits = "0.2"
+
+#![allow(dead_code, unused_variables)]
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{ToPrimitive, Zero};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+const HALT: usize = usize::MAX;
+
+struct Machine {
+    stack: Vec<BigInt>,
+    calls: Vec<usize>,
+    heap: HashMap<BigInt, BigInt>,
+    out: io::BufWriter<io::Stdout>,
+    // SplitMix64, seeded from the clock.
+    rng: u64,
+}
+
+impl Machine {
+    fn fail(&mut self, msg: &str, ip: usize) -> ! {
+        self.out.flush().ok();
+        eprintln!("albus: {} at instruction {}", msg, ip);
+        std::process::exit(1)
+    }
+
+    fn need(&mut self, n: usize, ip: usize) {
+        if self.stack.len() < n {
+            self.fail("stack underflow", ip);
+        }
+    }
+
+    fn pop(&mut self, ip: usize) -> BigInt {
+        self.need(1, ip);
+        self.stack.pop().unwrap()
+    }
+
+    fn copy(&mut self, n: usize, ip: usize) {
+        self.need(n + 1, ip);
+        let v = self.stack[self.stack.len() - 1 - n].clone();
+        self.stack.push(v);
+    }
+
+    fn slide(&mut self, n: usize, ip: usize) {
+        self.need(1, ip);
+        let top = self.stack.len() - 1;
+        if n > top {
+            self.fail("argument out of range", ip);
+        }
+        self.stack.drain(top - n..top);
+    }
+
+    fn swap(&mut self, ip: usize) {
+        self.need(2, ip);
+        let n = self.stack.len();
+        self.stack.swap(n - 1, n - 2);
+    }
+
+    fn operands(&mut self, ip: usize) -> (BigInt, BigInt) {
+        self.need(2, ip);
+        let r = self.stack.pop().unwrap();
+        (self.stack.pop().unwrap(), r)
+    }
+
+    fn divisor(&mut self, ip: usize) -> (BigInt, BigInt) {
+        let (l, r) = self.operands(ip);
+        if r.is_zero() {
+            self.fail("division by zero", ip);
+        }
+        (l, r)
+    }
+
+    fn store(&mut self, ip: usize) {
+        let (k, v) = self.operands(ip);
+        self.heap.insert(k, v);
+    }
+
+    fn load(&mut self, ip: usize) {
+        let k = self.pop(ip);
+        match self.heap.get(&k) {
+            Some(v) => self.stack.push(v.clone()),
+            None => self.fail("load from uninitialized heap address", ip),
+        }
+    }
+
+    fn ret(&mut self, ip: usize) -> usize {
+        match self.calls.pop() {
+            Some(b) => b,
+            None => self.fail("return outside of a call", ip),
+        }
+    }
+
+    fn ichr(&mut self, ip: usize) {
+        let k = self.pop(ip);
+        let mut buf = [0u8];
+        self.out.flush().ok();
+        io::stdin().read_exact(&mut buf).ok();
+        self.heap.insert(k, BigInt::from(buf[0]));
+    }
+
+    fn inum(&mut self, ip: usize) {
+        let k = self.pop(ip);
+        let mut line = String::new();
+        self.out.flush().ok();
+        io::stdin().lock().read_line(&mut line).ok();
+        match line.trim_end().parse::<BigInt>() {
+            Ok(v) => drop(self.heap.insert(k, v)),
+            Err(_) => self.fail("invalid number", ip),
+        }
+    }
+
+    fn ochr(&mut self, ip: usize) {
+        let v = self.pop(ip);
+        match v.to_u8() {
+            Some(c) => drop(write!(self.out, "{}", c as char)),
+            None => self.fail("value is not a character", ip),
+        }
+    }
+
+    fn onum(&mut self, ip: usize) {
+        let v = self.pop(ip);
+        write!(self.out, "{}", v).ok();
+    }
+
+    fn random(&mut self, ip: usize) {
+        let n = self.pop(ip);
+        match n.to_u64().filter(|&n| n > 0) {
+            Some(n) => {
+                self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = self.rng;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                self.stack.push(BigInt::from((z ^ (z >> 31)) % n));
+            }
+            None => self.fail("argument out of range", ip),
+        }
+    }
+
+    fn dump_stack(&mut self) {
+        self.out.flush().ok();
+        let values: Vec<_> = self.stack.iter().map(BigInt::to_string).collect();
+        eprintln!("stack: [{}]", values.join(", "));
+    }
+
+    fn dump_heap(&mut self) {
+        self.out.flush().ok();
+        let mut heap: Vec<_> = self.heap.iter().collect();
+        heap.sort();
+        let cells: Vec<_> = heap.into_iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+        eprintln!("heap: {{{}}}", cells.join(", "));
+    }
+}
+"#;
+
+fn literal(n: &Num) -> String {
+    match n.to_i64() {
+        Some(n) => format!("BigInt::from({}i64)", n),
+        None => format!("\"{}\".parse::<BigInt>().unwrap()", n),
+    }
+}
+
+// Each basic block becomes a function returning the index of the block to run next, with
+// the call stack holding return blocks.
+pub fn rust(insns: &[Insn], labels: &HashMap<Label, usize>) -> String {
+    let blocks = blocks(insns);
+    let ids: HashMap<usize, usize> = blocks.iter().enumerate().map(|(id, b)| (b.range.start, id)).collect();
+    let mut out = String::from(PRELUDE);
+
+    for (id, block) in blocks.iter().enumerate() {
+        let next = if block.range.end < insns.len() {
+            (id + 1).to_string()
+        } else {
+            "HALT".to_string()
+        };
+        let mut tail = format!("    {}\n", next);
+
+        write!(out, "\nfn b{}(m: &mut Machine) -> usize {{\n", id).unwrap();
+        for ip in block.range.clone() {
+            let insn = &insns[ip];
+            let target = |l: &Label| match labels.get(l) {
+                Some(t) => ids[t].to_string(),
+                None => format!("m.fail(\"undefined label\", {})", ip),
+            };
+            let arg = |op: &str, n: &Num| match n.to_usize() {
+                Some(n) => format!("m.{}({}, {});", op, n, ip),
+                None => format!("m.fail(\"argument out of range\", {});", ip),
+            };
+
+            writeln!(out, "    // {}: {}", ip, insn).unwrap();
+            let line = match insn {
+                Insn::None | Insn::Label(_) => continue,
+                Insn::Push(n) => format!("m.stack.push({});", literal(n)),
+                Insn::Pop => format!("m.pop({});", ip),
+                Insn::Dup => format!("m.copy(0, {});", ip),
+                Insn::Swap => format!("m.swap({});", ip),
+                Insn::Copy(n) => arg("copy", n),
+                Insn::Slide(n) => arg("slide", n),
+                Insn::Add => format!("let (l, r) = m.operands({});\n    m.stack.push(l + r);", ip),
+                Insn::Sub => format!("let (l, r) = m.operands({});\n    m.stack.push(l - r);", ip),
+                Insn::Mul => format!("let (l, r) = m.operands({});\n    m.stack.push(l * r);", ip),
+                Insn::Div => format!("let (l, r) = m.divisor({});\n    m.stack.push(l.div_floor(&r));", ip),
+                Insn::Mod => format!("let (l, r) = m.divisor({});\n    m.stack.push(l.mod_floor(&r));", ip),
+                Insn::Store => format!("m.store({});", ip),
+                Insn::Load => format!("m.load({});", ip),
+                Insn::Ichr => format!("m.ichr({});", ip),
+                Insn::Inum => format!("m.inum({});", ip),
+                Insn::Ochr => format!("m.ochr({});", ip),
+                Insn::Onum => format!("m.onum({});", ip),
+                Insn::DumpStack => "m.dump_stack();".to_string(),
+                Insn::DumpHeap => "m.dump_heap();".to_string(),
+                Insn::Random => format!("m.random({});", ip),
+                Insn::Call(l) => {
+                    writeln!(out, "    m.calls.push({});", next).unwrap();
+                    tail = format!("    {}\n", target(l));
+                    continue;
+                }
+                Insn::Jump(l) => {
+                    tail = format!("    {}\n", target(l));
+                    continue;
+                }
+                Insn::Jz(l) => format!("if m.pop({}).is_zero() {{\n        return {};\n    }}", ip, target(l)),
+                Insn::Jn(l) => format!("if m.pop({}).sign() == num_bigint::Sign::Minus {{\n        return {};\n    }}", ip, target(l)),
+                Insn::Ret => {
+                    tail = format!("    m.ret({})\n", ip);
+                    continue;
+                }
+                Insn::Exit => {
+                    tail = "    HALT\n".to_string();
+                    continue;
+                }
+            };
+            writeln!(out, "    {}", line).unwrap();
+        }
+        writeln!(out, "{}}}", tail).unwrap();
+    }
+
+    out.push_str("\nconst BLOCKS: &[fn(&mut Machine) -> usize] = &[");
+    for id in 0..blocks.len() {
+        write!(out, "{}b{}", if id % 8 == 0 { "\n    " } else { " " }, id).unwrap();
+        out.push(',');
+    }
+    out.push_str(
+        "\n];
+
+fn main() {
+    let mut m = Machine {
+        stack: Vec::new(),
+        calls: Vec::new(),
+        heap: HashMap::new(),
+        out: io::BufWriter::new(io::stdout()),
+        rng: std::time::UNIX_EPOCH.elapsed().map_or(0, |t| t.as_nanos() as u64),
+    };
+    let mut b = if BLOCKS.is_empty() { HALT } else { 0 };
+
+    while b != HALT {
+        b = BLOCKS[b](&mut m);
+    }
+    m.out.flush().ok();
+}
+",
+    );
+
+    out
+}
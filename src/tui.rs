@@ -0,0 +1,192 @@
+use crate::{Debugger, Result, Value};
+use std::{
+    io::{self, stdin, stdout, Read, Write},
+    mem,
+};
+
+const KEYS: &str = "s step  n next  f finish  c continue  r back  R back to breakpoint  b breakpoint  j/k move  q quit";
+
+// How many lines of the program's output to show.
+const OUTPUT_LINES: usize = 6;
+
+// Keeps the terminal in raw mode on the alternate screen for as long as it lives.
+struct Screen {
+    saved: libc::termios,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        let mut termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let saved = termios;
+        unsafe { libc::cfmakeraw(&mut termios) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(Screen { saved })
+    }
+
+    // The terminal's width and height, or a common size if it won't say.
+    fn size() -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24),
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        stdout().flush().ok();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.saved) };
+    }
+}
+
+// Reads a key, with the up and down arrows read as `k` and `j`.
+fn key(input: &mut dyn Read) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    if input.read(&mut buf)? == 0 {
+        return Ok(None);
+    }
+    if buf[0] != 0x1b {
+        return Ok(Some(buf[0]));
+    }
+    let mut seq = [0; 2];
+    input.read_exact(&mut seq)?;
+    Ok(Some(match seq {
+        [b'[', b'A'] => b'k',
+        [b'[', b'B'] => b'j',
+        _ => 0,
+    }))
+}
+
+// Cuts or pads a line to exactly `width` characters.
+fn fit(line: &str, width: usize) -> String {
+    let mut line: String = line.chars().filter(|c| !c.is_control()).take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
+
+fn section(lines: &mut Vec<String>, title: &str, items: impl Iterator<Item = String>) {
+    lines.push(format!("── {} ", title));
+    lines.extend(items);
+}
+
+struct Tui<'a, R> {
+    debugger: &'a mut Debugger<R, Vec<u8>>,
+    // The instruction that breakpoints are set on, which follows the program as it runs.
+    cursor: usize,
+    message: String,
+}
+
+impl<R: Read> Tui<'_, R> {
+    fn code(&self, width: usize, height: usize) -> Vec<String> {
+        let len = self.debugger.vm.insns().len();
+        let start = self.cursor.saturating_sub(height / 2).min(len.saturating_sub(height));
+        let mut lines = vec![fit("── Program ", width)];
+        for i in start..(start + height).min(len) {
+            let mut line = Vec::new();
+            self.debugger.show(&mut line, i).ok();
+            let line = fit(&String::from_utf8_lossy(&line), width);
+            lines.push(if i == self.cursor { format!("\x1b[7m{}\x1b[0m", line) } else { line });
+        }
+
+        lines
+    }
+
+    fn state(&self, height: usize) -> Vec<String> {
+        let vm = &self.debugger.vm;
+        let mut heap: Vec<_> = vm.heap.iter().collect();
+        heap.sort();
+
+        let mut lines = Vec::new();
+        section(&mut lines, "Stack", vm.stack.iter().rev().map(Value::to_string).take(height / 2));
+        let symbols = &self.debugger.symbols;
+        let calls = vm.calls.iter().rev().map(|i| format!("{:>5}  {}", i, symbols.show(&vm.insns()[*i])));
+        section(&mut lines, "Calls", calls);
+        section(&mut lines, "Heap", heap.into_iter().map(|(k, v)| format!("{}: {}", k, v)));
+        lines
+    }
+
+    fn draw(&self, out: &mut dyn Write) -> io::Result<()> {
+        let (width, height) = Screen::size();
+        let rows = height.saturating_sub(OUTPUT_LINES + 3);
+        let left = width * 3 / 5;
+        let right = width - left - 1;
+
+        let code = self.code(left, rows - 1);
+        let state = self.state(rows);
+        let mut frame = String::from("\x1b[H");
+        for row in 0..rows {
+            let code = code.get(row).cloned().unwrap_or_else(|| fit("", left));
+            let state = fit(state.get(row).map_or("", String::as_str), right);
+            frame += &format!("{}│{}\r\n", code, state);
+        }
+
+        let output = String::from_utf8_lossy(&self.debugger.vm.output);
+        let lines: Vec<_> = output.lines().collect();
+        frame += &fit("── Output ", width);
+        frame += "\r\n";
+        for row in 0..OUTPUT_LINES {
+            let line = lines.len().checked_sub(OUTPUT_LINES - row).map_or("", |i| lines[i]);
+            frame += &fit(line, width);
+            frame += "\r\n";
+        }
+        frame += &format!("\x1b[7m{}\x1b[0m\r\n{}", fit(&self.message, width), fit(KEYS, width));
+
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+
+    fn act(&mut self, how: fn(&mut Debugger<R, Vec<u8>>) -> Result<bool>) {
+        self.message = match how(self.debugger) {
+            Ok(true) => match &self.debugger.stored {
+                Some((key, old, new)) => {
+                    let old = old.as_ref().map_or_else(|| "unset".to_string(), Value::to_string);
+                    format!("watchpoint {}: {} -> {}", key, old, new)
+                }
+                None => String::new(),
+            },
+            Ok(false) => format!("program halted after {} instructions", self.debugger.vm.steps),
+            Err(e) => format!("error: {}", e),
+        };
+        self.cursor = self.debugger.vm.ip.min(self.debugger.vm.insns().len().saturating_sub(1));
+    }
+}
+
+// Runs a full-screen debugging session in the terminal, showing what the program writes in
+// a pane of its own.
+pub fn tui<R: Read>(debugger: &mut Debugger<R, Vec<u8>>) -> io::Result<()> {
+    let _screen = Screen::enter()?;
+    let mut tui = Tui { cursor: debugger.vm.ip, debugger, message: String::new() };
+    let mut input = stdin();
+    let mut out = stdout();
+
+    loop {
+        tui.draw(&mut out)?;
+        match key(&mut input)? {
+            None | Some(b'q') => return Ok(()),
+            Some(b's') => tui.act(Debugger::step),
+            Some(b'n') => tui.act(Debugger::step_over),
+            Some(b'f') => tui.act(Debugger::finish),
+            Some(b'c') => tui.act(Debugger::cont),
+            Some(b'r') => tui.act(|d| d.reverse_step(1)),
+            Some(b'R') => tui.act(Debugger::reverse_continue),
+            Some(b'j') => tui.cursor = (tui.cursor + 1).min(tui.debugger.vm.insns().len().saturating_sub(1)),
+            Some(b'k') => tui.cursor = tui.cursor.saturating_sub(1),
+            Some(b'b') => {
+                let breakpoints = &mut tui.debugger.breakpoints;
+                if breakpoints.remove(&tui.cursor).is_none() {
+                    breakpoints.insert(tui.cursor, None);
+                }
+            }
+            _ => {}
+        }
+    }
+}
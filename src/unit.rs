@@ -0,0 +1,132 @@
+use crate::{AlbusError, Num, Result};
+
+// A check that calling one subroutine with some values on the stack and some input leaves
+// the stack as expected and writes what's expected. Either expectation may be left out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitTest {
+    pub name: String,
+    // The label to call, by its name in the program's map or as bits.
+    pub call: String,
+    pub stack: Vec<Num>,
+    pub input: String,
+    pub expect_stack: Option<Vec<Num>>,
+    pub expect_output: Option<String>,
+}
+
+// The tests for one program, which is FILE.ws beside the FILE.toml they're read from unless
+// `program` names another, relative to it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitSuite {
+    pub program: Option<String>,
+    pub tests: Vec<UnitTest>,
+}
+
+enum Field {
+    Str(String),
+    Ints(Vec<Num>),
+}
+
+fn error(line: usize, reason: impl Into<String>) -> AlbusError {
+    AlbusError::AsmError { line, reason: reason.into() }
+}
+
+// A basic string, with the escapes a test is likely to want, or a literal one in single
+// quotes, which has none.
+fn string(s: &str, line: usize) -> Result<(String, &str)> {
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or_else(|| error(line, "unterminated string"))?;
+        return Ok((rest[..end].to_string(), &rest[end + 1..]));
+    }
+    let mut out = String::new();
+    let mut chars = s[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &s[i + 2..])),
+            '\\' => out.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err(error(line, "unknown escape in string")),
+            }),
+            c => out.push(c),
+        }
+    }
+    Err(error(line, "unterminated string"))
+}
+
+fn field(value: &str, line: usize) -> Result<Field> {
+    let int = |s: &str| s.trim().replace('_', "").parse::<Num>().map_err(|_| error(line, "expected an integer"));
+    let (field, rest) = if value.starts_with(['"', '\'']) {
+        let (s, rest) = string(value, line)?;
+        (Field::Str(s), rest)
+    } else if let Some(list) = value.strip_prefix('[') {
+        let end = list.find(']').ok_or_else(|| error(line, "unterminated array"))?;
+        let items = list[..end].split(',').map(str::trim).filter(|item| !item.is_empty());
+        (Field::Ints(items.map(int).collect::<Result<_>>()?), &list[end + 1..])
+    } else {
+        return Err(error(line, "expected a string or an array of integers"));
+    };
+    match rest.trim() {
+        rest if rest.is_empty() || rest.starts_with('#') => Ok(field),
+        _ => Err(error(line, "unexpected text after the value")),
+    }
+}
+
+// Reads tests written in a small part of TOML: `key = value` lines, a `[[test]]` header
+// before each test's, and comments. Values are strings or arrays of integers, each on one
+// line.
+//
+//     program = "math.ws"
+//
+//     [[test]]
+//     name = "adds"
+//     call = "add"
+//     stack = [3, 5]
+//     expect_stack = [8]
+pub fn parse_units(src: &str) -> Result<UnitSuite> {
+    let mut suite = UnitSuite::default();
+    let mut headers = Vec::new();
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        if text.split('#').next().unwrap().trim() == "[[test]]" {
+            suite.tests.push(UnitTest::default());
+            headers.push(line);
+            continue;
+        }
+        let (key, value) = text.split_once('=').ok_or_else(|| error(line, "expected `key = value` or [[test]]"))?;
+        let (key, value) = (key.trim(), field(value.trim(), line)?);
+        let Some(test) = suite.tests.last_mut() else {
+            match (key, value) {
+                ("program", Field::Str(s)) => suite.program = Some(s),
+                ("program", _) => return Err(error(line, "`program` needs a string")),
+                _ => return Err(error(line, format!("unknown key `{}` before the first [[test]]", key))),
+            }
+            continue;
+        };
+        match (key, value) {
+            ("name", Field::Str(s)) => test.name = s,
+            ("call", Field::Str(s)) => test.call = s,
+            ("input", Field::Str(s)) => test.input = s,
+            ("expect_output", Field::Str(s)) => test.expect_output = Some(s),
+            ("stack", Field::Ints(values)) => test.stack = values,
+            ("expect_stack", Field::Ints(values)) => test.expect_stack = Some(values),
+            ("name" | "call" | "input" | "expect_output", _) => {
+                return Err(error(line, format!("`{}` needs a string", key)));
+            }
+            ("stack" | "expect_stack", _) => return Err(error(line, format!("`{}` needs an array of integers", key))),
+            _ => return Err(error(line, format!("unknown key `{}`", key))),
+        }
+    }
+    if let Some(i) = suite.tests.iter().position(|test| test.call.is_empty()) {
+        return Err(error(headers[i], "the test has no label to `call`"));
+    }
+
+    Ok(suite)
+}
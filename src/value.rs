@@ -0,0 +1,172 @@
+use crate::Num;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Div, Mul, Rem, Sub},
+    rc::Rc,
+};
+
+// A stack or heap value. Values that fit in an i64 are always stored as `Small`, so the
+// derived equality and hashing agree with numeric equality; `Big` only ever holds values
+// outside that range. Bignums are shared, so that copying one around the stack and heap
+// never copies its digits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Value {
+    Small(i64),
+    Big(Rc<Num>),
+}
+
+impl Value {
+    pub fn to_num(&self) -> Num {
+        self.num().into_owned()
+    }
+
+    // The value as a bignum, borrowing the one it holds if it's already that.
+    fn num(&self) -> Cow<'_, Num> {
+        match self {
+            Value::Small(n) => Cow::Owned(Num::from(*n)),
+            Value::Big(n) => Cow::Borrowed(n),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Value::Small(0))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Value::Small(n) => *n < 0,
+            Value::Big(n) => n.is_negative(),
+        }
+    }
+
+    // Bytes of bignum storage the value holds, which is nothing for small values.
+    pub fn big_bytes(&self) -> usize {
+        match self {
+            Value::Small(_) => 0,
+            Value::Big(n) => n.bits().div_ceil(8) as usize,
+        }
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        match self {
+            Value::Small(n) => n.to_usize(),
+            Value::Big(_) => None,
+        }
+    }
+
+    // Division rounding toward negative infinity, as Haskell's `div` does. The caller is
+    // responsible for rejecting a zero divisor.
+    pub fn div_floor(&self, r: &Value) -> Value {
+        if let (Value::Small(a), Value::Small(b)) = (self, r) {
+            if let Some(q) = a.checked_div(*b) {
+                let inexact = a % b != 0 && (*a < 0) != (*b < 0);
+                return Value::Small(q - inexact as i64);
+            }
+        }
+        Value::from(self.num().div_floor(&r.num()))
+    }
+
+    // The remainder of `div_floor`, which takes the sign of the divisor.
+    pub fn mod_floor(&self, r: &Value) -> Value {
+        if let (Value::Small(a), Value::Small(b)) = (self, r) {
+            if let Some(m) = a.checked_rem(*b) {
+                let wrong_sign = m != 0 && (m < 0) != (*b < 0);
+                return Value::Small(if wrong_sign { m + b } else { m });
+            }
+        }
+        Value::from(self.num().mod_floor(&r.num()))
+    }
+
+    pub fn to_u8(&self) -> Option<u8> {
+        match self {
+            Value::Small(n) => n.to_u8(),
+            Value::Big(_) => None,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Small(n)
+    }
+}
+
+impl From<Num> for Value {
+    fn from(n: Num) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Small(n),
+            None => Value::Big(Rc::new(n)),
+        }
+    }
+}
+
+impl From<&Num> for Value {
+    fn from(n: &Num) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Small(n),
+            None => Value::Big(Rc::new(n.clone())),
+        }
+    }
+}
+
+impl From<Value> for Num {
+    fn from(v: Value) -> Num {
+        match v {
+            Value::Small(n) => Num::from(n),
+            Value::Big(n) => Rc::try_unwrap(n).unwrap_or_else(|n| (*n).clone()),
+        }
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Small(l), Value::Small(r)) => l.cmp(r),
+            _ => self.num().cmp(&other.num()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Small(n) => write!(f, "{}", n),
+            Value::Big(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+// Arithmetic stays on i64 until it overflows, then redoes the operation as a bignum. The
+// caller is responsible for rejecting a zero divisor.
+macro_rules! arith {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        impl $trait<&Value> for &Value {
+            type Output = Value;
+
+            fn $method(self, r: &Value) -> Value {
+                if let (Value::Small(a), Value::Small(b)) = (self, r) {
+                    if let Some(n) = a.$checked(*b) {
+                        return Value::Small(n);
+                    }
+                }
+                Value::from((&*self.num()).$method(&*r.num()))
+            }
+        }
+    };
+}
+
+arith!(Add, add, checked_add);
+arith!(Sub, sub, checked_sub);
+arith!(Mul, mul, checked_mul);
+arith!(Div, div, checked_div);
+arith!(Rem, rem, checked_rem);
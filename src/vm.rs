@@ -0,0 +1,955 @@
+use crate::{gen::Rng, AlbusError, Heap, Host, Insn, Label, Num, Result, Value};
+use hashbrown::HashMap;
+use std::{
+    convert::TryFrom,
+    io::{self, stdin, stdout, Read, Stdin, Stdout, Write},
+    ops::ControlFlow,
+    str::FromStr,
+    time::Instant,
+};
+
+// Pops the right operand of a binary operation, leaving the stack untouched on underflow.
+fn operands(stack: &mut Vec<Value>) -> Option<(Value, &mut Value)> {
+    if stack.len() < 2 {
+        return None;
+    }
+    let r = stack.pop()?;
+    Some((r, stack.last_mut()?))
+}
+
+// An instruction in the form the Vm executes it, with constants converted to values and
+// jump targets resolved to the index of their label.
+#[derive(Clone, Debug)]
+enum Op {
+    None,
+    Push(Value),
+    Pop,
+    Dup,
+    Swap,
+    Copy(Value),
+    Slide(Value),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Label,
+    Call(usize),
+    // A call to the host function with this index.
+    Host(usize),
+    Jump(usize),
+    Jz(usize),
+    Jn(usize),
+    Ret,
+    Store,
+    Load,
+    Ichr,
+    Inum,
+    Ochr,
+    Onum,
+    Exit,
+    DumpStack,
+    DumpHeap,
+    Random,
+    // Pairs joined by `fuse`, each standing in for the first of the two and skipping over
+    // the second, which stays as it was for anything that jumps straight to it.
+    PushAdd(Value),
+    PushSub(Value),
+    PushMul(Value),
+    PushStore(Value),
+    PushLoad(Value),
+    DupJz(usize),
+    DupJn(usize),
+}
+
+impl Op {
+    fn fused(&self) -> bool {
+        matches!(
+            self,
+            Op::PushAdd(_)
+                | Op::PushSub(_)
+                | Op::PushMul(_)
+                | Op::PushStore(_)
+                | Op::PushLoad(_)
+                | Op::DupJz(_)
+                | Op::DupJn(_)
+        )
+    }
+
+    // How many more values than a fused pair leaves were on the stack between its halves.
+    fn peak(&self) -> usize {
+        match self {
+            Op::PushStore(_) => 2,
+            Op::PushLoad(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+// The pairs of instructions that turn up most in profiles of loops, joined into one.
+fn fuse(first: &Op, second: &Op) -> Option<Op> {
+    Some(match (first, second) {
+        (Op::Push(v), Op::Add) => Op::PushAdd(v.clone()),
+        (Op::Push(v), Op::Sub) => Op::PushSub(v.clone()),
+        (Op::Push(v), Op::Mul) => Op::PushMul(v.clone()),
+        (Op::Push(v), Op::Store) => Op::PushStore(v.clone()),
+        (Op::Push(v), Op::Load) => Op::PushLoad(v.clone()),
+        (Op::Dup, Op::Jz(target)) => Op::DupJz(*target),
+        (Op::Dup, Op::Jn(target)) => Op::DupJn(*target),
+        _ => return None,
+    })
+}
+
+fn resolve(insn: &Insn, ip: usize, labels: &HashMap<Label, usize>, host: &Host) -> Result<Op> {
+    let target = |label: &Label| {
+        labels.get(label).copied().ok_or_else(|| AlbusError::UndefinedLabel { ip, label: label.clone() })
+    };
+
+    Ok(match insn {
+        Insn::None => Op::None,
+        Insn::Push(n) => Op::Push(n.into()),
+        Insn::Pop => Op::Pop,
+        Insn::Dup => Op::Dup,
+        Insn::Swap => Op::Swap,
+        Insn::Copy(n) => Op::Copy(n.into()),
+        Insn::Slide(n) => Op::Slide(n.into()),
+        Insn::Add => Op::Add,
+        Insn::Sub => Op::Sub,
+        Insn::Mul => Op::Mul,
+        Insn::Div => Op::Div,
+        Insn::Mod => Op::Mod,
+        Insn::Label(_) => Op::Label,
+        Insn::Call(l) => match host.labels.get(l) {
+            Some(&i) => Op::Host(i),
+            None => Op::Call(target(l)?),
+        },
+        Insn::Jump(l) => Op::Jump(target(l)?),
+        Insn::Jz(l) => Op::Jz(target(l)?),
+        Insn::Jn(l) => Op::Jn(target(l)?),
+        Insn::Ret => Op::Ret,
+        Insn::Store => Op::Store,
+        Insn::Load => Op::Load,
+        Insn::Ichr => Op::Ichr,
+        Insn::Inum => Op::Inum,
+        Insn::Ochr => Op::Ochr,
+        Insn::Onum => Op::Onum,
+        Insn::Exit => Op::Exit,
+        Insn::DumpStack => Op::DumpStack,
+        Insn::DumpHeap => Op::DumpHeap,
+        Insn::Random => Op::Random,
+    })
+}
+
+// How far below the top of the stack a `copy` or `slide` argument reaches, when the top
+// value is at index `top`.
+fn reach(arg: &Value, top: usize, clamp: bool, ip: usize) -> Result<usize> {
+    match arg.to_usize().filter(|&n| n <= top) {
+        Some(n) => Ok(n),
+        None if clamp => Ok(if arg.is_negative() { 0 } else { top }),
+        None => Err(AlbusError::ArgumentOutOfRange { ip, arg: arg.to_num(), depth: top + 1 }),
+    }
+}
+
+// Reads a line a byte at a time, so that nothing past the newline is taken from the input.
+pub(crate) fn read_line(input: &mut impl Read) -> String {
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while input.read(&mut byte).unwrap_or(0) == 1 {
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&line).into_owned()
+}
+
+// What `ichr` and `inum` do when there is no more input: store 0 or -1 as if it had been
+// read, stop with an error, or end the program as `exit` would.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eof {
+    Zero,
+    MinusOne,
+    #[default]
+    Error,
+    Halt,
+}
+
+impl Eof {
+    // The value to store in place of input, or None if the program should halt.
+    fn value(self, ip: usize) -> Result<Option<Value>> {
+        match self {
+            Eof::Zero => Ok(Some(Value::Small(0))),
+            Eof::MinusOne => Ok(Some(Value::Small(-1))),
+            Eof::Error => Err(AlbusError::UnexpectedEof { ip }),
+            Eof::Halt => Ok(None),
+        }
+    }
+}
+
+impl FromStr for Eof {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Eof, ()> {
+        match s {
+            "zero" => Ok(Eof::Zero),
+            "minus-one" => Ok(Eof::MinusOne),
+            "error" => Ok(Eof::Error),
+            "halt" => Ok(Eof::Halt),
+            _ => Err(()),
+        }
+    }
+}
+
+// How `ichr` and `ochr` map characters to bytes. By default input is read a byte at a time and
+// characters up to 255 are written as those code points, while UTF-8 works in whole Unicode
+// scalar values both ways and bytes are read and written as they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Latin1,
+    Utf8,
+    Bytes,
+}
+
+impl Charset {
+    fn char(self, n: usize) -> Option<char> {
+        match self {
+            Charset::Latin1 | Charset::Bytes => u8::try_from(n).ok().map(char::from),
+            Charset::Utf8 => u32::try_from(n).ok().and_then(char::from_u32),
+        }
+    }
+
+    // Writes a value as a character, or returns None if it doesn't stand for one.
+    pub(crate) fn write(self, out: &mut impl Write, n: usize) -> Option<io::Result<()>> {
+        match self {
+            Charset::Bytes => u8::try_from(n).ok().map(|b| out.write_all(&[b])),
+            _ => self.char(n).map(|c| write!(out, "{}", c)),
+        }
+    }
+
+    // How many bytes a character starting with `lead` takes, or None if no UTF-8 character
+    // can start with it.
+    pub(crate) fn width(self, lead: u8) -> Option<usize> {
+        match lead {
+            _ if self != Charset::Utf8 => Some(1),
+            0x00..=0x7f => Some(1),
+            0xc2..=0xdf => Some(2),
+            0xe0..=0xef => Some(3),
+            0xf0..=0xf4 => Some(4),
+            _ => None,
+        }
+    }
+
+    // Reads the next character a byte at a time, so that nothing after it is taken from the
+    // input, or returns None at the end of input. Malformed UTF-8 reads as U+FFFD.
+    pub(crate) fn read(self, input: &mut impl Read) -> io::Result<Option<u32>> {
+        let mut buf = [0u8; 4];
+        if input.read(&mut buf[..1])? == 0 {
+            return Ok(None);
+        }
+        let len = match self.width(buf[0]) {
+            Some(1) => return Ok(Some(buf[0].into())),
+            Some(len) => len,
+            None => return Ok(Some(char::REPLACEMENT_CHARACTER.into())),
+        };
+        for i in 1..len {
+            if input.read(&mut buf[i..=i])? == 0 || buf[i] & 0xc0 != 0x80 {
+                return Ok(Some(char::REPLACEMENT_CHARACTER.into()));
+            }
+        }
+        let c = std::str::from_utf8(&buf[..len]).ok().and_then(|s| s.chars().next());
+        Ok(Some(c.unwrap_or(char::REPLACEMENT_CHARACTER).into()))
+    }
+
+    // The input that `ichr` reads as a value, for replaying what a program was given.
+    pub(crate) fn encode(self, v: &Value) -> Option<Vec<u8>> {
+        match self {
+            Charset::Latin1 | Charset::Bytes => v.to_u8().map(|b| vec![b]),
+            Charset::Utf8 => self.char(v.to_usize()?).map(|c| c.to_string().into_bytes()),
+        }
+    }
+}
+
+impl FromStr for Charset {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Charset, ()> {
+        match s {
+            "latin1" => Ok(Charset::Latin1),
+            "utf8" => Ok(Charset::Utf8),
+            "bytes" => Ok(Charset::Bytes),
+            _ => Err(()),
+        }
+    }
+}
+
+// Integers as interpreters that keep them in a fixed number of bits do, for reproducing how
+// programs written for those behave. Results of arithmetic, pushed numbers and numbers read
+// as input that don't fit either wrap around or stick at the nearest end of the range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntWidth {
+    pub bits: u32,
+    pub saturating: bool,
+}
+
+impl IntWidth {
+    pub fn fit(self, v: Value) -> Value {
+        let shift = 64u32.saturating_sub(self.bits);
+        match v {
+            Value::Small(n) if shift == 0 => Value::Small(n),
+            Value::Small(n) if self.saturating => Value::Small(n.clamp(i64::MIN >> shift, i64::MAX >> shift)),
+            Value::Small(n) => Value::Small((n << shift) >> shift),
+            v => {
+                let (n, half) = (v.to_num(), Num::from(1) << (self.bits - 1));
+                let n = if self.saturating {
+                    n.clamp(-half.clone(), half - 1)
+                } else {
+                    let modulus: Num = half.clone() << 1;
+                    let n = (n % &modulus + &modulus) % &modulus;
+                    if n >= half {
+                        n - modulus
+                    } else {
+                        n
+                    }
+                };
+                Value::from(n)
+            }
+        }
+    }
+}
+
+// A number of bits from 2 to 128, wrapping unless followed by `-saturating`, as in `32` or
+// `64-saturating`.
+impl FromStr for IntWidth {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<IntWidth, ()> {
+        let (bits, saturating) = match s.split_once('-') {
+            Some((bits, "saturating")) => (bits, true),
+            Some((bits, "wrapping")) => (bits, false),
+            Some(_) => return Err(()),
+            None => (s, false),
+        };
+        match bits.parse() {
+            Ok(bits @ 2..=128) => Ok(IntWidth { bits, saturating }),
+            _ => Err(()),
+        }
+    }
+}
+
+// Whether running on from `ip` reaches a `ret` before doing anything else.
+fn returns(ops: &[Op], mut ip: usize) -> bool {
+    for _ in 0..ops.len() {
+        match ops.get(ip) {
+            Some(Op::Label | Op::None) => ip += 1,
+            Some(Op::Jump(target)) => ip = *target,
+            Some(Op::Ret) => return true,
+            _ => return false,
+        }
+    }
+    false
+}
+
+// Bounds on how much work and memory a program may use. Exceeding one stops execution with
+// an error at the offending instruction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub max_stack: Option<usize>,
+    pub max_heap: Option<usize>,
+    pub max_calls: Option<usize>,
+    // Total bytes held by values too large for an i64, across the stack and heap.
+    pub max_bytes: Option<usize>,
+    // Checked every DEADLINE_INTERVAL instructions rather than on each one.
+    pub deadline: Option<Instant>,
+}
+
+const DEADLINE_INTERVAL: u64 = 1024;
+
+// Called with the machine and an instruction before or after it runs. Breaking stops the
+// program with the reason given.
+pub type Hook<R, W> = Box<dyn FnMut(&Vm<R, W>, &Insn) -> ControlFlow<String>>;
+
+pub struct Vm<R = Stdin, W = Stdout> {
+    insns: Vec<Insn>,
+    ops: Vec<Op>,
+    labels: HashMap<Label, usize>,
+    pub stack: Vec<Value>,
+    pub calls: Vec<usize>,
+    pub heap: Heap,
+    pub ip: usize,
+    pub steps: u64,
+    // The deepest the stack has been.
+    pub max_depth: usize,
+    // The deepest the call stack has been.
+    pub max_call_depth: usize,
+    // Bytes of values too large for an i64 made in total, however many are still around.
+    pub big_allocated: u64,
+    pub halted: bool,
+    pub limits: Limits,
+    pub eof: Eof,
+    // Rounds `div` toward zero and gives `mod` the sign of the dividend, as Rust's operators
+    // do, instead of the floored division the reference implementation uses.
+    pub trunc_div: bool,
+    // Makes out-of-range `copy` and `slide` arguments reach only as far as the stack does,
+    // so that copying too deep copies the bottom value and sliding too far keeps just the
+    // top one, while negative arguments act as 0.
+    pub clamp_args: bool,
+    // Makes a call whose return would go straight on to a `ret`, past any labels and jumps
+    // in between, leave no return address, so that recursion in tail position runs in
+    // constant space. The `ret` and jumps it skips aren't counted as steps.
+    pub tail_calls: bool,
+    // Keeps integers to a fixed width rather than letting them grow without bound.
+    pub int_width: Option<IntWidth>,
+    pub charset: Charset,
+    pub(crate) rng: Rng,
+    // An upper bound on the bignum bytes in use, recounted exactly when it passes the limit.
+    pub(crate) charged: usize,
+    before: Vec<Hook<R, W>>,
+    after: Vec<Hook<R, W>>,
+    host: Host,
+    pub input: R,
+    pub output: W,
+}
+
+impl Vm {
+    // Fails if any instruction refers to a label the program doesn't define.
+    pub fn new(insns: Vec<Insn>, labels: HashMap<Label, usize>) -> Result<Vm> {
+        Vm::with_io(insns, labels, stdin(), stdout())
+    }
+}
+
+impl<R: Read, W: Write> Vm<R, W> {
+    pub fn with_io(insns: Vec<Insn>, labels: HashMap<Label, usize>, input: R, output: W) -> Result<Vm<R, W>> {
+        Vm::with_host(insns, labels, Host::new(), input, output)
+    }
+
+    // Like `with_io`, with calls to labels bound in `host` running native functions.
+    pub fn with_host(
+        insns: Vec<Insn>,
+        labels: HashMap<Label, usize>,
+        host: Host,
+        input: R,
+        output: W,
+    ) -> Result<Vm<R, W>> {
+        let ops = insns.iter().enumerate().map(|(ip, insn)| resolve(insn, ip, &labels, &host)).collect::<Result<_>>()?;
+
+        Ok(Vm {
+            insns,
+            ops,
+            labels,
+            stack: Vec::new(),
+            calls: Vec::new(),
+            heap: Heap::default(),
+            ip: 0,
+            steps: 0,
+            max_depth: 0,
+            max_call_depth: 0,
+            big_allocated: 0,
+            halted: false,
+            limits: Limits::default(),
+            eof: Eof::default(),
+            trunc_div: false,
+            clamp_args: false,
+            tail_calls: false,
+            int_width: None,
+            charset: Charset::default(),
+            rng: Rng::default(),
+            charged: 0,
+            before: Vec::new(),
+            after: Vec::new(),
+            host,
+            input,
+            output,
+        })
+    }
+
+    // Sets where the numbers `random` pushes start from.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng(seed);
+    }
+
+    // Where the numbers `random` pushes have got to, as a seed that carries on from there.
+    pub fn rng_state(&self) -> u64 {
+        self.rng.0
+    }
+
+    // Joins common pairs of instructions so that each runs in a single step, leaving
+    // `steps` counting both. While there are limits or hooks, or the second instruction
+    // would fail, the first runs on its own so that they see every instruction.
+    pub fn fuse(&mut self) {
+        for ip in 1..self.ops.len() {
+            if let Some(op) = fuse(&self.ops[ip - 1], &self.ops[ip]) {
+                self.ops[ip - 1] = op;
+            }
+        }
+    }
+
+    pub fn insns(&self) -> &[Insn] {
+        &self.insns
+    }
+
+    pub fn labels(&self) -> &HashMap<Label, usize> {
+        &self.labels
+    }
+
+    // Whether any calls go to host functions, which only the interpreter can run.
+    #[cfg(feature = "jit")]
+    pub(crate) fn hosted(&self) -> bool {
+        self.ops.iter().any(|op| matches!(op, Op::Host(_)))
+    }
+
+    // Appends instructions to the program, registering any labels they define. Nothing is
+    // appended if they refer to a label that is still undefined.
+    pub fn append(&mut self, insns: Vec<Insn>) -> Result<()> {
+        let mut labels = self.labels.clone();
+        for (i, insn) in insns.iter().enumerate() {
+            if let Insn::Label(l) = insn {
+                labels.insert(l.clone(), self.insns.len() + i);
+            }
+        }
+
+        let start = self.insns.len();
+        let ops = insns
+            .iter()
+            .enumerate()
+            .map(|(i, insn)| resolve(insn, start + i, &labels, &self.host))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.labels = labels;
+        self.ops.extend(ops);
+        self.insns.extend(insns);
+
+        Ok(())
+    }
+
+    pub fn current(&self) -> Option<&Insn> {
+        if self.halted {
+            None
+        } else {
+            self.insns.get(self.ip)
+        }
+    }
+
+    // Rejects an instruction that would take the program past one of its limits.
+    fn check_limits(&self, op: &Op, ip: usize) -> Result<()> {
+        let limits = &self.limits;
+        let exhausted = |resource, limit| Err(AlbusError::ResourceExhausted { ip, resource, limit });
+
+        if let Some(limit) = limits.max_steps {
+            if self.steps >= limit && !matches!(op, Op::Label | Op::None) {
+                return Err(AlbusError::StepLimitExceeded { ip, limit });
+            }
+        }
+        if let Some(deadline) = limits.deadline {
+            if self.steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline {
+                return Err(AlbusError::TimedOut { ip, steps: self.steps });
+            }
+        }
+
+        match op {
+            Op::Push(_) | Op::Dup | Op::Copy(_) => match limits.max_stack {
+                Some(limit) if self.stack.len() >= limit => exhausted("stack", limit),
+                _ => Ok(()),
+            },
+            Op::Call(_) => match limits.max_calls {
+                Some(limit) if self.calls.len() >= limit => exhausted("call stack", limit),
+                _ => Ok(()),
+            },
+            Op::Store | Op::Ichr | Op::Inum => {
+                let depth = if let Op::Store = op { 2 } else { 1 };
+                let key = self.stack.len().checked_sub(depth).map(|i| &self.stack[i]);
+                match (limits.max_heap, key) {
+                    (Some(limit), Some(key)) if self.heap.len() >= limit && !self.heap.contains_key(key) => {
+                        exhausted("heap", limit)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn charge(&mut self, ip: usize, bytes: usize, limit: usize) -> Result<()> {
+        self.charged += bytes;
+        if self.charged > limit {
+            let values = self.stack.iter().chain(self.heap.keys()).chain(self.heap.values());
+            self.charged = values.map(Value::big_bytes).sum();
+            if self.charged > limit {
+                return Err(AlbusError::ResourceExhausted { ip, resource: "bignum memory", limit });
+            }
+        }
+        Ok(())
+    }
+
+    // Adds a hook to call before each instruction runs, not counting those the JIT runs.
+    pub fn on_before_insn(&mut self, hook: impl FnMut(&Vm<R, W>, &Insn) -> ControlFlow<String> + 'static) {
+        self.before.push(Box::new(hook));
+    }
+
+    // Adds a hook to call after each instruction runs successfully.
+    pub fn on_after_insn(&mut self, hook: impl FnMut(&Vm<R, W>, &Insn) -> ControlFlow<String> + 'static) {
+        self.after.push(Box::new(hook));
+    }
+
+    // Runs each of the hooks for the instruction at `ip`, stopping at the first that breaks.
+    fn call_hooks(&mut self, after: bool, ip: usize) -> Result<()> {
+        let mut hooks = std::mem::take(if after { &mut self.after } else { &mut self.before });
+        let flow = hooks.iter_mut().try_for_each(|hook| hook(self, &self.insns[ip]));
+        *(if after { &mut self.after } else { &mut self.before }) = hooks;
+        match flow {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(reason) => Err(AlbusError::Stopped { ip, reason }),
+        }
+    }
+
+    // Executes the instruction at `ip`, returning false once the program has halted.
+    pub fn step(&mut self) -> Result<bool> {
+        let ip = self.ip;
+        if (self.before.is_empty() && self.after.is_empty()) || self.current().is_none() {
+            return self.execute();
+        }
+        self.call_hooks(false, ip)?;
+        let running = self.execute()?;
+        self.call_hooks(true, ip)?;
+        Ok(running)
+    }
+
+    // Whether a fused pair can run in one go, for which neither half may fail and nothing
+    // may need to see them one at a time.
+    fn joinable(&self, op: &Op) -> bool {
+        let ready = match op {
+            Op::PushLoad(k) => self.heap.contains_key(k),
+            _ => !self.stack.is_empty(),
+        };
+        // Neither half of a pair is a call, so the call depth can be limited.
+        let unlimited = matches!(
+            self.limits,
+            Limits { max_steps: None, max_stack: None, max_heap: None, max_bytes: None, deadline: None, .. }
+        );
+        let plain = unlimited && self.int_width.is_none();
+        ready && plain && self.before.is_empty() && self.after.is_empty()
+    }
+
+    // Runs the first instruction of the fused pair at `ip` by itself.
+    fn unfused(&mut self) -> Result<bool> {
+        let ip = self.ip;
+        let op = resolve(&self.insns[ip], ip, &self.labels, &self.host)?;
+        let fused = std::mem::replace(&mut self.ops[ip], op);
+        let result = self.execute();
+        self.ops[ip] = fused;
+        result
+    }
+
+    fn execute(&mut self) -> Result<bool> {
+        let op = match self.ops.get(self.ip) {
+            Some(op) if !self.halted => op,
+            _ => return Ok(self.halt()),
+        };
+        if op.fused() && !self.joinable(op) {
+            return self.unfused();
+        }
+        let ip = self.ip;
+        self.check_limits(op, ip)?;
+
+        let underflow = || AlbusError::StackUnderflow { ip };
+        let io = |error| AlbusError::IoError { ip, error };
+        let stack = &mut self.stack;
+        let pushes = matches!(
+            op,
+            Op::Push(_) | Op::Dup | Op::Copy(_) | Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Load
+        );
+        let mut created = 0;
+
+        self.steps += 1;
+        match op {
+            Op::Push(v) => stack.push(v.clone()),
+            Op::Copy(arg) => {
+                let top = stack.len().checked_sub(1).ok_or_else(underflow)?;
+                let n = reach(arg, top, self.clamp_args, ip)?;
+                stack.push(stack[top - n].clone());
+            }
+            Op::Slide(arg) => {
+                let top = stack.len().checked_sub(1).ok_or_else(underflow)?;
+                let k = reach(arg, top, self.clamp_args, ip)?;
+                stack.drain(top - k..top);
+            }
+            Op::Label | Op::None => self.steps -= 1,
+            Op::Call(target) => {
+                if !self.tail_calls || !returns(&self.ops, ip + 1) {
+                    self.calls.push(ip);
+                    self.max_call_depth = self.max_call_depth.max(self.calls.len());
+                }
+                self.ip = *target;
+            }
+            Op::Jump(target) => self.ip = *target,
+            Op::Jz(target) => {
+                if stack.pop().ok_or_else(underflow)?.is_zero() {
+                    self.ip = *target;
+                }
+            }
+            Op::Jn(target) => {
+                if stack.pop().ok_or_else(underflow)?.is_negative() {
+                    self.ip = *target;
+                }
+            }
+            Op::Pop => {
+                stack.pop().ok_or_else(underflow)?;
+            }
+            Op::Dup => stack.push(stack.last().ok_or_else(underflow)?.clone()),
+            Op::Swap => {
+                let n = stack.len();
+                if n < 2 {
+                    return Err(underflow());
+                }
+                stack.swap(n - 1, n - 2);
+            }
+            Op::Add => {
+                let (r, l) = operands(stack).ok_or_else(underflow)?;
+                *l = &*l + &r;
+            }
+            Op::Sub => {
+                let (r, l) = operands(stack).ok_or_else(underflow)?;
+                *l = &*l - &r;
+            }
+            Op::Mul => {
+                let (r, l) = operands(stack).ok_or_else(underflow)?;
+                *l = &*l * &r;
+            }
+            Op::Div => {
+                let (r, l) = operands(stack).ok_or_else(underflow)?;
+                if r.is_zero() {
+                    return Err(AlbusError::DivisionByZero { ip });
+                }
+                *l = if self.trunc_div { &*l / &r } else { l.div_floor(&r) };
+            }
+            Op::Mod => {
+                let (r, l) = operands(stack).ok_or_else(underflow)?;
+                if r.is_zero() {
+                    return Err(AlbusError::DivisionByZero { ip });
+                }
+                *l = if self.trunc_div { &*l % &r } else { l.mod_floor(&r) };
+            }
+            Op::Store => {
+                if stack.len() < 2 {
+                    return Err(underflow());
+                }
+                let v = stack.pop().ok_or_else(underflow)?;
+                let k = stack.pop().ok_or_else(underflow)?;
+                self.heap.insert(k, v);
+            }
+            Op::Load => {
+                let k = stack.pop().ok_or_else(underflow)?;
+                match self.heap.get(&k) {
+                    Some(v) => stack.push(v.clone()),
+                    None => return Err(AlbusError::UninitializedHeap { ip, key: k.into() }),
+                }
+            }
+            Op::Host(i) => {
+                let (label, arity, f) = &mut self.host.functions[*i];
+                let args = stack.split_off(stack.len().checked_sub(*arity).ok_or_else(underflow)?);
+                let results = f(args).map_err(|reason| AlbusError::HostFailed { ip, label: label.clone(), reason })?;
+                created = results.iter().map(Value::big_bytes).sum();
+                stack.extend(results);
+                self.max_depth = self.max_depth.max(stack.len());
+            }
+            Op::Ret => self.ip = self.calls.pop().ok_or(AlbusError::CallStackUnderflow { ip })?,
+            Op::Ichr => {
+                let k = stack.pop().ok_or_else(underflow)?;
+                // A prompt written just before should be seen before waiting on input.
+                self.output.flush().map_err(io)?;
+                let v = match self.charset.read(&mut self.input).map_err(io)? {
+                    None => self.eof.value(ip)?,
+                    Some(c) => Some(Value::Small(c.into())),
+                };
+                match v {
+                    Some(v) => self.heap.insert(k, v),
+                    None => return Ok(self.halt()),
+                };
+            }
+            Op::Inum => {
+                let k = stack.pop().ok_or_else(underflow)?;
+                self.output.flush().map_err(io)?;
+                let n = read_line(&mut self.input);
+                let v = if n.is_empty() {
+                    match self.eof.value(ip)? {
+                        Some(v) => v,
+                        None => return Ok(self.halt()),
+                    }
+                } else {
+                    Value::from(n.trim_end().parse::<Num>().map_err(|_| AlbusError::BadInput { ip, input: n })?)
+                };
+                let v = match self.int_width {
+                    Some(width) => width.fit(v),
+                    None => v,
+                };
+                created = v.big_bytes();
+                self.heap.insert(k, v);
+            }
+            Op::Ochr => {
+                let v = stack.pop().ok_or_else(underflow)?;
+                let (charset, output) = (self.charset, &mut self.output);
+                match v.to_usize().and_then(|n| charset.write(output, n)) {
+                    Some(result) => result.map_err(io)?,
+                    None => return Err(AlbusError::BadChar { ip, value: v.into() }),
+                }
+            }
+            Op::Onum => write!(self.output, "{}", stack.pop().ok_or_else(underflow)?).map_err(io)?,
+            Op::Exit => return Ok(self.halt()),
+            Op::Random => {
+                let top = stack.last_mut().ok_or_else(underflow)?;
+                match top.to_usize().filter(|&n| n > 0) {
+                    Some(n) => *top = Value::Small(self.rng.below(n as u64) as i64),
+                    None => return Err(AlbusError::BadArgument { ip, arg: top.to_num() }),
+                }
+            }
+            // Output is flushed first so that the dump lands after whatever came before it.
+            Op::DumpStack => {
+                self.output.flush().map_err(io)?;
+                let values: Vec<_> = stack.iter().map(Value::to_string).collect();
+                eprintln!("stack: [{}]", values.join(", "));
+            }
+            Op::DumpHeap => {
+                self.output.flush().map_err(io)?;
+                let mut heap: Vec<_> = self.heap.iter().collect();
+                heap.sort();
+                let cells: Vec<_> = heap.into_iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                eprintln!("heap: {{{}}}", cells.join(", "));
+            }
+            // The second half of each of these moves `ip` along itself.
+            Op::PushAdd(v) | Op::PushSub(v) | Op::PushMul(v) => {
+                let l = stack.last_mut().ok_or_else(underflow)?;
+                *l = match op {
+                    Op::PushAdd(_) => &*l + v,
+                    Op::PushSub(_) => &*l - v,
+                    _ => &*l * v,
+                };
+                created = v.big_bytes() + l.big_bytes();
+                self.ip += 1;
+            }
+            Op::PushStore(v) => {
+                let k = stack.pop().ok_or_else(underflow)?;
+                self.heap.insert(k, v.clone());
+                created = v.big_bytes();
+                self.ip += 1;
+            }
+            Op::PushLoad(k) => {
+                let v = self.heap.get(k).ok_or_else(|| AlbusError::UninitializedHeap { ip, key: k.to_num() })?;
+                stack.push(v.clone());
+                created = k.big_bytes() + v.big_bytes();
+                self.ip += 1;
+            }
+            Op::DupJz(target) | Op::DupJn(target) => {
+                let top = stack.last().ok_or_else(underflow)?;
+                created = top.big_bytes();
+                let taken = if let Op::DupJz(_) = op { top.is_zero() } else { top.is_negative() };
+                self.ip = if taken { *target } else { self.ip + 1 };
+            }
+        }
+
+        if let Some(width) = self.int_width {
+            if matches!(op, Op::Push(_) | Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod) {
+                if let Some(top) = stack.last_mut() {
+                    *top = width.fit(std::mem::replace(top, Value::Small(0)));
+                }
+            }
+        }
+        // A fused pair is counted as the two instructions it stands for would have been.
+        if op.fused() {
+            self.steps += 1;
+            self.max_depth = self.max_depth.max(stack.len() + op.peak());
+        }
+        if pushes {
+            created = stack.last().map_or(0, Value::big_bytes);
+            self.max_depth = self.max_depth.max(stack.len());
+        }
+        self.big_allocated += created as u64;
+        if let Some(limit) = self.limits.max_bytes.filter(|_| created > 0) {
+            self.charge(ip, created, limit)?;
+        }
+        self.ip += 1;
+
+        Ok(true)
+    }
+
+    // Output is flushed on the way out, without an error for a program that has finished.
+    fn halt(&mut self) -> bool {
+        self.output.flush().ok();
+        self.halted = true;
+        false
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? {}
+
+        Ok(())
+    }
+
+    // Calls the subroutine at `label` with `args` pushed, the last on top, and runs it until
+    // it returns or the program exits, taking off the stack and returning whatever it left
+    // above where the arguments went. The heap is kept and `ip` restored, so that a host can
+    // call one function after another as from a library. A call that fails leaves the
+    // machine as the error found it.
+    pub fn call(&mut self, label: &Label, args: &[Num]) -> Result<Vec<Num>> {
+        let undefined = || AlbusError::UndefinedLabel { ip: self.ip, label: label.clone() };
+        let target = *self.labels.get(label).ok_or_else(undefined)?;
+        let (ip, depth, base) = (self.ip, self.calls.len(), self.stack.len());
+
+        self.stack.extend(args.iter().map(Value::from));
+        // Returning lands past the end of the program, where a stray step would halt.
+        self.calls.push(self.insns.len());
+        self.ip = target;
+        self.halted = false;
+        while self.calls.len() > depth && self.step()? {}
+        self.calls.truncate(depth);
+        self.ip = ip;
+
+        let base = base.min(self.stack.len());
+        Ok(self.stack.split_off(base).into_iter().map(Num::from).collect())
+    }
+}
+
+// Why a program stopped.
+#[derive(Debug)]
+pub enum ExitReason {
+    // It ran an `exit`.
+    Exit,
+    // It ran past its last instruction.
+    End,
+    // It read past the end of input with `eof` set to halt.
+    Eof,
+    Error(AlbusError),
+}
+
+// How a program run by `interpret` finished: what it left on the stack and in the heap, how
+// many instructions it ran, why it stopped and everything it wrote.
+#[derive(Debug)]
+pub struct RunResult {
+    pub stack: Vec<Num>,
+    pub heap: HashMap<Num, Num>,
+    pub steps: u64,
+    pub exit: ExitReason,
+    pub output: Vec<u8>,
+}
+
+// Runs a program reading from stdin, failing only if it can't start. An error while it runs
+// is the reason it stopped, with everything up to then still there.
+pub fn interpret(insns: Vec<Insn>, labels: HashMap<Label, usize>) -> Result<RunResult> {
+    let mut vm = Vm::with_io(insns, labels, stdin(), Vec::new())?;
+    let exit = match vm.run() {
+        Err(e) => ExitReason::Error(e),
+        Ok(()) => match vm.insns.get(vm.ip) {
+            None => ExitReason::End,
+            Some(Insn::Ichr) | Some(Insn::Inum) => ExitReason::Eof,
+            Some(_) => ExitReason::Exit,
+        },
+    };
+
+    Ok(RunResult {
+        stack: vm.stack.into_iter().map(Num::from).collect(),
+        heap: vm.heap.iter().map(|(k, v)| (k.to_num(), v.to_num())).collect(),
+        steps: vm.steps,
+        exit,
+        output: vm.output,
+    })
+}
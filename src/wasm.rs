@@ -0,0 +1,455 @@
+use crate::{block::blocks, AlbusError, Insn, Label, Result};
+use hashbrown::HashMap;
+use num_traits::ToPrimitive;
+
+// Values are 64-bit wrapping integers. Imports come from the "albus" module: `ochr(i64)`,
+// `onum(i64)`, `ichr() -> i64`, `inum() -> i64`, and `fail(code, ip)`, which must not
+// return. The exported `run` function executes the program against exported `memory`.
+pub const FAIL_UNDERFLOW: i32 = 1;
+pub const FAIL_RETURN: i32 = 2;
+pub const FAIL_ARGUMENT: i32 = 3;
+pub const FAIL_CHAR: i32 = 4;
+pub const FAIL_UNINITIALIZED: i32 = 5;
+pub const FAIL_DIVISION: i32 = 6;
+pub const FAIL_LABEL: i32 = 7;
+pub const FAIL_LIMIT: i32 = 8;
+
+const STACK: i32 = 0;
+const STACK_CAP: i32 = 1 << 16;
+const CALLS: i32 = STACK + STACK_CAP * 8;
+const CALL_CAP: i32 = 1 << 16;
+const HEAP: i32 = CALLS + CALL_CAP * 4;
+const HEAP_CAP: i32 = 1 << 16;
+const FLAGS: i32 = HEAP + HEAP_CAP * 8;
+const PAGES: u32 = ((FLAGS + HEAP_CAP) / 65536) as u32;
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const EMPTY: u8 = 0x40;
+
+// Function indices: imports first, then the helpers defined below, then `run`.
+const OCHR: u32 = 0;
+const ONUM: u32 = 1;
+const ICHR: u32 = 2;
+const INUM: u32 = 3;
+const FAIL: u32 = 4;
+const PUSH: u32 = 5;
+const POP: u32 = 6;
+const COPY: u32 = 7;
+const SLIDE: u32 = 8;
+const SWAP: u32 = 9;
+const CALL: u32 = 10;
+const RET: u32 = 11;
+const ADDR: u32 = 12;
+const RUN: u32 = 13;
+
+const SP: u32 = 0;
+const CSP: u32 = 1;
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn op(&mut self, op: u8) -> &mut Code {
+        self.0.push(op);
+        self
+    }
+
+    fn idx(&mut self, op: u8, n: u32) -> &mut Code {
+        self.0.push(op);
+        uleb(&mut self.0, n as u64);
+        self
+    }
+
+    fn block(&mut self, op: u8, ty: u8) -> &mut Code {
+        self.0.extend_from_slice(&[op, ty]);
+        self
+    }
+
+    fn i32(&mut self, n: i32) -> &mut Code {
+        self.0.push(0x41);
+        sleb(&mut self.0, n as i64);
+        self
+    }
+
+    fn i64(&mut self, n: i64) -> &mut Code {
+        self.0.push(0x42);
+        sleb(&mut self.0, n);
+        self
+    }
+
+    fn mem(&mut self, op: u8, align: u32, offset: i32) -> &mut Code {
+        self.0.push(op);
+        uleb(&mut self.0, align as u64);
+        uleb(&mut self.0, offset as u64);
+        self
+    }
+
+    fn call(&mut self, f: u32) -> &mut Code {
+        self.idx(0x10, f)
+    }
+
+    fn local(&mut self, n: u32) -> &mut Code {
+        self.idx(0x20, n)
+    }
+
+    fn set_local(&mut self, n: u32) -> &mut Code {
+        self.idx(0x21, n)
+    }
+
+    fn global(&mut self, n: u32) -> &mut Code {
+        self.idx(0x23, n)
+    }
+
+    fn set_global(&mut self, n: u32) -> &mut Code {
+        self.idx(0x24, n)
+    }
+
+    fn end(&mut self) -> &mut Code {
+        self.op(0x0b)
+    }
+
+    // Calls `fail(code, ip)` when the i32 condition on the operand stack holds.
+    fn fail_if(&mut self, code: i32, ip: u32) -> &mut Code {
+        self.block(0x04, EMPTY).i32(code).local(ip).call(FAIL).op(0x00).end()
+    }
+
+    fn fail(&mut self, code: i32, ip: usize) -> &mut Code {
+        self.i32(code).i32(ip as i32).call(FAIL).op(0x00)
+    }
+}
+
+fn section(out: &mut Vec<u8>, id: u8, items: Vec<Vec<u8>>) {
+    let mut body = Vec::new();
+    uleb(&mut body, items.len() as u64);
+    for item in items {
+        body.extend(item);
+    }
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend(body);
+}
+
+fn name(out: &mut Vec<u8>, s: &str) {
+    uleb(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn functype(params: &[u8], results: &[u8]) -> Vec<u8> {
+    let mut ty = vec![0x60, params.len() as u8];
+    ty.extend_from_slice(params);
+    ty.push(results.len() as u8);
+    ty.extend_from_slice(results);
+    ty
+}
+
+fn body(locals: &[(u32, u8)], code: &Code) -> Vec<u8> {
+    let mut func = Vec::new();
+    uleb(&mut func, locals.len() as u64);
+    for &(n, ty) in locals {
+        uleb(&mut func, n as u64);
+        func.push(ty);
+    }
+    func.extend_from_slice(&code.0);
+    func.push(0x0b);
+
+    let mut out = Vec::new();
+    uleb(&mut out, func.len() as u64);
+    out.extend(func);
+    out
+}
+
+struct Func {
+    ty: u8,
+    locals: Vec<(u32, u8)>,
+    code: Code,
+}
+
+// Helper bodies, indexed from PUSH. Parameters come first in each function's locals.
+fn helpers() -> Vec<Func> {
+    let mut push = Code::default();
+    push.global(SP).i32(STACK_CAP).op(0x46);
+    push.block(0x04, EMPTY).i32(FAIL_LIMIT).i32(-1).call(FAIL).op(0x00).end();
+    push.global(SP).i32(8).op(0x6c).local(0).mem(0x37, 3, STACK);
+    push.global(SP).i32(1).op(0x6a).set_global(SP);
+
+    let mut pop = Code::default();
+    pop.global(SP).op(0x45).fail_if(FAIL_UNDERFLOW, 0);
+    pop.global(SP).i32(1).op(0x6b).set_global(SP);
+    pop.global(SP).i32(8).op(0x6c).mem(0x29, 3, STACK);
+
+    // copy(n, ip, code): push the value n below the top, failing with `code` if there is none.
+    let mut copy = Code::default();
+    copy.local(0).global(SP).op(0x4f);
+    copy.block(0x04, EMPTY).local(2).local(1).call(FAIL).op(0x00).end();
+    copy.global(SP).local(0).op(0x6b).i32(1).op(0x6b).i32(8).op(0x6c).mem(0x29, 3, STACK).call(PUSH);
+
+    // slide(n, ip): keep the top, discard the n values beneath it.
+    let mut slide = Code::default();
+    slide.global(SP).op(0x45).fail_if(FAIL_UNDERFLOW, 1);
+    slide.local(0).global(SP).i32(1).op(0x6b).op(0x4b).fail_if(FAIL_ARGUMENT, 1);
+    slide.local(1).call(POP).set_local(2);
+    slide.global(SP).local(0).op(0x6b).set_global(SP);
+    slide.local(2).call(PUSH);
+
+    let mut swap = Code::default();
+    swap.global(SP).i32(2).op(0x49).fail_if(FAIL_UNDERFLOW, 0);
+    swap.local(0).call(POP).set_local(1).local(0).call(POP).set_local(2);
+    swap.local(1).call(PUSH).local(2).call(PUSH);
+
+    let mut call = Code::default();
+    call.global(CSP).i32(CALL_CAP).op(0x46);
+    call.block(0x04, EMPTY).i32(FAIL_LIMIT).i32(-1).call(FAIL).op(0x00).end();
+    call.global(CSP).i32(4).op(0x6c).local(0).mem(0x36, 2, CALLS);
+    call.global(CSP).i32(1).op(0x6a).set_global(CSP);
+
+    let mut ret = Code::default();
+    ret.global(CSP).op(0x45).fail_if(FAIL_RETURN, 0);
+    ret.global(CSP).i32(1).op(0x6b).set_global(CSP);
+    ret.global(CSP).i32(4).op(0x6c).mem(0x28, 2, CALLS);
+
+    // addr(key, ip) -> cell index, failing for keys outside the heap region.
+    let mut addr = Code::default();
+    addr.local(0).i64(HEAP_CAP as i64).op(0x5a).fail_if(FAIL_LIMIT, 1);
+    addr.local(0).op(0xa7);
+
+    let func = |ty, locals, code| Func { ty, locals, code };
+    vec![
+        func(0, vec![], push),
+        func(3, vec![], pop),
+        func(4, vec![], copy),
+        func(2, vec![(1, I64)], slide),
+        func(5, vec![(2, I64)], swap),
+        func(5, vec![], call),
+        func(6, vec![], ret),
+        func(7, vec![], addr),
+    ]
+}
+
+fn arith(code: &mut Code, ip: i32, op: u8, divides: bool) {
+    code.global(SP).i32(2).op(0x49);
+    code.block(0x04, EMPTY).i32(FAIL_UNDERFLOW).i32(ip).call(FAIL).op(0x00).end();
+    code.i32(ip).call(POP).set_local(1).i32(ip).call(POP).set_local(0);
+    if divides {
+        code.local(1).op(0x50);
+        code.block(0x04, EMPTY).i32(FAIL_DIVISION).i32(ip).call(FAIL).op(0x00).end();
+    }
+    code.local(0).local(1).op(op);
+    if divides {
+        // Floors the result: a nonzero remainder on operands of differing signs moves the
+        // quotient down by one and the remainder over by the divisor.
+        code.local(0).local(1).op(0x81).i64(0).op(0x52);
+        code.local(0).local(1).op(0x85).i64(0).op(0x53).op(0x71);
+        if op == 0x7f {
+            code.op(0xad).op(0x7d);
+        } else {
+            code.set_local(3).local(1).i64(0).local(3).op(0x1b).op(0x7c);
+        }
+    }
+    code.call(PUSH);
+}
+
+pub fn compile(insns: &[Insn], labels: &HashMap<Label, usize>) -> Result<Vec<u8>> {
+    let blocks = blocks(insns);
+    let n = blocks.len() as u32;
+    let ids: HashMap<usize, u32> = blocks.iter().enumerate().map(|(id, b)| (b.range.start, id as u32)).collect();
+    let mut code = Code::default();
+
+    code.block(0x03, EMPTY).block(0x02, EMPTY);
+    for _ in 0..n {
+        code.block(0x02, EMPTY);
+    }
+    code.local(2).op(0x0e);
+    uleb(&mut code.0, n as u64);
+    for depth in 0..=n {
+        uleb(&mut code.0, depth as u64);
+    }
+    code.end();
+
+    for (k, block) in blocks.iter().enumerate() {
+        let k = k as u32;
+        // Branch depths to the dispatch loop and the halt block from inside block k.
+        let (dispatch, halt) = (n - k, n - k - 1);
+        let goto = |code: &mut Code, l: &Label, ip: usize, depth: u32| match labels.get(l) {
+            Some(t) => {
+                code.i32(ids[t] as i32).set_local(2).idx(0x0c, depth);
+            }
+            None => {
+                code.fail(FAIL_LABEL, ip);
+            }
+        };
+
+        for ip in block.range.clone() {
+            let at = ip as i32;
+            match &insns[ip] {
+                // Modules can't reach stderr through their imports, so dumps do nothing.
+                Insn::None | Insn::Label(_) | Insn::DumpStack | Insn::DumpHeap => {}
+                Insn::Random => return Err(AlbusError::Unsupported { ip, what: "random", target: "wasm" }),
+                Insn::Push(v) => {
+                    let v = v.to_i64().ok_or_else(|| AlbusError::BadArgument { ip, arg: v.clone() })?;
+                    code.i64(v).call(PUSH);
+                }
+                Insn::Pop => {
+                    code.i32(at).call(POP).op(0x1a);
+                }
+                Insn::Dup => {
+                    code.i32(0).i32(at).i32(FAIL_UNDERFLOW).call(COPY);
+                }
+                Insn::Copy(v) => match v.to_i32() {
+                    Some(v) if v >= 0 => {
+                        code.i32(v).i32(at).i32(FAIL_ARGUMENT).call(COPY);
+                    }
+                    _ => {
+                        code.fail(FAIL_ARGUMENT, ip);
+                    }
+                },
+                Insn::Slide(v) => match v.to_i32() {
+                    Some(v) if v >= 0 => {
+                        code.i32(v).i32(at).call(SLIDE);
+                    }
+                    _ => {
+                        code.fail(FAIL_ARGUMENT, ip);
+                    }
+                },
+                Insn::Swap => {
+                    code.i32(at).call(SWAP);
+                }
+                Insn::Add => arith(&mut code, at, 0x7c, false),
+                Insn::Sub => arith(&mut code, at, 0x7d, false),
+                Insn::Mul => arith(&mut code, at, 0x7e, false),
+                Insn::Div => arith(&mut code, at, 0x7f, true),
+                Insn::Mod => arith(&mut code, at, 0x81, true),
+                Insn::Store => {
+                    code.global(SP).i32(2).op(0x49);
+                    code.block(0x04, EMPTY).i32(FAIL_UNDERFLOW).i32(at).call(FAIL).op(0x00).end();
+                    code.i32(at).call(POP).set_local(1);
+                    code.i32(at).call(POP).i32(at).call(ADDR).set_local(3);
+                    code.local(3).i32(8).op(0x6c).local(1).mem(0x37, 3, HEAP);
+                    code.local(3).i32(1).mem(0x3a, 0, FLAGS);
+                }
+                Insn::Load => {
+                    code.i32(at).call(POP).i32(at).call(ADDR).set_local(3);
+                    code.local(3).mem(0x2d, 0, FLAGS).op(0x45);
+                    code.block(0x04, EMPTY).i32(FAIL_UNINITIALIZED).i32(at).call(FAIL).op(0x00).end();
+                    code.local(3).i32(8).op(0x6c).mem(0x29, 3, HEAP).call(PUSH);
+                }
+                Insn::Ichr | Insn::Inum => {
+                    let read = if insns[ip] == Insn::Ichr { ICHR } else { INUM };
+                    code.i32(at).call(POP).i32(at).call(ADDR).set_local(3);
+                    code.local(3).i32(8).op(0x6c).call(read).mem(0x37, 3, HEAP);
+                    code.local(3).i32(1).mem(0x3a, 0, FLAGS);
+                }
+                Insn::Ochr => {
+                    code.i32(at).call(POP).set_local(0);
+                    code.local(0).i64(255).op(0x58).op(0x45);
+                    code.block(0x04, EMPTY).i32(FAIL_CHAR).i32(at).call(FAIL).op(0x00).end();
+                    code.local(0).call(OCHR);
+                }
+                Insn::Onum => {
+                    code.i32(at).call(POP).call(ONUM);
+                }
+                Insn::Call(l) => {
+                    code.i32(k as i32 + 1).call(CALL);
+                    goto(&mut code, l, ip, dispatch);
+                }
+                Insn::Jump(l) => goto(&mut code, l, ip, dispatch),
+                Insn::Jz(l) | Insn::Jn(l) => {
+                    code.i32(at).call(POP);
+                    if let Insn::Jz(_) = insns[ip] {
+                        code.op(0x50);
+                    } else {
+                        code.i64(0).op(0x53);
+                    }
+                    code.block(0x04, EMPTY);
+                    goto(&mut code, l, ip, dispatch + 1);
+                    code.end();
+                }
+                Insn::Ret => {
+                    code.i32(at).call(RET).set_local(2).idx(0x0c, dispatch);
+                }
+                Insn::Exit => {
+                    code.idx(0x0c, halt);
+                }
+            }
+        }
+        code.end();
+    }
+    code.end();
+
+    let mut out = b"\0asm\x01\0\0\0".to_vec();
+    let types = vec![
+        functype(&[I64], &[]),
+        functype(&[], &[I64]),
+        functype(&[I32, I32], &[]),
+        functype(&[I32], &[I64]),
+        functype(&[I32, I32, I32], &[]),
+        functype(&[I32], &[]),
+        functype(&[I32], &[I32]),
+        functype(&[I64, I32], &[I32]),
+        functype(&[], &[]),
+    ];
+    section(&mut out, 1, types);
+
+    let imports = [("ochr", 0), ("onum", 0), ("ichr", 1), ("inum", 1), ("fail", 2)]
+        .iter()
+        .map(|&(field, ty)| {
+            let mut import = Vec::new();
+            name(&mut import, "albus");
+            name(&mut import, field);
+            import.extend_from_slice(&[0x00, ty]);
+            import
+        })
+        .collect();
+    section(&mut out, 2, imports);
+
+    let helpers = helpers();
+    let mut funcs: Vec<Vec<u8>> = helpers.iter().map(|f| vec![f.ty]).collect();
+    funcs.push(vec![8]);
+    section(&mut out, 3, funcs);
+
+    let mut memory = vec![0x00];
+    uleb(&mut memory, PAGES as u64);
+    section(&mut out, 5, vec![memory]);
+
+    let global = vec![I32, 0x01, 0x41, 0x00, 0x0b];
+    section(&mut out, 6, vec![global.clone(), global]);
+
+    let mut run = Vec::new();
+    name(&mut run, "run");
+    run.push(0x00);
+    uleb(&mut run, RUN as u64);
+    let mut mem = Vec::new();
+    name(&mut mem, "memory");
+    mem.extend_from_slice(&[0x02, 0x00]);
+    section(&mut out, 7, vec![run, mem]);
+
+    let mut bodies: Vec<Vec<u8>> = helpers.iter().map(|f| body(&f.locals, &f.code)).collect();
+    bodies.push(body(&[(2, I64), (2, I32)], &code));
+    section(&mut out, 10, bodies);
+
+    Ok(out)
+}
@@ -0,0 +1,544 @@
+// Decompresses Zstandard frames, as RFC 8878 describes, for sources shipped compressed.
+// Dictionaries aren't supported and checksums aren't checked.
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+const EARLY: &str = "the data ends early";
+
+pub(crate) const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+fn highest_bit(n: u32) -> u32 {
+    31 - n.leading_zeros()
+}
+
+// Reads little-endian bits forward, for table descriptions.
+struct Forward<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl Forward<'_> {
+    fn peek(&self, n: u32) -> u32 {
+        (0..n as usize).fold(0, |value, i| {
+            let at = self.bit + i;
+            let bit = self.data.get(at / 8).map_or(0, |&b| (b >> (at % 8)) & 1);
+            value | (bit as u32) << i
+        })
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        if self.bit + n as usize > self.data.len() * 8 {
+            return Err(EARLY);
+        }
+        let value = self.peek(n);
+        self.bit += n as usize;
+        Ok(value)
+    }
+
+    fn bytes(&self) -> usize {
+        self.bit.div_ceil(8)
+    }
+}
+
+// Reads bits backward from the end of a stream, past the 1 bit that pads its last byte,
+// with zeroes for any wanted from before its start.
+struct Backward<'a> {
+    data: &'a [u8],
+    offset: isize,
+}
+
+impl Backward<'_> {
+    fn new(data: &[u8]) -> Result<Backward<'_>> {
+        let last = *data.last().ok_or(EARLY)?;
+        if last == 0 {
+            return Err("a bitstream has no end marker");
+        }
+        Ok(Backward { data, offset: (data.len() * 8) as isize - 8 + highest_bit(last as u32) as isize })
+    }
+
+    fn bits(&mut self, n: u32) -> u64 {
+        self.offset -= n as isize;
+        let (start, skipped) = if self.offset < 0 { (0, (-self.offset) as u32) } else { (self.offset as usize, 0) };
+        let wanted = n.saturating_sub(skipped);
+        let value = (0..wanted as usize).fold(0u64, |value, i| {
+            let at = start + i;
+            value | (((self.data[at / 8] >> (at % 8)) & 1) as u64) << i
+        });
+        if skipped >= 64 {
+            0
+        } else {
+            value << skipped
+        }
+    }
+}
+
+// A table for decoding finite state entropy, giving for each state a symbol and how to
+// reach the next state.
+#[derive(Clone, Default)]
+struct Fse {
+    log: u32,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+    bases: Vec<u16>,
+}
+
+impl Fse {
+    fn new(counts: &[i16], log: u32) -> Result<Fse> {
+        let size = 1usize << log;
+        let mut symbols = vec![0; size];
+        let mut next = vec![0u32; counts.len()];
+        let mut high = size;
+        for (s, &count) in counts.iter().enumerate() {
+            if count == -1 {
+                high -= 1;
+                symbols[high] = s as u8;
+                next[s] = 1;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        for (s, &count) in counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            next[s] = count as u32;
+            for _ in 0..count {
+                symbols[pos] = s as u8;
+                pos = (pos + step) & (size - 1);
+                while pos >= high {
+                    pos = (pos + step) & (size - 1);
+                }
+            }
+        }
+        if pos != 0 {
+            return Err("a table's counts don't add up");
+        }
+        let (mut bits, mut bases) = (vec![0; size], vec![0; size]);
+        for state in 0..size {
+            let s = symbols[state] as usize;
+            let desc = next[s];
+            next[s] += 1;
+            bits[state] = (log - highest_bit(desc)) as u8;
+            bases[state] = ((desc << bits[state]) as usize - size) as u16;
+        }
+        Ok(Fse { log, symbols, bits, bases })
+    }
+
+    fn rle(symbol: u8) -> Fse {
+        Fse { log: 0, symbols: vec![symbol], bits: vec![0], bases: vec![0] }
+    }
+
+    // Reads a table's description, returning the table and how many bytes it took.
+    fn read(data: &[u8], max_log: u32, max_symbols: usize) -> Result<(Fse, usize)> {
+        let mut bits = Forward { data, bit: 0 };
+        let log = bits.bits(4)? + 5;
+        if log > max_log {
+            return Err("a table is too large");
+        }
+        let mut remaining = 1i32 << log;
+        let mut counts = Vec::new();
+        while remaining > 0 && counts.len() < max_symbols {
+            let width = highest_bit(remaining as u32 + 1) + 1;
+            let mut value = bits.peek(width);
+            let lower = (1 << (width - 1)) - 1;
+            let threshold = (1 << width) - 1 - (remaining as u32 + 1);
+            if value & lower < threshold {
+                bits.bits(width - 1)?;
+                value &= lower;
+            } else {
+                bits.bits(width)?;
+                if value > lower {
+                    value -= threshold;
+                }
+            }
+            let count = value as i16 - 1;
+            remaining -= count.unsigned_abs() as i32;
+            counts.push(count);
+            if count == 0 {
+                loop {
+                    let repeat = bits.bits(2)?;
+                    counts.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 0 || counts.len() > max_symbols {
+            return Err("a table's counts don't add up");
+        }
+        Ok((Fse::new(&counts, log)?, bits.bytes()))
+    }
+
+    fn init(&self, bits: &mut Backward) -> usize {
+        bits.bits(self.log) as usize
+    }
+
+    fn update(&self, state: &mut usize, bits: &mut Backward) {
+        *state = self.bases[*state] as usize + bits.bits(self.bits[*state] as u32) as usize;
+    }
+}
+
+// A Huffman table for literals, indexed by the next `log` bits of the stream.
+#[derive(Clone, Default)]
+struct Huffman {
+    log: u32,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+}
+
+impl Huffman {
+    fn from_weights(mut weights: Vec<u8>) -> Result<Huffman> {
+        let sum: u32 = weights.iter().filter(|&&w| w > 0).map(|&w| 1 << (w - 1)).sum();
+        if sum == 0 {
+            return Err("a Huffman table has no weights");
+        }
+        let log = highest_bit(sum) + 1;
+        let left = (1 << log) - sum;
+        if !left.is_power_of_two() {
+            return Err("a Huffman table's weights don't add up");
+        }
+        weights.push(highest_bit(left) as u8 + 1);
+        let lengths: Vec<u32> = weights.iter().map(|&w| if w > 0 { log + 1 - w as u32 } else { 0 }).collect();
+
+        let size = 1usize << log;
+        let mut ranks = vec![0usize; log as usize + 2];
+        for &len in lengths.iter().filter(|&&len| len > 0) {
+            ranks[len as usize] += 1;
+        }
+        let mut starts = vec![0usize; log as usize + 1];
+        let mut bits = vec![0; size];
+        for len in (1..=log as usize).rev() {
+            starts[len - 1] = starts[len] + ranks[len] * (1 << (log as usize - len));
+            bits[starts[len]..starts[len - 1]].fill(len as u8);
+        }
+        let mut symbols = vec![0; size];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len > 0) {
+            let code = starts[len as usize];
+            let span = 1 << (log - len);
+            symbols[code..code + span].fill(symbol as u8);
+            starts[len as usize] += span;
+        }
+        Ok(Huffman { log, symbols, bits })
+    }
+
+    // Reads a table's description, returning the table and how many bytes it took.
+    fn read(data: &[u8]) -> Result<(Huffman, usize)> {
+        let header = *data.first().ok_or(EARLY)? as usize;
+        if header >= 128 {
+            let count = header - 127;
+            let packed = data.get(1..1 + count.div_ceil(2)).ok_or(EARLY)?;
+            let weights = (0..count).map(|i| if i % 2 == 0 { packed[i / 2] >> 4 } else { packed[i / 2] & 15 }).collect();
+            return Ok((Huffman::from_weights(weights)?, 1 + count.div_ceil(2)));
+        }
+        let data = data.get(1..1 + header).ok_or(EARLY)?;
+        let (table, used) = Fse::read(data, 6, 256)?;
+        let mut bits = Backward::new(data.get(used..).ok_or(EARLY)?)?;
+        let (mut one, mut two) = (table.init(&mut bits), table.init(&mut bits));
+        let mut weights = Vec::new();
+        // Two states take turns, until the stream runs out partway through one's update.
+        loop {
+            weights.push(table.symbols[one]);
+            table.update(&mut one, &mut bits);
+            if bits.offset < 0 {
+                weights.push(table.symbols[two]);
+                break;
+            }
+            weights.push(table.symbols[two]);
+            table.update(&mut two, &mut bits);
+            if bits.offset < 0 {
+                weights.push(table.symbols[one]);
+                break;
+            }
+            if weights.len() > 255 {
+                return Err("a Huffman table has too many weights");
+            }
+        }
+        Ok((Huffman::from_weights(weights)?, 1 + header))
+    }
+
+    fn decode(&self, stream: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut bits = Backward::new(stream)?;
+        let mut state = bits.bits(self.log) as usize;
+        let mask = (1 << self.log) - 1;
+        while bits.offset > -(self.log as isize) {
+            out.push(self.symbols[state]);
+            let n = self.bits[state] as u32;
+            state = ((state << n) + bits.bits(n) as usize) & mask;
+        }
+        if bits.offset != -(self.log as isize) {
+            return Err("a literals stream is corrupt");
+        }
+        Ok(())
+    }
+}
+
+// The default distributions for literal lengths, match lengths and offsets.
+const LL_DEFAULT: [i16; 36] =
+    [4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] =
+    [1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1];
+
+// What literal and match length codes past those standing for themselves start from, and
+// how many extra bits follow them.
+const LL_CODES: [(u32, u32); 20] = [
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+const ML_CODES: [(u32, u32); 21] = [
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+// A length from its code, given how many codes stand for themselves less `least`, the
+// shortest length there can be.
+fn length(code: u8, direct: u32, least: u32, codes: &[(u32, u32)], bits: &mut Backward) -> Result<usize> {
+    let code = code as u32;
+    if code < direct {
+        return Ok((code + least) as usize);
+    }
+    let &(base, extra) = codes.get((code - direct) as usize).ok_or("a length code is invalid")?;
+    Ok((base + bits.bits(extra) as u32) as usize)
+}
+
+// What carries over from one block of a frame to the next.
+struct Frame {
+    huffman: Option<Huffman>,
+    tables: [Option<Fse>; 3],
+    offsets: [usize; 3],
+}
+
+fn literals(data: &[u8], frame: &mut Frame) -> Result<(Vec<u8>, usize)> {
+    let byte = |i: usize| data.get(i).map(|&b| b as usize).ok_or(EARLY);
+    let kind = byte(0)? & 3;
+    let format = (byte(0)? >> 2) & 3;
+    if kind < 2 {
+        let (size, header) = match format {
+            0 | 2 => (byte(0)? >> 3, 1),
+            1 => ((byte(0)? >> 4) + (byte(1)? << 4), 2),
+            _ => ((byte(0)? >> 4) + (byte(1)? << 4) + (byte(2)? << 12), 3),
+        };
+        return if kind == 0 {
+            Ok((data.get(header..header + size).ok_or(EARLY)?.to_vec(), header + size))
+        } else {
+            Ok((vec![byte(header)? as u8; size], header + 1))
+        };
+    }
+
+    let (streams, header, width) = match format {
+        0 => (1, 3, 10),
+        1 => (4, 3, 10),
+        2 => (4, 4, 14),
+        _ => (4, 5, 18),
+    };
+    let fields = (0..header).map(byte).collect::<Result<Vec<_>>>()?;
+    let packed = fields.iter().rev().fold(0u64, |n, &b| n << 8 | b as u64) >> 4;
+    let regenerated = (packed & ((1 << width) - 1)) as usize;
+    let compressed = (packed >> width & ((1 << width) - 1)) as usize;
+    let mut body = data.get(header..header + compressed).ok_or(EARLY)?;
+    if kind == 2 {
+        let (table, used) = Huffman::read(body)?;
+        frame.huffman = Some(table);
+        body = &body[used..];
+    }
+    let table = frame.huffman.as_ref().ok_or("literals reuse a Huffman table there isn't")?;
+
+    let mut out = Vec::with_capacity(regenerated);
+    if streams == 1 {
+        table.decode(body, &mut out)?;
+    } else {
+        let jump = body.get(..6).ok_or(EARLY)?;
+        let sizes = [0, 2, 4].map(|i| u16::from_le_bytes([jump[i], jump[i + 1]]) as usize);
+        let mut rest = &body[6..];
+        for size in sizes {
+            let stream = rest.get(..size).ok_or(EARLY)?;
+            table.decode(stream, &mut out)?;
+            rest = &rest[size..];
+        }
+        table.decode(rest, &mut out)?;
+    }
+    if out.len() != regenerated {
+        return Err("the literals come out the wrong size");
+    }
+    Ok((out, header + compressed))
+}
+
+fn block(data: &[u8], frame: &mut Frame, out: &mut Vec<u8>) -> Result<()> {
+    let (literals, used) = literals(data, frame)?;
+    let data = &data[used..];
+    let byte = |i: usize| data.get(i).map(|&b| b as usize).ok_or(EARLY);
+    let (count, mut pos) = match byte(0)? {
+        0 => {
+            out.extend(literals);
+            return Ok(());
+        }
+        n @ 1..=127 => (n, 1),
+        n @ 128..=254 => (((n - 128) << 8) + byte(1)?, 2),
+        _ => (byte(1)? + (byte(2)? << 8) + 0x7f00, 3),
+    };
+    let modes = byte(pos)?;
+    pos += 1;
+
+    // Literal lengths, offsets and match lengths, in the order their modes are given.
+    let kinds: [(&[i16], u32, u32); 3] = [(&LL_DEFAULT, 6, 9), (&OF_DEFAULT, 5, 8), (&ML_DEFAULT, 6, 9)];
+    for (i, &(default, log, max_log)) in kinds.iter().enumerate() {
+        frame.tables[i] = Some(match (modes >> (6 - 2 * i)) & 3 {
+            0 => Fse::new(default, log)?,
+            1 => {
+                pos += 1;
+                Fse::rle(byte(pos - 1)? as u8)
+            }
+            2 => {
+                let (table, used) = Fse::read(data.get(pos..).ok_or(EARLY)?, max_log, default.len().max(32))?;
+                pos += used;
+                table
+            }
+            _ => frame.tables[i].take().ok_or("sequences reuse a table there isn't")?,
+        });
+    }
+    let [Some(ll), Some(of), Some(ml)] = &frame.tables else { unreachable!() };
+
+    let mut bits = Backward::new(data.get(pos..).ok_or(EARLY)?)?;
+    let (mut ll_state, mut of_state, mut ml_state) = (ll.init(&mut bits), of.init(&mut bits), ml.init(&mut bits));
+    let mut literals = literals.into_iter();
+    for i in 0..count {
+        let of_code = of.symbols[of_state] as u32;
+        if of_code > 31 {
+            return Err("an offset code is invalid");
+        }
+        let value = (1usize << of_code) + bits.bits(of_code) as usize;
+        let match_len = length(ml.symbols[ml_state], 32, 3, &ML_CODES, &mut bits)?;
+        let literal_len = length(ll.symbols[ll_state], 16, 0, &LL_CODES, &mut bits)?;
+
+        let history = &mut frame.offsets;
+        let offset = if value > 3 {
+            let offset = value - 3;
+            *history = [offset, history[0], history[1]];
+            offset
+        } else {
+            let i = value - 1 + (literal_len == 0) as usize;
+            if i == 0 {
+                history[0]
+            } else {
+                let offset = if i < 3 { history[i] } else { history[0].checked_sub(1).ok_or("an offset is invalid")? };
+                if i > 1 {
+                    history[2] = history[1];
+                }
+                history[1] = history[0];
+                history[0] = offset;
+                offset
+            }
+        };
+
+        if literals.len() < literal_len {
+            return Err("a sequence wants more literals than there are");
+        }
+        out.extend(literals.by_ref().take(literal_len));
+        let start = out.len().checked_sub(offset).filter(|_| offset > 0).ok_or("an offset reaches too far back")?;
+        for i in start..start + match_len {
+            out.push(out[i]);
+        }
+
+        if i + 1 < count {
+            ll.update(&mut ll_state, &mut bits);
+            ml.update(&mut ml_state, &mut bits);
+            of.update(&mut of_state, &mut bits);
+        }
+    }
+    out.extend(literals);
+    Ok(())
+}
+
+// Decompresses every frame of the data, one after another, skipping skippable ones.
+pub(crate) fn unzstd(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let magic = data.get(pos..pos + 4).ok_or(EARLY)?;
+        if magic[1..] == [0x2a, 0x4d, 0x18] && magic[0] & 0xf0 == 0x50 {
+            let size = data.get(pos + 4..pos + 8).ok_or(EARLY)?;
+            pos += 8 + u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+            continue;
+        }
+        if magic != MAGIC {
+            return Err("a frame doesn't start with the magic number");
+        }
+        let descriptor = *data.get(pos + 4).ok_or(EARLY)?;
+        let single = descriptor & 0x20 != 0;
+        if descriptor & 3 != 0 {
+            return Err("the frame needs a dictionary");
+        }
+        let content_size = match descriptor >> 6 {
+            0 => single as usize,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        pos += 5 + (!single) as usize + content_size;
+
+        let mut frame = Frame { huffman: None, tables: [None, None, None], offsets: [1, 4, 8] };
+        loop {
+            let header = data.get(pos..pos + 3).ok_or(EARLY)?;
+            let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+            let size = (header >> 3) as usize;
+            pos += 3;
+            match (header >> 1) & 3 {
+                0 => out.extend_from_slice(data.get(pos..pos + size).ok_or(EARLY)?),
+                1 => {
+                    out.extend(std::iter::repeat_n(*data.get(pos).ok_or(EARLY)?, size));
+                    pos += 1;
+                }
+                2 => block(data.get(pos..pos + size).ok_or(EARLY)?, &mut frame, &mut out)?,
+                _ => return Err("a block is of an unknown type"),
+            }
+            if header >> 1 & 3 != 1 {
+                pos += size;
+            }
+            if header & 1 == 1 {
+                break;
+            }
+        }
+        if descriptor & 4 != 0 {
+            pos += 4;
+        }
+    }
+    Ok(out)
+}
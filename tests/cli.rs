@@ -0,0 +1,46 @@
+// Runs the command line on small programs written out to a scratch directory.
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+const PROGRAM: &str = "    exit
+label double
+    dup
+    add
+    dup
+    onum
+    ret
+";
+
+// Assembles the program into its own directory, returning where the .ws file is.
+fn assemble(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("albus-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (wsa, ws) = (dir.join("double.wsa"), dir.join("double.ws"));
+    fs::write(&wsa, PROGRAM).unwrap();
+    assert!(albus(&["asm", wsa.to_str().unwrap(), "-o", ws.to_str().unwrap()]).status.success());
+    ws
+}
+
+fn albus(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_albus")).args(args).output().unwrap()
+}
+
+#[test]
+fn optimized_entry() {
+    let ws = assemble("entry");
+    let out = albus(&["run", "-O", "--entry", "double", "--stack", "21", ws.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.stdout, b"42");
+}
+
+#[test]
+fn optimized_units() {
+    let ws = assemble("units");
+    let test = "[[test]]\ncall = \"double\"\nstack = [21]\nexpect_output = \"42\"\nexpect_stack = [42]\n";
+    fs::write(ws.with_extension("toml"), test).unwrap();
+    let out = albus(&["test", "--units", "-O", ws.parent().unwrap().to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+}
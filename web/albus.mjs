@@ -0,0 +1,54 @@
+// Loads albus built with `cargo build --release --lib --target wasm32-unknown-unknown`
+// and wraps its exports, which trade strings through the module's memory.
+//
+//   const albus = await load(fetch("albus.wasm"));    // or the module's bytes
+//   albus.run(source, input)        // {output, stack, heap, ip, steps, halted, error?}
+//   const vm = albus.session(source, input);
+//   vm.step(100); vm.free();
+export async function load(wasm) {
+  const bytes = wasm instanceof ArrayBuffer || ArrayBuffer.isView(wasm);
+  const { instance } = bytes ? await WebAssembly.instantiate(wasm, {}) : await WebAssembly.instantiateStreaming(wasm, {});
+  const albus = instance.exports;
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  // Calls `f` with each string as a pointer and length, freeing them afterwards.
+  const withStrings = (strings, f) => {
+    const args = strings.flatMap((s) => {
+      const bytes = encoder.encode(s);
+      const ptr = albus.albus_alloc(bytes.length);
+      new Uint8Array(albus.memory.buffer, ptr, bytes.length).set(bytes);
+      return [ptr, bytes.length];
+    });
+    try {
+      return f(...args);
+    } finally {
+      for (let i = 0; i < args.length; i += 2) albus.albus_free(args[i], args[i + 1]);
+    }
+  };
+  const result = () => {
+    const bytes = new Uint8Array(albus.memory.buffer, albus.albus_result(), albus.albus_result_len());
+    return JSON.parse(decoder.decode(bytes));
+  };
+
+  return {
+    run(source, input = "") {
+      withStrings([source, input], albus.albus_run);
+      return result();
+    },
+
+    session(source, input = "") {
+      const id = withStrings([source, input], albus.albus_session_new);
+      if (id < 0) throw new Error(result().error);
+      return {
+        step(n = 1) {
+          albus.albus_session_step(id, BigInt(n));
+          return result();
+        },
+        free() {
+          albus.albus_session_free(id);
+        },
+      };
+    },
+  };
+}